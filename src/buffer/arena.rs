@@ -0,0 +1,205 @@
+// One contiguous, page-aligned region holding the bytes of every frame in the pool.
+//
+// Frames used to own their RawPage inline, which put page bytes wherever the allocator put the frame - fine for
+// buffered I/O but useless for O_DIRECT, which wants every buffer aligned to the device block size. The arena
+// maps the whole pool (at its maximum capacity) up front and frame i uses page slot i, so every page buffer is
+// PAGE_SIZE aligned and the pool's page memory is one range we can account for and advise the kernel about.
+//
+// The mapping is anonymous, so reserving the capacity costs address space, not memory - the kernel only backs
+// a slot once it is touched. On Linux we ask for transparent huge pages (MADV_HUGEPAGE) to cut TLB misses on a
+// large pool, and slots retired by a shrink are handed back with MADV_DONTNEED. A retired slot reads as zeroes
+// afterwards rather than becoming unmapped, so a stale frame index can still look at it safely.
+
+use crate::page::{PAGE_SIZE, RawPage};
+use std::ops::Range;
+use std::ptr::NonNull;
+
+// Regions at least this big start on a multiple of it so huge pages can back them from the first byte
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+pub(super) struct FrameArena {
+    base: NonNull<u8>,
+    pages: usize,
+}
+
+// SAFETY: The arena only hands out pointers to page slots, it never reads or writes them itself. Who may touch a
+// slot is up to the frame that owns it.
+unsafe impl Send for FrameArena {}
+unsafe impl Sync for FrameArena {}
+
+impl FrameArena {
+    pub(super) fn new(pages: usize) -> Self {
+        assert!(pages > 0, "empty frame arena");
+        let arena = Self {
+            base: map(pages * PAGE_SIZE),
+            pages,
+        };
+        arena.advise_huge_pages();
+        arena
+    }
+
+    pub(super) fn pages(&self) -> usize {
+        self.pages
+    }
+
+    // The slot for frame `frame_idx` - always PAGE_SIZE aligned
+    pub(super) fn page(&self, frame_idx: usize) -> NonNull<RawPage> {
+        assert!(frame_idx < self.pages, "frame index beyond the arena");
+        // SAFETY: In bounds of the mapping, checked above
+        unsafe { self.base.add(frame_idx * PAGE_SIZE) }.cast()
+    }
+
+    // Gives the memory behind the slots back to the OS. Only for slots no frame is using - whatever was there
+    // is gone and the slots read as zeroes until written again.
+    pub(super) fn release(&self, frames: Range<usize>) {
+        debug_assert!(frames.end <= self.pages);
+        if frames.is_empty() {
+            return;
+        }
+        #[cfg(unix)]
+        // SAFETY: The range is inside our mapping. DONTNEED on a private anonymous mapping leaves it mapped
+        // and zero-filled so nothing is invalidated.
+        unsafe {
+            libc::madvise(
+                self.page(frames.start).as_ptr().cast(),
+                frames.len() * PAGE_SIZE,
+                libc::MADV_DONTNEED,
+            );
+        }
+        #[cfg(not(unix))]
+        for frame_idx in frames {
+            // SAFETY: As above, nobody is using the slot. All we can do without madvise is zero it.
+            unsafe { self.page(frame_idx).write([0u8; PAGE_SIZE]) };
+        }
+    }
+
+    // Only a hint - the kernel may not have THP enabled, and it is no error if it doesn't
+    fn advise_huge_pages(&self) {
+        #[cfg(target_os = "linux")]
+        if self.pages * PAGE_SIZE >= HUGE_PAGE_SIZE {
+            // SAFETY: The whole range is our mapping
+            unsafe {
+                libc::madvise(
+                    self.base.as_ptr().cast(),
+                    self.pages * PAGE_SIZE,
+                    libc::MADV_HUGEPAGE,
+                );
+            }
+        }
+    }
+}
+
+impl Drop for FrameArena {
+    fn drop(&mut self) {
+        unmap(self.base, self.pages * PAGE_SIZE);
+    }
+}
+
+// Maps `len` zeroed bytes. Regions big enough to use huge pages are aligned to HUGE_PAGE_SIZE by over-mapping
+// and trimming the ends, anything smaller only needs the page alignment mmap gives us anyway.
+#[cfg(unix)]
+fn map(len: usize) -> NonNull<u8> {
+    let align = if len >= HUGE_PAGE_SIZE {
+        HUGE_PAGE_SIZE
+    } else {
+        PAGE_SIZE
+    };
+    let reserve = len + align - PAGE_SIZE;
+
+    // SAFETY: A fresh private anonymous mapping, nothing else can refer to it
+    let addr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            reserve,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if addr == libc::MAP_FAILED {
+        panic!(
+            "failed to map {} bytes for the frame arena: {}",
+            reserve,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    let start = addr as usize;
+    let aligned = start.next_multiple_of(align);
+    let head = aligned - start;
+    let tail = reserve - head - len;
+    // SAFETY: Both pieces are inside the mapping we just made and outside the part we keep
+    unsafe {
+        if head > 0 {
+            libc::munmap(addr, head);
+        }
+        if tail > 0 {
+            libc::munmap((aligned + len) as *mut libc::c_void, tail);
+        }
+    }
+    NonNull::new(aligned as *mut u8).unwrap()
+}
+
+#[cfg(unix)]
+fn unmap(base: NonNull<u8>, len: usize) {
+    // SAFETY: Called once, from Drop, with what map returned
+    unsafe {
+        libc::munmap(base.as_ptr().cast(), len);
+    }
+}
+
+#[cfg(not(unix))]
+fn map(len: usize) -> NonNull<u8> {
+    let layout = std::alloc::Layout::from_size_align(len, PAGE_SIZE).unwrap();
+    // SAFETY: len is non-zero, the arena never has zero pages
+    let base = unsafe { std::alloc::alloc_zeroed(layout) };
+    match NonNull::new(base) {
+        Some(base) => base,
+        None => std::alloc::handle_alloc_error(layout),
+    }
+}
+
+#[cfg(not(unix))]
+fn unmap(base: NonNull<u8>, len: usize) {
+    let layout = std::alloc::Layout::from_size_align(len, PAGE_SIZE).unwrap();
+    // SAFETY: Allocated in map with the same layout
+    unsafe { std::alloc::dealloc(base.as_ptr(), layout) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_aligned_zeroed_and_released() {
+        let arena = FrameArena::new(1024);
+        assert_eq!(arena.pages(), 1024);
+        // Big enough to start on a huge page boundary
+        assert_eq!(arena.page(0).as_ptr() as usize % HUGE_PAGE_SIZE, 0);
+
+        for frame_idx in [0, 1, 511, 1023] {
+            let page = arena.page(frame_idx);
+            assert_eq!(page.as_ptr() as usize % PAGE_SIZE, 0);
+            assert_eq!(
+                page.as_ptr() as usize - arena.page(0).as_ptr() as usize,
+                frame_idx * PAGE_SIZE
+            );
+            // SAFETY: Nobody else has the arena
+            let bytes = unsafe { &mut *page.as_ptr() };
+            assert!(bytes.iter().all(|b| *b == 0));
+            bytes[0] = 7;
+            bytes[PAGE_SIZE - 1] = 9;
+        }
+
+        // Released slots are still mapped, but empty
+        arena.release(511..1024);
+        assert_eq!(unsafe { (*arena.page(1).as_ptr())[0] }, 7);
+        assert_eq!(unsafe { (*arena.page(511).as_ptr())[0] }, 0);
+        assert_eq!(unsafe { (*arena.page(1023).as_ptr())[PAGE_SIZE - 1] }, 0);
+
+        // A small pool still gets page-aligned slots
+        let small = FrameArena::new(3);
+        assert_eq!(small.page(2).as_ptr() as usize % PAGE_SIZE, 0);
+    }
+}
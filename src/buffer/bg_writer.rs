@@ -0,0 +1,98 @@
+// Background writer - keeps a supply of clean frames ahead of the eviction hand so foreground threads rarely
+// have to write out a dirty victim themselves before they can reuse its frame.
+//
+// Each round scans `scan_ahead` frames starting at the replacement policy's hand (or where the last round
+// stopped for policies without one) and writes out dirty, unpinned pages, at most `max_pages` per round,
+// then sleeps for `delay`. Writes go through the same write-back path as eviction so the WAL rule holds.
+//
+// Given an AsyncPager the writer owns it and each round's pages go out as one batch, every page latched until
+// its write completes, instead of one write at a time. If the queue itself fails the writer drops it and carries
+// on with plain writes.
+//
+// The thread only holds a Weak reference to the buffer manager between rounds so it never keeps the pool alive
+// on its own - the manager owns the handle and stops the thread on drop.
+
+use crate::buffer::buffer_manager::BufferManager;
+use crate::pager::async_pager::AsyncPager;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub(crate) struct BgWriterConfig {
+    pub(crate) delay: Duration,
+    pub(crate) max_pages: usize,
+    pub(crate) scan_ahead: usize,
+}
+
+impl Default for BgWriterConfig {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(200),
+            max_pages: 100,
+            scan_ahead: 256,
+        }
+    }
+}
+
+pub(crate) struct BgWriterHandle {
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BgWriterHandle {
+    pub(super) fn spawn(
+        bm: Weak<BufferManager>,
+        config: BgWriterConfig,
+        mut io: Option<AsyncPager>,
+    ) -> Self {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = shutdown.clone();
+
+        let thread = std::thread::Builder::new()
+            .name("inkdb-bgwriter".to_string())
+            .spawn(move || {
+                let mut cursor = 0;
+                loop {
+                    {
+                        let (lock, cvar) = &*signal;
+                        let stopped = cvar
+                            .wait_timeout_while(lock.lock().unwrap(), config.delay, |stop| !*stop)
+                            .unwrap()
+                            .0;
+                        if *stopped {
+                            return;
+                        }
+                    }
+
+                    // The pool went away without stopping us first
+                    let Some(bm) = bm.upgrade() else {
+                        return;
+                    };
+                    cursor = bm.bg_writer_round(&config, cursor, &mut io);
+                }
+            })
+            .expect("failed to spawn background writer");
+
+        Self {
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    pub(super) fn stop(mut self) {
+        {
+            let (lock, cvar) = &*self.shutdown;
+            *lock.lock().unwrap() = true;
+            cvar.notify_all();
+        }
+
+        if let Some(thread) = self.thread.take() {
+            // If the writer itself held the last reference to the pool we are being dropped on its own thread,
+            // it exits as soon as the round returns so there is nothing to wait for
+            if thread.thread().id() != std::thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
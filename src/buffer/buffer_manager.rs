@@ -0,0 +1,2548 @@
+// The buffer manager wires the page table, the frame pool and the pager together. Calling code (the tree,
+// transactions) only asks it for pages by PageID and gets back a PinnedPage.
+//
+// Fetch path:
+//   1. get or create the page table entry for the PageID
+//   2. race on the entry latch - the winner claims a frame (free list first, then clock-sweep), reads the page
+//      through the pager and publishes PageTableResult::Memory(frame)
+//   3. pin the frame and check the entry still points at it - an evictor may have taken the frame between
+//      2 and 3, in which case we unpin and go back through the latch
+//
+// Eviction path:
+//   1. the replacement policy (clock-sweep unless configured otherwise) proposes an unpinned frame
+//   2. we pin it ourselves and move the entry from in-memory to evicting, which only goes ahead if ours is the
+//      only pin - the entry then either finishes as on-disk or is put back in memory untouched
+//   3. a dirty victim is written back through the pager while the entry is evicting
+//   4. the frame is cleared, we wait out any optimistic readers that might still be looking at it (see
+//      epoch.rs) and it is handed back to the caller still pinned so nobody else can claim it
+//
+// Write-back (eviction, flush_page and flush_all) follows the WAL rule - a page whose header LSN is beyond the
+// durably flushed log position is never written, because a crash would leave a change on disk that the log
+// cannot undo. Until the log catches up such a page simply stays dirty and is not a candidate for eviction.
+//
+// With swizzling on, descending through fetch_child replaces the child pointer in a resident internal page with
+// the index of the frame holding the child (see swip.rs), so the next traversal goes straight to the frame
+// without touching the page table. A swizzled child cannot be evicted until the pointer in its parent is put
+// back, and a parent with swizzled children cannot be evicted at all, so hot upper levels stay put.
+//
+// Pages can be loaded ahead of use by the prefetcher (see prefetch.rs), either on request or as readahead for
+// scans walking a right-sibling chain with fetch_right_sibling. We count how many of those were used before
+// being evicted so readahead depth can be tuned against what it actually saves.
+//
+// Large scans and bulk loads pass a BufferRing (see strategy.rs) so they recycle a few frames of their own
+// instead of running the whole pool through the replacement policy.
+//
+// Reads of hot pages can skip the pin and the latch entirely with read_optimistic - the frame's version and
+// identity are checked after the page is copied out and the caller retries or falls back to fetch_page if they
+// moved.
+//
+// An optional background writer (see bg_writer.rs) cleans frames ahead of the eviction hand so foreground
+// threads mostly find clean victims. Writes are counted by who did them so we can tell how well it keeps up.
+// Given an AsyncPager it writes each round as one batch.
+//
+// The pool can be resized while running (see frame_pool.rs) - growing hands new frames to the free list and
+// shrinking evicts frames from the top of the pool down, within an optional memory budget.
+//
+// Hits, misses, evictions, write-backs and time spent waiting on page table latches are counted as we go, see
+// stats() and buffer_cache() (stats.rs).
+
+use crate::buffer::bg_writer::{BgWriterConfig, BgWriterHandle};
+use crate::buffer::epoch::EpochManager;
+use crate::buffer::eviction::{EvictionKind, EvictionPolicy};
+use crate::buffer::frame_pool::FramePool;
+use crate::buffer::hybrid_latch::OptimisticRetry;
+use crate::buffer::page_frame::{PageFrame, PinnedPage};
+use crate::buffer::page_table::{
+    PageTable, PageTableEntry, PageTableHandle, PageTableKind, PageTableResult,
+};
+use crate::buffer::page_table_latch::{PT_ON_DISK, WaitStrategy};
+use crate::buffer::pin_tracker::{PinScope, PinTracker};
+use crate::buffer::prefetch::{PrefetchConfig, PrefetchHandle, PrefetchStats};
+use crate::buffer::stats::{BufferCounters, BufferStats, FrameInfo, MemoryUsage};
+use crate::buffer::strategy::{AccessStrategy, BufferRing};
+use crate::buffer::swip::Swip;
+use crate::page::internal_page::{IndexPageError, IndexPageMut, IndexPageRef};
+use crate::page::{
+    PAGE_SIZE, PageID, PageKind, RawPage, SlotID, SlottedPageMut, SlottedPageRef, page_kind_of,
+    page_lsn_of,
+};
+use crate::pager::async_pager::{AsyncPager, IoRequest};
+use crate::pager::pager::{Pager, PagerError};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteSource {
+    Backend,
+    BgWriter,
+}
+
+pub(crate) type Result<T> = std::result::Result<T, BufferError>;
+
+// Budget estimates for what is not counted exactly - a frame's slot in the free list and the policy's state for
+// it, and a page table entry with its slot in the map
+const FRAME_METADATA_BYTES: usize = 64;
+const PAGE_TABLE_ENTRY_BYTES: usize =
+    size_of::<PageTableEntry>() + size_of::<PageTableHandle>() + size_of::<PageID>() + 32;
+
+#[derive(Debug)]
+pub(crate) enum BufferError {
+    NoFreeFrames,
+    Pager(PagerError),
+    Index(IndexPageError),
+    Latch(String),
+    PageExists(PageID),
+    PagePinned(PageID),
+    PoolSize {
+        requested: usize,
+        capacity: usize,
+    },
+    OverBudget {
+        needed: usize,
+        budget: usize,
+    },
+    WalNotFlushed {
+        page_id: PageID,
+        page_lsn: u64,
+        flushed_lsn: u64,
+    },
+    SwizzledPageID(PageID),
+}
+
+impl From<PagerError> for BufferError {
+    fn from(err: PagerError) -> Self {
+        BufferError::Pager(err)
+    }
+}
+
+impl From<IndexPageError> for BufferError {
+    fn from(err: IndexPageError) -> Self {
+        BufferError::Index(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BufferConfig {
+    pub(crate) pool_size: usize,
+    pub(crate) eviction: EvictionKind,
+    pub(crate) page_table: PageTableKind,
+    pub(crate) swizzling: bool,
+    // Bytes the pool may use for frames, page table entries and policy state. The pool can be resized up to
+    // whatever fits, without a budget it can never grow beyond pool_size.
+    pub(crate) memory_budget: Option<usize>,
+    // How fetches wait on a page somebody else is loading or evicting
+    pub(crate) latch_wait: WaitStrategy,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            pool_size: 1024,
+            eviction: EvictionKind::default(),
+            page_table: PageTableKind::default(),
+            swizzling: false,
+            memory_budget: None,
+            latch_wait: WaitStrategy::default(),
+        }
+    }
+}
+
+pub(crate) struct BufferManager {
+    frames: FramePool,
+    // Serialises resizes, which need the pool to hold still while they work out what to retire
+    resize_lock: Mutex<()>,
+    memory_budget: Option<usize>,
+    free_list: Mutex<Vec<usize>>,
+    page_table: Box<dyn PageTable>,
+    pager: Arc<dyn Pager>,
+    policy: Box<dyn EvictionPolicy>,
+    epochs: EpochManager,
+    swizzling: bool,
+    flushed_lsn: AtomicU64,
+    bg_writer: Mutex<Option<BgWriterHandle>>,
+    prefetcher: Mutex<Option<PrefetchHandle>>,
+    counters: BufferCounters,
+    latch_wait: WaitStrategy,
+    pins: PinTracker,
+}
+
+impl BufferManager {
+    pub(crate) fn new(pool_size: usize, pager: Arc<dyn Pager>) -> Self {
+        Self::with_config(
+            BufferConfig {
+                pool_size,
+                ..Default::default()
+            },
+            pager,
+        )
+    }
+
+    pub(crate) fn with_config(config: BufferConfig, pager: Arc<dyn Pager>) -> Self {
+        // Everything sized per frame is built for the most the budget allows, only the frames themselves are
+        // allocated as the pool grows into them
+        let capacity = config
+            .memory_budget
+            .map_or(config.pool_size, Self::frames_within);
+        assert!(capacity > 0, "memory budget too small for a single frame");
+        let pool_size = config.pool_size.min(capacity);
+
+        let policy = config.eviction.build(capacity);
+        policy.resize(pool_size);
+
+        Self {
+            frames: FramePool::new(pool_size, capacity),
+            resize_lock: Mutex::new(()),
+            memory_budget: config.memory_budget,
+            // Reversed so frames are handed out from the front of the pool
+            free_list: Mutex::new((0..pool_size).rev().collect()),
+            page_table: config.page_table.build(),
+            pager,
+            policy,
+            epochs: EpochManager::new(),
+            swizzling: config.swizzling,
+            flushed_lsn: AtomicU64::new(0),
+            bg_writer: Mutex::new(None),
+            prefetcher: Mutex::new(None),
+            counters: BufferCounters::default(),
+            latch_wait: config.latch_wait,
+            pins: PinTracker::new(),
+        }
+    }
+
+    pub(crate) fn pool_size(&self) -> usize {
+        self.frames.active()
+    }
+
+    pub(crate) fn max_pool_size(&self) -> usize {
+        self.frames.capacity()
+    }
+
+    // Roughly how many frames fit in the budget once each frame's share of the free list and policy state is
+    // paid for, along with the page table entry of the page it holds. Entries of pages that are not resident are
+    // reclaimed, so beyond that the table only holds pages somebody is in the middle of loading or evicting -
+    // resize and new entries check the budget with the real numbers.
+    fn frames_within(budget: usize) -> usize {
+        let per_frame =
+            size_of::<PageFrame>() + PAGE_SIZE + FRAME_METADATA_BYTES + PAGE_TABLE_ENTRY_BYTES;
+        budget / per_frame
+    }
+
+    pub(crate) fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            frames: self.frames.memory_usage(),
+            page_table: self.page_table.len() * PAGE_TABLE_ENTRY_BYTES,
+            metadata: self.policy.memory_usage()
+                + self.frames.capacity() * size_of::<usize>()
+                + size_of::<Self>(),
+        }
+    }
+
+    // Every entry the fetch paths look up or create goes through here, so a new one is only added while the
+    // table still fits in the memory budget
+    fn page_table_entry(&self, page_id: PageID) -> Result<PageTableHandle> {
+        if let Some(entry) = self.page_table.get(page_id) {
+            return Ok(entry);
+        }
+        if let Some(budget) = self.memory_budget {
+            let needed = self.memory_usage().total() + PAGE_TABLE_ENTRY_BYTES;
+            if needed > budget {
+                return Err(BufferError::OverBudget { needed, budget });
+            }
+        }
+        Ok(self.page_table.get_or_insert(page_id))
+    }
+
+    // Drops the entry of a page that is back on disk, unless somebody still holds it - then it stays until the
+    // next time the page leaves the pool. The caller must have let go of its own handle.
+    fn reclaim_entry(&self, page_id: PageID) {
+        self.page_table.remove_idle(page_id);
+    }
+
+    // Grows or shrinks the pool to `frames` frames and returns the size it ended up at.
+    //
+    // Growing allocates whatever chunks are missing, as long as the pool stays within the memory budget, and
+    // hands the new frames to the free list. Shrinking retires frames from the top of the pool down - each one
+    // is evicted like any victim, dirty pages are written back first - and stops early at a frame it cannot
+    // evict (pinned, or ahead of the log), so the pool may end up larger than asked for.
+    pub(crate) fn resize(&self, frames: usize) -> Result<usize> {
+        let _resizing = self.resize_lock.lock().unwrap();
+        let current = self.frames.active();
+
+        if frames == 0 || frames > self.frames.capacity() {
+            return Err(BufferError::PoolSize {
+                requested: frames,
+                capacity: self.frames.capacity(),
+            });
+        }
+
+        if frames > current {
+            if let Some(budget) = self.memory_budget {
+                let grown = self.frames.bytes_for(frames);
+                let usage = self.memory_usage();
+                let needed = usage.total() - usage.frames + grown;
+                if needed > budget {
+                    return Err(BufferError::OverBudget { needed, budget });
+                }
+            }
+
+            self.frames.allocate(frames);
+            self.frames.set_active(frames);
+            self.policy.resize(frames);
+            self.free_list
+                .lock()
+                .unwrap()
+                .extend((current..frames).rev());
+            return Ok(frames);
+        }
+
+        // Holding the free list keeps anybody from claiming a frame we are about to retire (or have retired).
+        // Victims are chosen below `active` only, and loads into the frames above it that were already under
+        // way show up as pinned frames which stop the shrink.
+        let mut free = self.free_list.lock().unwrap();
+        self.frames.set_active(frames);
+
+        let mut retained = frames;
+        let mut result = Ok(());
+        for frame_idx in (frames..current).rev() {
+            match self.retire_frame(frame_idx) {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => result = Err(err),
+            }
+            retained = frame_idx + 1;
+            break;
+        }
+
+        self.frames.set_active(retained);
+        self.frames.release(retained..current);
+        self.policy.resize(retained);
+        free.retain(|frame_idx| *frame_idx < retained);
+        result.map(|_| retained)
+    }
+
+    // Empties a frame for good. Frames that are empty and unpinned are already done, anything else has to be
+    // evicted first.
+    fn retire_frame(&self, frame_idx: usize) -> Result<bool> {
+        let frame = self.frames.get(frame_idx);
+        if frame.page_id().is_none() {
+            return Ok(!frame.is_pinned());
+        }
+        if frame.swizzled_children() > 0 {
+            return Ok(false);
+        }
+        match self.try_evict(frame_idx)? {
+            Some(_retired) => Ok(true),
+            None => Ok(false),
+        }
+    }
+
+    // Called by the log once everything up to `lsn` is durable. Only ever moves forward.
+    pub(crate) fn set_flushed_lsn(&self, lsn: u64) {
+        self.flushed_lsn.fetch_max(lsn, Ordering::AcqRel);
+    }
+
+    pub(crate) fn flushed_lsn(&self) -> u64 {
+        self.flushed_lsn.load(Ordering::Acquire)
+    }
+
+    // Starts the background writer, replacing one that is already running
+    pub(crate) fn start_bg_writer(self: &Arc<Self>, config: BgWriterConfig) {
+        self.install_bg_writer(BgWriterHandle::spawn(Arc::downgrade(self), config, None));
+    }
+
+    // The writer submits through `io`, which must write to the same store as our pager
+    pub(crate) fn start_bg_writer_with_io(
+        self: &Arc<Self>,
+        config: BgWriterConfig,
+        io: AsyncPager,
+    ) {
+        self.install_bg_writer(BgWriterHandle::spawn(
+            Arc::downgrade(self),
+            config,
+            Some(io),
+        ));
+    }
+
+    fn install_bg_writer(&self, handle: BgWriterHandle) {
+        if let Some(old) = self.bg_writer.lock().unwrap().replace(handle) {
+            old.stop();
+        }
+    }
+
+    // Stops the background writer and waits for its current round to finish
+    pub(crate) fn stop_bg_writer(&self) {
+        let handle = self.bg_writer.lock().unwrap().take();
+        if let Some(handle) = handle {
+            handle.stop();
+        }
+    }
+
+    pub(crate) fn pages_written_bgwriter(&self) -> u64 {
+        self.counters.pages_written_bgwriter.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn pages_written_backend(&self) -> u64 {
+        self.counters.pages_written_backend.load(Ordering::Relaxed)
+    }
+
+    // Starts the prefetcher, replacing one that is already running. It reads through our pager one page at a time.
+    pub(crate) fn start_prefetcher(self: &Arc<Self>, config: PrefetchConfig) {
+        self.start_prefetcher_with_io(config, self.sync_io());
+    }
+
+    // The prefetcher reads each window through `io`, which must read from the same store as our pager
+    pub(crate) fn start_prefetcher_with_io(
+        self: &Arc<Self>,
+        config: PrefetchConfig,
+        io: AsyncPager,
+    ) {
+        let handle = PrefetchHandle::spawn(Arc::downgrade(self), config, io);
+        if let Some(old) = self.prefetcher.lock().unwrap().replace(handle) {
+            old.stop();
+        }
+    }
+
+    // Stops the prefetcher, dropping whatever is still queued
+    pub(crate) fn stop_prefetcher(&self) {
+        let handle = self.prefetcher.lock().unwrap().take();
+        if let Some(handle) = handle {
+            handle.stop();
+        }
+    }
+
+    // Queues the pages to be loaded in the background. Only a hint - nothing happens without a prefetcher
+    // running, and the pages are not pinned so they can be evicted again before they are used.
+    pub(crate) fn prefetch(&self, pages: &[PageID]) {
+        if let Some(handle) = self.prefetcher.lock().unwrap().as_ref() {
+            for page_id in pages {
+                handle.enqueue(*page_id, 0);
+            }
+        }
+    }
+
+    pub(crate) fn prefetch_stats(&self) -> PrefetchStats {
+        self.counters.prefetch()
+    }
+
+    // Pins taken on this thread until the scope is dropped are checked for leaks when it is - a transaction
+    // holds one for as long as it runs. Does nothing unless pin tracking is compiled in (see pin_tracker.rs).
+    pub(crate) fn pin_scope(&self, name: &str) -> PinScope {
+        self.pins.scope(name)
+    }
+
+    // Pins handed out and not yet dropped, always 0 without pin tracking
+    pub(crate) fn outstanding_pins(&self) -> usize {
+        self.pins.outstanding()
+    }
+
+    pub(crate) fn stats(&self) -> BufferStats {
+        let pinned = self.frames.iter().filter(|frame| frame.is_pinned()).count();
+        self.counters.snapshot(pinned)
+    }
+
+    // Every resident frame, like pg_buffercache. Frames are read one at a time without stopping anybody so the
+    // rows can be out of date by the time they are returned.
+    pub(crate) fn buffer_cache(&self) -> Vec<FrameInfo> {
+        self.frames
+            .iter()
+            .enumerate()
+            .filter_map(|(frame_idx, frame)| {
+                Some(FrameInfo {
+                    frame: frame_idx,
+                    page_id: frame.page_id()?,
+                    kind: frame.kind(),
+                    pin_count: frame.pin_count(),
+                    dirty: frame.is_dirty(),
+                })
+            })
+            .collect()
+    }
+
+    // Writes the page out if it is resident and dirty. A page that is not resident has nothing to flush.
+    pub(crate) fn flush_page(&self, page_id: PageID) -> Result<()> {
+        match self.pin_resident(page_id) {
+            Some(page) if page.is_dirty() => self.write_back(page_id, &page, WriteSource::Backend),
+            _ => Ok(()),
+        }
+    }
+
+    // Writes out every dirty frame and syncs the pager. We keep going past failures so one bad page does not
+    // hold back the rest, and report the first error once we are done.
+    pub(crate) fn flush_all(&self) -> Result<()> {
+        let mut first_error = None;
+
+        for frame in self.frames.iter() {
+            let Some(page_id) = frame.page_id() else {
+                continue;
+            };
+            if !frame.is_dirty() {
+                continue;
+            }
+
+            if let Some(page) = self.pin_resident(page_id)
+                && page.is_dirty()
+                && let Err(err) = self.write_back(page_id, &page, WriteSource::Backend)
+            {
+                first_error.get_or_insert(err);
+            }
+        }
+
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(self.pager.sync()?),
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn fetch_page(&self, page_id: PageID) -> Result<PinnedPage<'_>> {
+        self.fetch(page_id, None)
+    }
+
+    // Moves a scan along to the right sibling of an index page and, with the prefetcher running, queues the
+    // pages after it so they are loaded by the time the scan gets there
+    #[track_caller]
+    pub(crate) fn fetch_right_sibling<'a>(
+        &'a self,
+        page: &PinnedPage<'a>,
+    ) -> Result<Option<PinnedPage<'a>>> {
+        let Some(sibling) = self.right_sibling_of(page) else {
+            return Ok(None);
+        };
+        let next = self.fetch_page(sibling)?;
+
+        if let Some(handle) = self.prefetcher.lock().unwrap().as_ref()
+            && handle.readahead() > 0
+            && let Some(after) = self.right_sibling_of(&next)
+        {
+            handle.enqueue(after, handle.readahead() - 1);
+        }
+        Ok(Some(next))
+    }
+
+    fn right_sibling_of(&self, page: &PinnedPage) -> Option<PageID> {
+        if !matches!(page.kind(), PageKind::IndexInternal | PageKind::IndexLeaf) {
+            return None;
+        }
+        let guard = page.read_guard();
+        IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(&guard)).get_right_sibling()
+    }
+
+    // Synchronous I/O over our own pager, for background threads not given an AsyncPager of their own
+    pub(super) fn sync_io(&self) -> AsyncPager {
+        AsyncPager::new(self.pager.clone())
+    }
+
+    // A window of requests off the prefetch queue, read through `io` all at once. Every page is claimed on its
+    // latch and given a frame first, so a fetch arriving in the meantime waits for our read rather than starting
+    // its own, then the reads go out as one batch and each entry is published as its read comes back. Pages
+    // that are resident, or being loaded or evicted by somebody else, are skipped - requests are only hints.
+    //
+    // Returns the next pages along the chains being read ahead, or the error if `io` itself failed, in which case
+    // pages it never read are handed back on disk.
+    pub(super) fn prefetch_window(
+        &self,
+        io: &mut AsyncPager,
+        window: &[(PageID, usize)],
+    ) -> Result<Vec<(PageID, usize)>> {
+        let mut next = Vec::new();
+        let mut entries = Vec::new();
+        for &(page_id, depth) in window {
+            match self.pin_resident(page_id) {
+                // Loaded ahead by an earlier request and not used yet, so the rest of the chain is there too
+                Some(page) if page.frame().is_prefetched() => {}
+                Some(page) => {
+                    if depth > 0
+                        && let Some(after) = self.right_sibling_of(&page)
+                    {
+                        next.push((after, depth - 1));
+                    }
+                }
+                // Over the memory budget there is no room for the entry, let alone the page
+                None => {
+                    if let Ok(entry) = self.page_table_entry(page_id) {
+                        entries.push((page_id, depth, entry));
+                    }
+                }
+            }
+        }
+
+        let mut loading = Vec::with_capacity(entries.len());
+        for (page_id, depth, entry) in &entries {
+            let Some(claim) = entry.latch().try_claim() else {
+                continue;
+            };
+            match self.claim_frame(None) {
+                Ok((frame_idx, frame)) => loading.push((*page_id, *depth, claim, frame_idx, frame)),
+                // Nothing to evict right now, leave the rest of the window to whoever asks for it
+                Err(_) => {
+                    claim.release();
+                    break;
+                }
+            }
+        }
+
+        // Reading into the frames goes through write guards, which stay held until every read is back
+        let mut guards: Vec<_> = loading
+            .iter()
+            .map(|(_, _, _, _, frame)| frame.write_guard())
+            .collect();
+        let requests: Vec<_> = guards
+            .iter_mut()
+            .zip(&loading)
+            .enumerate()
+            .map(|(idx, (guard, (page_id, ..)))| {
+                IoRequest::read(*page_id, NonNull::from(&mut **guard), idx as u64)
+            })
+            .collect();
+        // Safe as the guards are not released before drain has every completion back
+        let submitted = unsafe { io.submit(&requests) };
+        let mut completions = Vec::with_capacity(requests.len());
+        let drained = io.drain(&mut completions);
+
+        // A page with no completion was never read - the queue failed before it got to it
+        let mut outcomes: Vec<Option<std::result::Result<PageKind, String>>> =
+            (0..loading.len()).map(|_| None).collect();
+        for completion in completions {
+            let idx = completion.tag as usize;
+            outcomes[idx] = Some(match completion.result {
+                Ok(()) => Ok(page_kind_of(&guards[idx])),
+                Err(err) => Err(format!(
+                    "failed to load page {:?}: {:?}",
+                    completion.page_id, err
+                )),
+            });
+        }
+        drop(guards);
+
+        let mut loaded = 0;
+        for ((page_id, depth, claim, frame_idx, frame), outcome) in
+            loading.into_iter().zip(outcomes)
+        {
+            match outcome {
+                Some(Ok(kind)) => {
+                    // Same as load_page - the write guard marked the frame dirty but it matches disk, and the
+                    // prefetched flag goes on before the entry is published
+                    frame.frame().clear_dirty();
+                    frame.frame().set_prefetched();
+                    frame.frame().set_page(page_id, kind);
+                    self.policy.record_load(frame_idx, page_id);
+                    claim.publish(PageTableResult::Memory(frame_idx as u64));
+                    loaded += 1;
+
+                    if depth > 0
+                        && let Some(after) = self.right_sibling_of(&frame)
+                    {
+                        next.push((after, depth - 1));
+                    }
+                }
+                failed => {
+                    drop(frame);
+                    self.release_frame(frame_idx);
+                    match failed {
+                        Some(Err(msg)) => claim.fail(&msg),
+                        _ => claim.release(),
+                    }
+                }
+            }
+        }
+        // Counted once the frames are unpinned again
+        self.counters
+            .prefetch_loaded
+            .fetch_add(loaded, Ordering::Relaxed);
+
+        // Pages we did not get in are left on disk, the entries we added for them go again
+        let pages: Vec<_> = entries.iter().map(|(page_id, ..)| *page_id).collect();
+        drop(entries);
+        for page_id in pages {
+            self.reclaim_entry(page_id);
+        }
+
+        submitted.and(drained)?;
+        Ok(next)
+    }
+
+    // A ring for one scan or bulk operation - pass it to every fetch_page_with the operation makes
+    pub(crate) fn access_strategy(&self, strategy: AccessStrategy) -> BufferRing {
+        BufferRing::new(strategy, self.pool_size())
+    }
+
+    // Same as fetch_page but a miss reuses a frame from the ring where it can. Hits are served from wherever the
+    // page already is.
+    #[track_caller]
+    pub(crate) fn fetch_page_with(
+        &self,
+        page_id: PageID,
+        ring: &BufferRing,
+    ) -> Result<PinnedPage<'_>> {
+        self.fetch(page_id, Some(ring))
+    }
+
+    // Puts a page the caller built (a split, a newly allocated page) straight into the pool without reading it.
+    // It has never been written so it starts out dirty and reaches the pager when it is flushed or evicted.
+    #[track_caller]
+    pub(crate) fn create_page(
+        &self,
+        page_id: PageID,
+        kind: PageKind,
+        bytes: &RawPage,
+    ) -> Result<PinnedPage<'_>> {
+        let entry = self.page_table_entry(page_id)?;
+        let latch = entry.latch();
+
+        // Going through the latch load means a concurrent fetch of the same page waits for us rather than
+        // reading whatever is on disk - and if the page is already resident the closure never runs
+        let mut create_error = None;
+        let mut created = false;
+        let result = latch.load_tracked(self.latch_wait, &self.counters.latch, |_| {
+            match self.install_page(page_id, kind, bytes) {
+                Ok(frame) => {
+                    created = true;
+                    Ok(PageTableResult::Memory(frame as u64))
+                }
+                Err(err) => {
+                    let msg = format!("failed to create page {:?}: {:?}", page_id, err);
+                    create_error = Some(err);
+                    Err(msg)
+                }
+            }
+        });
+
+        let frame_idx = match result {
+            Ok(PageTableResult::Memory(frame)) if created => frame as usize,
+            Ok(_) => return Err(BufferError::PageExists(page_id)),
+            Err(msg) => {
+                drop(entry);
+                self.reclaim_entry(page_id);
+                return Err(create_error.unwrap_or(BufferError::Latch(msg)));
+            }
+        };
+
+        let frame = self.frames.get(frame_idx);
+        let pinned = frame.pin();
+        if latch.is_in_memory() && frame.page_id() == Some(page_id) {
+            return Ok(self.hand_out(pinned, frame_idx));
+        }
+
+        // Evicted before we pinned it, which wrote it out first - read it back like anyone else would
+        drop(pinned);
+        self.fetch_page(page_id)
+    }
+
+    // Drops a page from the pool without writing it back, for pages that are being freed. Fails with
+    // PagePinned while anybody else holds the page or it is in the middle of being loaded or evicted.
+    pub(crate) fn discard_page(&self, page_id: PageID) -> Result<()> {
+        let Some(entry) = self.page_table.get(page_id) else {
+            return Ok(());
+        };
+        let latch = entry.latch();
+
+        let Some(page) = self.pin_resident(page_id) else {
+            return match latch.state() {
+                PT_ON_DISK => {
+                    drop(entry);
+                    self.reclaim_entry(page_id);
+                    Ok(())
+                }
+                _ => Err(BufferError::PagePinned(page_id)),
+            };
+        };
+        let frame = page.frame();
+        let frame_idx = self.frame_index(frame);
+
+        if !self.unswizzle(frame_idx, page_id)
+            || !latch.begin_evict(|| {
+                frame.pin_count() != 1
+                    || frame.page_id() != Some(page_id)
+                    || frame.parent().is_some()
+                    || frame.swizzled_children() > 0
+            })
+        {
+            return Err(BufferError::PagePinned(page_id));
+        }
+
+        // Same order as try_evict, minus the write-back - whatever was in the page is being thrown away
+        frame.clear_page();
+        latch.finish_evict(PageTableResult::Disk(page_id.to_offset()));
+        self.epochs.synchronize();
+
+        drop(page);
+        self.policy.remove(frame_idx);
+        self.free_list.lock().unwrap().push(frame_idx);
+        drop(entry);
+        self.reclaim_entry(page_id);
+        Ok(())
+    }
+
+    #[track_caller]
+    fn fetch(&self, page_id: PageID, ring: Option<&BufferRing>) -> Result<PinnedPage<'_>> {
+        // Swizzled pointers must be followed with fetch_child
+        if Swip::is_swizzled(page_id) {
+            return Err(BufferError::SwizzledPageID(page_id));
+        }
+        let entry = self.page_table_entry(page_id)?;
+        let latch = entry.latch();
+        // Counted as a miss if we read the page in, even if it was evicted again before we pinned it
+        let mut missed = false;
+
+        loop {
+            // The latch only speaks String errors so we keep hold of the typed one ourselves. Threads that were
+            // waiting on somebody else's failed load only get the message.
+            let mut load_error = None;
+            let mut loaded = false;
+            let wait = self.latch_wait;
+            let result = latch.load_tracked(wait, &self.counters.latch, |_| {
+                match self.load_page(page_id, ring) {
+                    Ok(frame) => {
+                        loaded = true;
+                        Ok(PageTableResult::Memory(frame as u64))
+                    }
+                    Err(err) => {
+                        let msg = format!("failed to load page {:?}: {:?}", page_id, err);
+                        load_error = Some(err);
+                        Err(msg)
+                    }
+                }
+            });
+
+            let frame_idx = match result {
+                Ok(PageTableResult::Memory(frame)) => frame as usize,
+                // load() only returns once the entry is in memory
+                Ok(PageTableResult::Disk(_)) => continue,
+                Err(msg) => {
+                    // Nothing of the page made it in, so its entry goes like an evicted one's
+                    drop(entry);
+                    self.reclaim_entry(page_id);
+                    return Err(load_error.unwrap_or(BufferError::Latch(msg)));
+                }
+            };
+
+            let frame = self.frames.get(frame_idx);
+            let pinned = frame.pin();
+
+            if latch.is_in_memory() && frame.page_id() == Some(page_id) {
+                // The load itself was already reported to the policy. The first real fetch of a prefetched page
+                // is its first reference as far as the policy is concerned, so it isn't reported either.
+                if !loaded {
+                    if frame.take_prefetched() {
+                        self.counters.prefetch_used.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        self.policy.record_access(frame_idx, page_id);
+                    }
+                }
+                let counter = if missed || loaded {
+                    &self.counters.misses
+                } else {
+                    &self.counters.hits
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                return Ok(self.hand_out(pinned, frame_idx));
+            }
+
+            missed |= loaded;
+
+            // Lost the race with an evictor - drop the pin and go back through the latch which will either wait
+            // for the eviction to finish or load the page again
+        }
+    }
+
+    // Reads a resident page without pinning it. Pages which are not resident, being loaded or evicted, or
+    // which a writer touched while we were reading all come back as OptimisticRetry - this never goes to the
+    // pager, callers fall back to fetch_page for that. Child pointers seen here may be swizzled.
+    //
+    // `f` runs on a copy of the page after we have left the epoch, so it is free to fetch or allocate pages.
+    // Anything that can evict waits in epochs.synchronize for every reader inside one, ourselves included.
+    pub(crate) fn read_optimistic<R>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&RawPage) -> R,
+    ) -> std::result::Result<R, OptimisticRetry> {
+        let page = {
+            // Keeps the frame we peek from being handed to another page until we have copied it out
+            let _epoch = self.epochs.enter();
+            let entry = self.page_table.get(page_id).ok_or(OptimisticRetry)?;
+            let PageTableResult::Memory(frame_idx) = entry.latch().peek().1 else {
+                return Err(OptimisticRetry);
+            };
+            self.frames
+                .get(frame_idx as usize)
+                .copy_optimistic(page_id)?
+        };
+        Ok(f(&page))
+    }
+
+    // One step down the tree - pins the child of a resident internal page that covers `key`, or the right
+    // sibling if the key is beyond the page's high key. Swizzled pointers are followed straight to their frame
+    // and, with swizzling on, a child reached through the page table is swizzled into the parent on the way.
+    #[track_caller]
+    pub(crate) fn fetch_child<'a>(
+        &'a self,
+        parent: &PinnedPage<'a>,
+        key: &[u8],
+    ) -> Result<Option<PinnedPage<'a>>> {
+        if parent.kind() != PageKind::IndexInternal {
+            return Err(BufferError::Index(IndexPageError::InvalidPageType));
+        }
+
+        let step = {
+            let guard = parent.read_guard();
+            let index = IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(&guard));
+            match index.find_child_slot(key)? {
+                None => ChildStep::Follow(index.find_child_ptr(key)?),
+                Some(slot_id) => {
+                    let child_ptr = index.child_ptr_at(SlotID(slot_id.0))?;
+                    match Swip::decode(child_ptr) {
+                        // The pointer can only be unswizzled under the parent's exclusive latch and the child can't
+                        // be evicted while it is swizzled, so holding the shared latch makes the pin safe without
+                        // asking the page table
+                        Swip::Frame(child_idx) => {
+                            ChildStep::Swizzled(child_idx, self.frames.get(child_idx).pin())
+                        }
+                        Swip::Page(child_id) => ChildStep::Fetch(slot_id, child_id),
+                    }
+                }
+            }
+        };
+
+        match step {
+            ChildStep::Swizzled(child_idx, pinned) => {
+                if let Some(page_id) = pinned.page_id() {
+                    self.policy.record_access(child_idx, page_id);
+                }
+                Ok(Some(self.hand_out(pinned, child_idx)))
+            }
+            ChildStep::Fetch(slot_id, child_id) => {
+                let child = self.fetch_page(child_id)?;
+                if self.swizzling {
+                    self.swizzle(parent, slot_id, child_id, &child);
+                }
+                Ok(Some(child))
+            }
+            ChildStep::Follow(next) => next.map(|page_id| self.fetch_page(page_id)).transpose(),
+        }
+    }
+
+    // Best effort - if the parent latch is busy or the page changed while we were fetching the child we leave the
+    // pointer alone and the next traversal tries again
+    fn swizzle(&self, parent: &PinnedPage, slot_id: SlotID, child_id: PageID, child: &PinnedPage) {
+        let parent_idx = self.frame_index(parent.frame());
+        let child_idx = self.frame_index(child.frame());
+        let Some(mut guard) = parent.frame().try_swizzle_guard() else {
+            return;
+        };
+
+        let current = IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(&guard))
+            .child_ptr_at(SlotID(slot_id.0))
+            .ok();
+        if current != Some(child_id) || child.frame().parent().is_some() {
+            return;
+        }
+
+        let Ok(page) = guard.slotted_mut() else {
+            return;
+        };
+        if IndexPageMut::from_slotted_page(page)
+            .set_child_ptr(slot_id, Swip::Frame(child_idx).encode())
+            .is_ok()
+        {
+            child.frame().set_parent(Some(parent_idx));
+            parent.frame().add_swizzled_child();
+        }
+    }
+
+    // Puts the page id back in place of our swizzled pointer in the parent. Returns false if the parent latch is
+    // busy, in which case the frame can't be evicted right now.
+    fn unswizzle(&self, frame_idx: usize, page_id: PageID) -> bool {
+        let frame = self.frames.get(frame_idx);
+        let Some(parent_idx) = frame.parent() else {
+            return true;
+        };
+        let parent = self.frames.get(parent_idx);
+        let Some(mut guard) = parent.try_swizzle_guard() else {
+            return false;
+        };
+        // The parent link only changes under the parent's latch which we now hold
+        if frame.parent() != Some(parent_idx) {
+            return false;
+        }
+
+        let swizzled = Swip::Frame(frame_idx).encode();
+        let slots =
+            IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(&guard)).child_slots();
+        if let Ok(page) = guard.slotted_mut() {
+            let mut index = IndexPageMut::from_slotted_page(page);
+            for (slot_id, child_ptr) in slots {
+                if child_ptr == swizzled {
+                    let _ = index.set_child_ptr(slot_id, page_id);
+                }
+            }
+        }
+
+        frame.set_parent(None);
+        parent.remove_swizzled_child();
+        true
+    }
+
+    // Turns every swizzled pointer in a copy of a page back into a page id. The children can't change while the
+    // caller holds the shared latch on the original since unswizzling needs it exclusively.
+    pub(super) fn unswizzled_copy(&self, bytes: &RawPage) -> RawPage {
+        let mut copy = *bytes;
+        let slots =
+            IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(&copy)).child_slots();
+        let mut index = IndexPageMut::from_slotted_page(SlottedPageMut::from_bytes(&mut copy));
+        for (slot_id, child_ptr) in slots {
+            if let Swip::Frame(child_idx) = Swip::decode(child_ptr)
+                && let Some(child_id) = self.frames.get(child_idx).page_id()
+            {
+                let _ = index.set_child_ptr(slot_id, child_id);
+            }
+        }
+        copy
+    }
+
+    fn frame_index(&self, frame: &PageFrame) -> usize {
+        self.frames.index_of(frame)
+    }
+
+    // Runs inside the latch load so we are the only thread loading this page
+    fn load_page(&self, page_id: PageID, ring: Option<&BufferRing>) -> Result<usize> {
+        let (frame_idx, claimed) = self.claim_frame(ring)?;
+
+        let loaded = {
+            let mut page = claimed.write_guard();
+            self.pager
+                .read_page(page_id, &mut page)
+                .map(|_| page_kind_of(&page))
+        };
+
+        match loaded {
+            Ok(kind) => {
+                // Reading into the frame went through a write guard which marked it dirty, but it matches disk
+                claimed.frame().clear_dirty();
+                claimed.frame().set_page(page_id, kind);
+                self.policy.record_load(frame_idx, page_id);
+                if let Some(ring) = ring {
+                    ring.install(frame_idx, page_id);
+                }
+                Ok(frame_idx)
+            }
+            Err(err) => {
+                drop(claimed);
+                self.release_frame(frame_idx);
+                Err(err.into())
+            }
+        }
+    }
+
+    // Runs inside the latch load, like load_page but the bytes come from the caller instead of the pager
+    fn install_page(&self, page_id: PageID, kind: PageKind, bytes: &RawPage) -> Result<usize> {
+        let (frame_idx, claimed) = self.claim_frame(None)?;
+        // Through the write guard so the frame is marked dirty
+        claimed.write(|page| page.copy_from_slice(bytes));
+        claimed.frame().set_page(page_id, kind);
+        self.policy.record_load(frame_idx, page_id);
+        Ok(frame_idx)
+    }
+
+    // Hands back an empty frame which the caller holds pinned
+    fn claim_frame(&self, ring: Option<&BufferRing>) -> Result<(usize, PinnedPage<'_>)> {
+        // Recycle the ring's next frame if it still holds the page we put there and nobody else is using it.
+        // try_evict refuses pinned frames and pages that can't be written yet, and then we fall through to the
+        // normal path - the frame we get there takes the slot instead.
+        if let Some(ring) = ring
+            && let Some((frame_idx, page_id)) = ring.advance()
+            && self.frames.get(frame_idx).page_id() == Some(page_id)
+            && let Some(claimed) = self.try_evict(frame_idx)?
+        {
+            return Ok((frame_idx, claimed));
+        }
+
+        if let Some(frame_idx) = self.free_list.lock().unwrap().pop() {
+            return Ok((frame_idx, self.frames.get(frame_idx).pin()));
+        }
+
+        // Proposals can keep losing to threads pinning or loading the same frames, so don't chase them forever
+        for _ in 0..self.pool_size() * 4 {
+            let victim = self
+                .policy
+                .victim(&|frame_idx| {
+                    let frame = self.frames.get(frame_idx);
+                    frame_idx < self.frames.active()
+                        && !frame.is_pinned()
+                        && frame.page_id().is_some()
+                        && frame.swizzled_children() == 0
+                })
+                .ok_or(BufferError::NoFreeFrames)?;
+
+            if let Some(claimed) = self.try_evict(victim)? {
+                return Ok((victim, claimed));
+            }
+
+            // Policies keep proposing the same frame until its state changes, so give whoever holds it (a
+            // loader finishing up, a reader validating its pin) the chance to let go before asking again
+            std::thread::yield_now();
+        }
+
+        Err(BufferError::NoFreeFrames)
+    }
+
+    fn try_evict(&self, frame_idx: usize) -> Result<Option<PinnedPage<'_>>> {
+        let frame = self.frames.get(frame_idx);
+        let claimed = frame.pin();
+
+        let Some(page_id) = frame.page_id() else {
+            return Ok(None);
+        };
+        let Some(entry) = self.page_table.get(page_id) else {
+            return Ok(None);
+        };
+
+        // A swizzled child is only reachable through the pointer in its parent, which has to go first
+        if !self.unswizzle(frame_idx, page_id) {
+            return Ok(None);
+        }
+
+        // Ours must be the only pin, the frame must still hold the page we looked up and nobody may have swizzled
+        // it (or one of its children) again in the meantime
+        let latch = entry.latch();
+        if !latch.begin_evict(|| {
+            frame.pin_count() != 1
+                || frame.page_id() != Some(page_id)
+                || frame.parent().is_some()
+                || frame.swizzled_children() > 0
+        }) {
+            return Ok(None);
+        }
+
+        if claimed.is_dirty()
+            && let Err(err) = self.write_back(page_id, &claimed, WriteSource::Backend)
+        {
+            latch.abort_evict();
+            return match err {
+                // Not a failure, the page just can't leave until the log catches up
+                BufferError::WalNotFlushed { .. } => Ok(None),
+                err => Err(err),
+            };
+        }
+
+        // Cleared before the entry goes back to on-disk - once it does the page can be loaded into another frame,
+        // and a reader still holding our frame index must not find the page id here and validate against it
+        if frame.take_prefetched() {
+            self.counters
+                .prefetch_wasted
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        frame.clear_page();
+        latch.finish_evict(PageTableResult::Disk(page_id.to_offset()));
+        self.policy.record_evict(frame_idx, page_id);
+        self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        drop(entry);
+        self.reclaim_entry(page_id);
+
+        // Optimistic readers may have peeked the old mapping just before we moved it - wait for them to leave
+        // before the caller starts writing a new page into the frame
+        self.epochs.synchronize();
+        Ok(Some(claimed))
+    }
+
+    // Pins the frame holding the page without loading it if it is not resident
+    fn pin_resident(&self, page_id: PageID) -> Option<PinnedPage<'_>> {
+        let entry = self.page_table.get(page_id)?;
+        let (_, data) = entry.latch().peek();
+        let PageTableResult::Memory(frame_idx) = data else {
+            return None;
+        };
+
+        let frame = self.frames.get(frame_idx as usize);
+        let pinned = frame.pin();
+        if entry.latch().is_in_memory() && frame.page_id() == Some(page_id) {
+            Some(pinned)
+        } else {
+            None
+        }
+    }
+
+    // Holding the shared latch keeps writers out while we write, so the dirty flag we clear afterwards cannot
+    // belong to a change that missed the write
+    fn write_back(&self, page_id: PageID, page: &PinnedPage, source: WriteSource) -> Result<()> {
+        let guard = page.read_guard();
+
+        let page_lsn = page_lsn_of(&guard);
+        let flushed_lsn = self.flushed_lsn();
+        if page_lsn > flushed_lsn {
+            return Err(BufferError::WalNotFlushed {
+                page_id,
+                page_lsn,
+                flushed_lsn,
+            });
+        }
+
+        // Frame indexes mean nothing on disk
+        if page.frame().swizzled_children() > 0 {
+            self.pager
+                .write_page(page_id, &self.unswizzled_copy(&guard))?;
+        } else {
+            self.pager.write_page(page_id, &guard)?;
+        }
+        page.frame().clear_dirty();
+
+        match source {
+            WriteSource::Backend => &self.counters.pages_written_backend,
+            WriteSource::BgWriter => &self.counters.pages_written_bgwriter,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // One round of the background writer. Returns where the scan stopped so the next round can carry on from
+    // there when the policy has no hand to follow. If `io` itself fails it is dropped - the batch's pages are
+    // released still dirty and later rounds write them one at a time.
+    pub(super) fn bg_writer_round(
+        &self,
+        config: &BgWriterConfig,
+        cursor: usize,
+        io: &mut Option<AsyncPager>,
+    ) -> usize {
+        let frames = self.frames.active();
+        let start = self.policy.hand().unwrap_or(cursor) % frames;
+        let scan = config.scan_ahead.min(frames);
+        let mut written = 0;
+        let mut scanned = 0;
+        let mut batch = Vec::new();
+
+        while scanned < scan && written < config.max_pages {
+            let frame = self.frames.get((start + scanned) % frames);
+            scanned += 1;
+
+            if frame.is_pinned() || !frame.is_dirty() {
+                continue;
+            }
+            let Some(page_id) = frame.page_id() else {
+                continue;
+            };
+
+            // Ours must be the only pin - a page somebody is using is not about to be evicted. Failures are
+            // left for the backend that eventually evicts the page to report.
+            let Some(page) = self.pin_resident(page_id) else {
+                continue;
+            };
+            if frame.pin_count() != 1 || !page.is_dirty() {
+                continue;
+            }
+            if io.is_some() {
+                // Same as write_back refusing it - a page ahead of the log is not written and does not count.
+                // write_back_batch checks again under the latch it writes with.
+                if page_lsn_of(&page.read_guard()) > self.flushed_lsn() {
+                    continue;
+                }
+                batch.push((page_id, page));
+                written += 1;
+            } else if self
+                .write_back(page_id, &page, WriteSource::BgWriter)
+                .is_ok()
+            {
+                written += 1;
+            }
+        }
+
+        if let Some(queue) = io
+            && !batch.is_empty()
+            && self.write_back_batch(queue, &batch).is_err()
+        {
+            *io = None;
+        }
+        (start + scanned) % frames
+    }
+
+    // write_back for a batch of pages at once. Every page stays read latched until its write has completed, so
+    // like write_back the dirty flag we clear cannot belong to a change the write missed. Returns how many made it,
+    // or the error if the queue itself failed.
+    fn write_back_batch(
+        &self,
+        io: &mut AsyncPager,
+        pages: &[(PageID, PinnedPage)],
+    ) -> Result<usize> {
+        let guards: Vec<_> = pages.iter().map(|(_, page)| page.read_guard()).collect();
+        let flushed_lsn = self.flushed_lsn();
+        // Unswizzled copies live here until their writes complete
+        let mut copies = Vec::new();
+        let mut requests = Vec::with_capacity(pages.len());
+
+        for (idx, ((page_id, page), guard)) in pages.iter().zip(&guards).enumerate() {
+            if page_lsn_of(guard) > flushed_lsn {
+                continue;
+            }
+            let buf = if page.frame().swizzled_children() > 0 {
+                let copy = Box::new(self.unswizzled_copy(guard));
+                let buf = NonNull::from(&*copy);
+                copies.push(copy);
+                buf
+            } else {
+                NonNull::from(&**guard)
+            };
+            requests.push(IoRequest::write(*page_id, buf, idx as u64));
+        }
+
+        // Safe as neither the guards nor the copies are released before drain has every completion back - even
+        // when submit fails part way, the requests it queued before failing are still the kernel's
+        let submitted = unsafe { io.submit(&requests) };
+        let mut completions = Vec::with_capacity(requests.len());
+        let drained = io.drain(&mut completions);
+
+        let mut written = 0;
+        for completion in completions {
+            if completion.result.is_ok() {
+                pages[completion.tag as usize].1.frame().clear_dirty();
+                written += 1;
+            }
+        }
+        self.counters
+            .pages_written_bgwriter
+            .fetch_add(written as u64, Ordering::Relaxed);
+        submitted.and(drained)?;
+        Ok(written)
+    }
+
+    // Every pin that leaves the buffer manager goes through here
+    #[track_caller]
+    fn hand_out<'a>(&'a self, pinned: PinnedPage<'a>, frame_idx: usize) -> PinnedPage<'a> {
+        pinned
+            .with_policy(self.policy.as_ref(), frame_idx)
+            .with_frames(&self.frames)
+            .tracked(&self.pins, frame_idx)
+    }
+
+    fn release_frame(&self, frame_idx: usize) {
+        self.frames.get(frame_idx).clear_page();
+        self.policy.remove(frame_idx);
+        self.free_list.lock().unwrap().push(frame_idx);
+    }
+}
+
+enum ChildStep<'a> {
+    Swizzled(usize, PinnedPage<'a>),
+    Fetch(SlotID, PageID),
+    Follow(Option<PageID>),
+}
+
+impl Drop for BufferManager {
+    fn drop(&mut self) {
+        self.stop_prefetcher();
+        self.stop_bg_writer();
+        self.pins.check_leaks("buffer manager");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::page_cache::{PageCache, PageCacheError};
+    use crate::buffer::page_table_latch::{PT_IN_MEMORY, PT_ON_DISK};
+    use crate::page::internal_page::IndexCellOwned;
+    use crate::pager::file_pager::FilePager;
+    use crate::pager::pager;
+    use std::collections::HashMap;
+    use std::sync::Barrier;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    // Pages that were never written come back stamped with their own id so tests can spot a frame holding the
+    // wrong page
+    struct MemPager {
+        pages: Mutex<HashMap<PageID, RawPage>>,
+        writes: AtomicUsize,
+        fail_reads: AtomicBool,
+        fail_writes: AtomicBool,
+    }
+
+    impl MemPager {
+        fn new() -> Self {
+            Self {
+                pages: Mutex::new(HashMap::new()),
+                writes: AtomicUsize::new(0),
+                fail_reads: AtomicBool::new(false),
+                fail_writes: AtomicBool::new(false),
+            }
+        }
+
+        fn writes(&self) -> usize {
+            self.writes.load(Ordering::SeqCst)
+        }
+
+        fn stored(&self, page_id: PageID) -> Option<RawPage> {
+            self.pages.lock().unwrap().get(&page_id).cloned()
+        }
+
+        fn put(&self, page_id: PageID, page: RawPage) {
+            self.pages.lock().unwrap().insert(page_id, page);
+        }
+    }
+
+    impl Pager for MemPager {
+        fn read_page(&self, page_id: PageID, buf: &mut RawPage) -> pager::Result<()> {
+            if self.fail_reads.load(Ordering::SeqCst) {
+                return Err(PagerError::ReadFailed(page_id));
+            }
+            match self.pages.lock().unwrap().get(&page_id) {
+                Some(page) => buf.copy_from_slice(page),
+                None => {
+                    buf.fill(0);
+                    buf[100..108].copy_from_slice(&page_id.into().to_le_bytes());
+                }
+            }
+            Ok(())
+        }
+
+        fn write_page(&self, page_id: PageID, buf: &RawPage) -> pager::Result<()> {
+            if self.fail_writes.load(Ordering::SeqCst) {
+                return Err(PagerError::WriteFailed(page_id));
+            }
+            self.pages.lock().unwrap().insert(page_id, *buf);
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn allocate_page(&self) -> pager::Result<PageID> {
+            let mut pages = self.pages.lock().unwrap();
+            let page_id = PageID(pages.keys().map(|id| id.0 + 1).max().unwrap_or(0));
+            pages.insert(page_id, [0u8; 4096]);
+            Ok(page_id)
+        }
+
+        fn page_count(&self) -> u64 {
+            let pages = self.pages.lock().unwrap();
+            pages.keys().map(|id| id.0 + 1).max().unwrap_or(0)
+        }
+
+        fn sync(&self) -> pager::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn stamp(page: &PinnedPage) -> u64 {
+        let guard = page.read_guard();
+        u64::from_le_bytes(guard[100..108].try_into().unwrap())
+    }
+
+    // The LSN is the first 8 bytes of the header
+    fn set_lsn(page: &PinnedPage, lsn: u64) {
+        page.write(|bytes| bytes[0..8].copy_from_slice(&lsn.to_le_bytes()));
+    }
+
+    // An internal page where each child covers the keys below its own
+    fn internal_page(children: &[(&[u8], u64)]) -> RawPage {
+        let mut raw = [0u8; 4096];
+        let mut index = IndexPageMut::from_slotted_page(SlottedPageMut::init_new(
+            &mut raw,
+            PageKind::IndexInternal.into(),
+        ));
+        index.init_in_place(0).unwrap();
+        for (key, child) in children {
+            index
+                .add_cell_append_slot_entry(IndexCellOwned::new(key, PageID(*child)))
+                .unwrap();
+        }
+        raw
+    }
+
+    // A leaf in a right-sibling chain, stamped like the pages the pager makes up
+    fn leaf_page(page_id: u64, right_sibling: Option<u64>) -> RawPage {
+        let mut raw = internal_page(&[]);
+        let mut index = IndexPageMut::from_slotted_page(SlottedPageMut::from_bytes(&mut raw));
+        index.set_page_type(PageKind::IndexLeaf);
+        if let Some(sibling) = right_sibling {
+            index.set_right_sibling(PageID(sibling));
+        }
+        raw[100..108].copy_from_slice(&page_id.to_le_bytes());
+        raw
+    }
+
+    fn wait_for(what: &str, done: impl Fn() -> bool) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !done() {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "timed out waiting for {}",
+                what
+            );
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    fn child_ptr(bytes: &RawPage, slot: u16) -> PageID {
+        IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(bytes))
+            .child_ptr_at(SlotID(slot))
+            .unwrap()
+    }
+
+    fn swizzling_pool(pool_size: usize, pager: Arc<MemPager>) -> BufferManager {
+        let config = BufferConfig {
+            pool_size,
+            swizzling: true,
+            ..Default::default()
+        };
+        BufferManager::with_config(config, pager)
+    }
+
+    // Pages that left the pool have their entry reclaimed, which is as good as on disk
+    fn state(bm: &BufferManager, page_id: PageID) -> u8 {
+        bm.page_table
+            .get(page_id)
+            .map_or(PT_ON_DISK, |entry| entry.latch().state())
+    }
+
+    #[test]
+    fn clock_sweep_evicts_when_pool_is_full() {
+        let bm = BufferManager::new(3, Arc::new(MemPager::new()));
+
+        for id in 1..=5 {
+            let page = bm.fetch_page(PageID(id)).unwrap();
+            assert_eq!(stamp(&page), id);
+        }
+
+        let resident = (1..=5)
+            .filter(|id| state(&bm, PageID(*id)) == PT_IN_MEMORY)
+            .count();
+        assert_eq!(resident, 3);
+
+        // The two oldest pages were the victims and their latches flipped back to on-disk
+        assert_eq!(state(&bm, PageID(1)), PT_ON_DISK);
+        assert_eq!(state(&bm, PageID(2)), PT_ON_DISK);
+
+        // And they come back from the pager when asked for again
+        let page = bm.fetch_page(PageID(1)).unwrap();
+        assert_eq!(stamp(&page), 1);
+    }
+
+    #[test]
+    fn stats_count_hits_misses_and_evictions() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(3, pager.clone());
+
+        for id in 1..=3 {
+            bm.fetch_page(PageID(id)).unwrap();
+        }
+        let pinned = bm.fetch_page(PageID(1)).unwrap();
+        pinned.write(|_| {});
+        bm.fetch_page(PageID(2)).unwrap();
+
+        let stats = bm.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 3, 0));
+        assert_eq!(stats.pinned_frames, 1);
+
+        // Two more pages push out 3 and 2, page 1 is pinned
+        bm.fetch_page(PageID(4)).unwrap();
+        bm.fetch_page(PageID(5)).unwrap();
+        drop(pinned);
+        bm.flush_all().unwrap();
+
+        let stats = bm.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 5, 2));
+        assert_eq!(stats.pinned_frames, 0);
+        assert_eq!(stats.dirty_writes(), 1);
+        assert_eq!(stats.hit_ratio(), 2.0 / 7.0);
+    }
+
+    #[test]
+    fn buffer_cache_lists_resident_frames() {
+        let pager = Arc::new(MemPager::new());
+        pager.put(PageID(7), internal_page(&[]));
+        let bm = BufferManager::new(4, pager);
+
+        let leaf = bm.fetch_page(PageID(3)).unwrap();
+        leaf.write(|_| {});
+        let internal = bm.fetch_page(PageID(7)).unwrap();
+        let _again = bm.fetch_page(PageID(7)).unwrap();
+        drop(leaf);
+
+        let mut rows = bm.buffer_cache();
+        rows.sort_by_key(|row| row.page_id.into());
+
+        // Frames are handed out from the front of the pool
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].frame, rows[1].frame), (0, 1));
+        assert_eq!(
+            (rows[0].page_id, rows[0].pin_count, rows[0].dirty),
+            (PageID(3), 0, true)
+        );
+        assert_eq!(
+            (rows[1].page_id, rows[1].pin_count, rows[1].dirty),
+            (PageID(7), 2, false)
+        );
+        assert_eq!(rows[1].kind, PageKind::IndexInternal);
+        assert_eq!(rows[1].kind, internal.kind());
+    }
+
+    #[test]
+    fn resize_grows_and_shrinks_the_pool() {
+        let pager = Arc::new(MemPager::new());
+        let config = BufferConfig {
+            pool_size: 4,
+            memory_budget: Some(2 * 1024 * 1024),
+            ..Default::default()
+        };
+        let bm = BufferManager::with_config(config, pager.clone());
+        let capacity = bm.max_pool_size();
+        assert_eq!(capacity, BufferManager::frames_within(2 * 1024 * 1024));
+        let usage = bm.memory_usage();
+        assert_eq!(usage.frames, bm.frames.bytes_for(4));
+        assert_eq!(usage.page_table, 0);
+        assert!(usage.total() <= 2 * 1024 * 1024);
+
+        for id in 1..=4 {
+            bm.fetch_page(PageID(id)).unwrap();
+        }
+
+        // New frames join the free list so the next pages don't evict anything
+        assert_eq!(bm.resize(64).unwrap(), 64);
+        for id in 5..=64 {
+            bm.fetch_page(PageID(id)).unwrap();
+        }
+        assert_eq!(bm.stats().evictions, 0);
+
+        // Frames are handed out in order so page 20 sits in frame 19 - the shrink retires everything above it
+        // and stops there, writing back the dirty page it finds on the way
+        let pinned = bm.fetch_page(PageID(20)).unwrap();
+        bm.fetch_page(PageID(40)).unwrap().write(|_| {});
+        assert_eq!(bm.resize(8).unwrap(), 20);
+        assert_eq!(bm.pool_size(), 20);
+        assert_eq!(pager.writes(), 1);
+
+        drop(pinned);
+        assert_eq!(bm.resize(8).unwrap(), 8);
+
+        for id in 1..=64 {
+            assert_eq!(stamp(&bm.fetch_page(PageID(id)).unwrap()), id);
+        }
+        let rows = bm.buffer_cache();
+        assert_eq!(rows.len(), 8);
+        assert!(rows.iter().all(|row| row.frame < 8));
+
+        assert!(matches!(bm.resize(0), Err(BufferError::PoolSize { .. })));
+        assert!(matches!(
+            bm.resize(capacity + 1),
+            Err(BufferError::PoolSize { .. })
+        ));
+    }
+
+    #[test]
+    fn resize_stays_within_the_memory_budget() {
+        let budget = 1024 * 1024;
+        let config = BufferConfig {
+            pool_size: 16,
+            memory_budget: Some(budget),
+            ..Default::default()
+        };
+        let bm = BufferManager::with_config(config, Arc::new(MemPager::new()));
+        let capacity = bm.max_pool_size();
+
+        assert_eq!(bm.resize(capacity).unwrap(), capacity);
+        let usage = bm.memory_usage();
+        assert_eq!(usage.page_table, 0);
+        assert_eq!(usage.frames, bm.frames.bytes_for(capacity));
+        assert!(usage.total() <= budget);
+
+        // Entries of pages that left the pool are reclaimed, so a scan much bigger than the pool leaves a table
+        // no bigger than the pool
+        assert_eq!(bm.resize(16).unwrap(), 16);
+        for id in 1..=5000 {
+            bm.fetch_page(PageID(id)).unwrap();
+        }
+        assert_eq!(bm.memory_usage().page_table, 16 * PAGE_TABLE_ENTRY_BYTES);
+        bm.discard_page(PageID(5000)).unwrap();
+        assert_eq!(bm.memory_usage().page_table, 15 * PAGE_TABLE_ENTRY_BYTES);
+
+        // The full pool fits with a page in every frame
+        assert_eq!(bm.resize(capacity).unwrap(), capacity);
+        for id in 1..=capacity as u64 {
+            bm.fetch_page(PageID(id)).unwrap();
+        }
+        assert_eq!(bm.page_table.len(), capacity);
+        assert!(bm.memory_usage().total() <= budget);
+
+        // Entries held by fetches still under way count too - a new entry is refused once there is no room for
+        // it, and so is growing the pool
+        assert_eq!(bm.resize(16).unwrap(), 16);
+        let usage = bm.memory_usage();
+        let room = (budget - usage.total()) / PAGE_TABLE_ENTRY_BYTES;
+        let held: Vec<_> = (0..room as u64)
+            .map(|id| bm.page_table.get_or_insert(PageID(100_000 + id)))
+            .collect();
+        assert!(bm.memory_usage().total() <= budget);
+        assert!(matches!(
+            bm.fetch_page(PageID(99_999)),
+            Err(BufferError::OverBudget { .. })
+        ));
+        assert!(bm.page_table.get(PageID(99_999)).is_none());
+        assert!(matches!(
+            bm.resize(capacity),
+            Err(BufferError::OverBudget { .. })
+        ));
+        assert_eq!(bm.pool_size(), 16);
+        // Pages that already have an entry are still served
+        let resident = bm.buffer_cache()[0].page_id;
+        assert_eq!(stamp(&bm.fetch_page(resident).unwrap()), resident.0);
+        drop(held);
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "pin-tracking"))]
+    fn leaked_pins_are_reported_where_they_were_taken() {
+        use crate::transaction::tx_memory::TxMemory;
+
+        let bm = BufferManager::new(4, Arc::new(MemPager::new()));
+
+        let page = bm.fetch_page(PageID(1)).unwrap();
+        assert_eq!(bm.outstanding_pins(), 1);
+        drop(page);
+        assert_eq!(bm.outstanding_pins(), 0);
+
+        // A transaction that gives back everything it pinned ends quietly
+        {
+            let _tx = TxMemory::new_fake_tx(1, Arc::new(())).track_pins(&bm);
+            let _page = bm.fetch_page(PageID(1)).unwrap();
+        }
+
+        let leaked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _tx = TxMemory::new_fake_tx(2, Arc::new(())).track_pins(&bm);
+            std::mem::forget(bm.fetch_page(PageID(2)).unwrap());
+        }));
+        let msg = *leaked.unwrap_err().downcast::<String>().unwrap();
+        assert!(msg.starts_with("transaction 2 leaked 1 pin(s)"));
+        assert!(msg.contains(file!()));
+
+        // Reported once, the buffer manager doesn't report it again when it is dropped - the frame stays
+        // pinned though
+        assert_eq!(bm.outstanding_pins(), 0);
+        assert_eq!(bm.stats().pinned_frames, 1);
+    }
+
+    #[test]
+    fn pinned_pages_are_not_evicted() {
+        let bm = BufferManager::new(2, Arc::new(MemPager::new()));
+
+        let first = bm.fetch_page(PageID(1)).unwrap();
+        let second = bm.fetch_page(PageID(2)).unwrap();
+
+        assert!(matches!(
+            bm.fetch_page(PageID(3)),
+            Err(BufferError::NoFreeFrames)
+        ));
+
+        drop(first);
+        let third = bm.fetch_page(PageID(3)).unwrap();
+        assert_eq!(stamp(&third), 3);
+
+        // The pinned page stayed where it was
+        assert_eq!(state(&bm, PageID(1)), PT_ON_DISK);
+        assert_eq!(state(&bm, PageID(2)), PT_IN_MEMORY);
+        assert_eq!(stamp(&second), 2);
+    }
+
+    #[test]
+    fn failed_reads_can_be_retried() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(2, pager.clone());
+
+        pager.fail_reads.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            bm.fetch_page(PageID(1)),
+            Err(BufferError::Pager(PagerError::ReadFailed(PageID(1))))
+        ));
+        assert_eq!(state(&bm, PageID(1)), PT_ON_DISK);
+
+        // The frame went back to the pool and the entry can be loaded again once the pager recovers
+        pager.fail_reads.store(false, Ordering::SeqCst);
+        let page = bm.fetch_page(PageID(1)).unwrap();
+        assert_eq!(stamp(&page), 1);
+        assert!(bm.fetch_page(PageID(2)).is_ok());
+    }
+
+    #[test]
+    fn concurrent_fetches_survive_failing_reads() {
+        let pager = Arc::new(MemPager::new());
+        let bm = Arc::new(BufferManager::new(4, pager.clone()));
+        let thread_count = 3;
+        let barrier = Arc::new(Barrier::new(thread_count + 1));
+
+        let handles: Vec<_> = (0..thread_count)
+            .map(|t| {
+                let bm = bm.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    let mut failed = 0;
+                    for i in 0..300u64 {
+                        let id = (i * 5 + t as u64) % 8;
+                        match bm.fetch_page(PageID(id)) {
+                            Ok(page) => assert_eq!(stamp(&page), id),
+                            Err(BufferError::Pager(_)) | Err(BufferError::Latch(_)) => failed += 1,
+                            Err(err) => panic!("unexpected error {:?}", err),
+                        }
+                    }
+                    failed
+                })
+            })
+            .collect();
+
+        // Flap the pager while the readers run
+        barrier.wait();
+        for _ in 0..20 {
+            pager.fail_reads.fetch_xor(true, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        pager.fail_reads.store(false, Ordering::SeqCst);
+
+        // Only failed loads surface as errors, never a wrong page or a stuck fetch
+        for handle in handles {
+            assert!(handle.join().unwrap() <= 300);
+        }
+
+        // No entry was left stuck loading
+        for id in 0..8 {
+            assert_eq!(stamp(&bm.fetch_page(PageID(id)).unwrap()), id);
+        }
+    }
+
+    #[test]
+    fn dirty_victim_is_written_back() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(1, pager.clone());
+
+        {
+            let page = bm.fetch_page(PageID(1)).unwrap();
+            page.write(|bytes| bytes[200] = 7);
+        }
+        assert!(pager.stored(PageID(1)).is_none());
+
+        // Only one frame so page 1 has to go
+        drop(bm.fetch_page(PageID(2)).unwrap());
+        assert_eq!(pager.stored(PageID(1)).unwrap()[200], 7);
+
+        // Clean victims are not written
+        drop(bm.fetch_page(PageID(1)).unwrap());
+        assert!(pager.stored(PageID(2)).is_none());
+
+        let page = bm.fetch_page(PageID(1)).unwrap();
+        page.read(|bytes| assert_eq!(bytes[200], 7));
+    }
+
+    #[test]
+    fn flush_page_writes_dirty_pages_once() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(4, pager.clone());
+
+        let page = bm.fetch_page(PageID(1)).unwrap();
+        assert!(!page.is_dirty());
+
+        // Clean and non-resident pages have nothing to write
+        bm.flush_page(PageID(1)).unwrap();
+        bm.flush_page(PageID(2)).unwrap();
+        assert_eq!(pager.writes(), 0);
+
+        page.write(|bytes| bytes[200] = 9);
+        bm.flush_page(PageID(1)).unwrap();
+        assert_eq!(pager.writes(), 1);
+        assert_eq!(pager.stored(PageID(1)).unwrap()[200], 9);
+        assert!(!page.is_dirty());
+
+        bm.flush_page(PageID(1)).unwrap();
+        assert_eq!(pager.writes(), 1);
+    }
+
+    #[test]
+    fn failed_write_keeps_page_dirty() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(4, pager.clone());
+
+        let page = bm.fetch_page(PageID(1)).unwrap();
+        page.write(|bytes| bytes[200] = 1);
+
+        pager.fail_writes.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            bm.flush_page(PageID(1)),
+            Err(BufferError::Pager(PagerError::WriteFailed(PageID(1))))
+        ));
+        assert!(page.is_dirty());
+
+        pager.fail_writes.store(false, Ordering::SeqCst);
+        bm.flush_page(PageID(1)).unwrap();
+        assert!(!page.is_dirty());
+    }
+
+    #[test]
+    fn pages_ahead_of_the_log_are_not_written() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(1, pager.clone());
+
+        {
+            let page = bm.fetch_page(PageID(1)).unwrap();
+            set_lsn(&page, 50);
+        }
+
+        assert!(matches!(
+            bm.flush_page(PageID(1)),
+            Err(BufferError::WalNotFlushed {
+                page_lsn: 50,
+                flushed_lsn: 0,
+                ..
+            })
+        ));
+        assert!(matches!(
+            bm.flush_all(),
+            Err(BufferError::WalNotFlushed { .. })
+        ));
+
+        // It can't be evicted either so the single frame is stuck
+        assert!(matches!(
+            bm.fetch_page(PageID(2)),
+            Err(BufferError::NoFreeFrames)
+        ));
+        assert_eq!(pager.writes(), 0);
+
+        bm.set_flushed_lsn(50);
+        bm.flush_all().unwrap();
+        assert_eq!(pager.writes(), 1);
+        assert!(bm.fetch_page(PageID(3)).is_ok());
+    }
+
+    #[test]
+    fn flush_all_writes_every_dirty_frame() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(8, pager.clone());
+
+        for id in 0..8 {
+            let page = bm.fetch_page(PageID(id)).unwrap();
+            if id % 2 == 0 {
+                page.write(|bytes| bytes[200] = id as u8);
+            }
+        }
+
+        bm.flush_all().unwrap();
+        assert_eq!(pager.writes(), 4);
+        for id in (0..8).step_by(2) {
+            assert_eq!(pager.stored(PageID(id)).unwrap()[200], id as u8);
+        }
+    }
+
+    #[test]
+    fn bg_writer_cleans_frames_before_eviction() {
+        let pager = Arc::new(MemPager::new());
+        let bm = Arc::new(BufferManager::new(4, pager.clone()));
+
+        for id in 0..4 {
+            let page = bm.fetch_page(PageID(id)).unwrap();
+            page.write(|bytes| bytes[200] = id as u8 + 1);
+        }
+
+        bm.start_bg_writer(BgWriterConfig {
+            delay: std::time::Duration::from_millis(5),
+            max_pages: 2,
+            scan_ahead: 4,
+        });
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while bm.pages_written_bgwriter() < 4 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "bgwriter made no progress"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        bm.stop_bg_writer();
+
+        // Everything was cleaned in the background so evicting the lot costs the foreground nothing
+        for id in 4..8 {
+            drop(bm.fetch_page(PageID(id)).unwrap());
+        }
+        assert_eq!(bm.pages_written_backend(), 0);
+        for id in 0..4 {
+            assert_eq!(pager.stored(PageID(id)).unwrap()[200], id as u8 + 1);
+        }
+
+        // Without the writer a dirty victim is the backend's problem
+        bm.fetch_page(PageID(4))
+            .unwrap()
+            .write(|bytes| bytes[200] = 1);
+        for id in 0..4 {
+            drop(bm.fetch_page(PageID(id)).unwrap());
+        }
+        assert_eq!(bm.pages_written_backend(), 1);
+    }
+
+    #[test]
+    fn bg_writer_skips_pinned_pages_and_stops_on_drop() {
+        let pager = Arc::new(MemPager::new());
+        let bm = Arc::new(BufferManager::new(2, pager.clone()));
+
+        let pinned = bm.fetch_page(PageID(1)).unwrap();
+        pinned.write(|bytes| bytes[200] = 1);
+
+        assert_eq!(
+            bm.bg_writer_round(&BgWriterConfig::default(), 0, &mut None),
+            0
+        );
+        assert_eq!(bm.pages_written_bgwriter(), 0);
+        assert!(pinned.is_dirty());
+        drop(pinned);
+
+        bm.bg_writer_round(&BgWriterConfig::default(), 0, &mut None);
+        assert_eq!(bm.pages_written_bgwriter(), 1);
+
+        // Dropping the last reference shuts the thread down rather than leaking it
+        bm.start_bg_writer(BgWriterConfig {
+            delay: std::time::Duration::from_millis(1),
+            ..Default::default()
+        });
+        drop(bm);
+    }
+
+    #[test]
+    fn bg_writer_batches_writes_through_an_async_pager() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(8, pager.clone());
+        let mut io = Some(AsyncPager::new(pager.clone()));
+
+        for id in 0..4 {
+            let page = bm.fetch_page(PageID(id)).unwrap();
+            page.write(|bytes| bytes[200] = id as u8 + 1);
+        }
+        // Ahead of the log, so it has to stay behind
+        set_lsn(&bm.fetch_page(PageID(3)).unwrap(), 10);
+
+        bm.bg_writer_round(&BgWriterConfig::default(), 0, &mut io);
+        assert_eq!(bm.pages_written_bgwriter(), 3);
+        assert_eq!(io.as_ref().unwrap().in_flight(), 0);
+        for id in 0..3 {
+            assert!(!bm.fetch_page(PageID(id)).unwrap().is_dirty());
+            assert_eq!(pager.stored(PageID(id)).unwrap()[200], id as u8 + 1);
+        }
+        assert!(bm.fetch_page(PageID(3)).unwrap().is_dirty());
+        assert!(pager.stored(PageID(3)).is_none());
+
+        // Failed writes leave their pages dirty for the next round
+        bm.set_flushed_lsn(10);
+        pager.fail_writes.store(true, Ordering::SeqCst);
+        bm.bg_writer_round(&BgWriterConfig::default(), 0, &mut io);
+        assert!(bm.fetch_page(PageID(3)).unwrap().is_dirty());
+        pager.fail_writes.store(false, Ordering::SeqCst);
+        bm.bg_writer_round(&BgWriterConfig::default(), 0, &mut io);
+        assert!(!bm.fetch_page(PageID(3)).unwrap().is_dirty());
+        assert_eq!(bm.pages_written_bgwriter(), 4);
+        assert!(io.is_some());
+
+        // Pages held back by the log don't use up the round's budget
+        for id in 0..4 {
+            let page = bm.fetch_page(PageID(id)).unwrap();
+            page.write(|bytes| bytes[200] = 0);
+            set_lsn(&page, if id < 2 { 20 } else { 0 });
+        }
+        let config = BgWriterConfig {
+            max_pages: 2,
+            ..Default::default()
+        };
+        bm.bg_writer_round(&config, 0, &mut io);
+        assert_eq!(bm.pages_written_bgwriter(), 6);
+        for id in 0..4 {
+            assert_eq!(bm.fetch_page(PageID(id)).unwrap().is_dirty(), id < 2);
+        }
+    }
+
+    #[test]
+    fn optimistic_reads_only_see_resident_pages() {
+        let bm = BufferManager::new(1, Arc::new(MemPager::new()));
+
+        assert_eq!(bm.read_optimistic(PageID(1), |_| ()), Err(OptimisticRetry));
+
+        drop(bm.fetch_page(PageID(1)).unwrap());
+        let read_stamp = |bytes: &RawPage| u64::from_le_bytes(bytes[100..108].try_into().unwrap());
+        assert_eq!(bm.read_optimistic(PageID(1), read_stamp), Ok(1));
+
+        // Page 2 takes the only frame, page 1 must not be read out of it
+        let second = bm.fetch_page(PageID(2)).unwrap();
+        assert_eq!(
+            bm.read_optimistic(PageID(1), read_stamp),
+            Err(OptimisticRetry)
+        );
+        assert_eq!(bm.read_optimistic(PageID(2), read_stamp), Ok(2));
+
+        // Nor while a writer holds it
+        let guard = second.write_guard();
+        assert_eq!(
+            bm.read_optimistic(PageID(2), read_stamp),
+            Err(OptimisticRetry)
+        );
+        drop(guard);
+        drop(second);
+
+        // The closure may fetch, even when that evicts the page it is reading
+        let fetched = bm.read_optimistic(PageID(2), |bytes| {
+            let third = bm.fetch_page(PageID(3)).unwrap();
+            (read_stamp(bytes), stamp(&third))
+        });
+        assert_eq!(fetched, Ok((2, 3)));
+    }
+
+    #[test]
+    fn swizzled_children_skip_the_page_table() {
+        let pager = Arc::new(MemPager::new());
+        pager.put(PageID(1), internal_page(&[(b"b", 2), (b"d", 3)]));
+        let bm = swizzling_pool(4, pager.clone());
+
+        let parent = bm.fetch_page(PageID(1)).unwrap();
+        let child = bm.fetch_child(&parent, b"a").unwrap().unwrap();
+        assert_eq!(stamp(&child), 2);
+        let child_idx = bm.frame_index(child.frame());
+        drop(child);
+
+        // The pointer now names the frame and swizzling did not dirty the parent
+        let swip = Swip::decode(child_ptr(&parent.read_guard(), 0));
+        assert_eq!(swip, Swip::Frame(child_idx));
+        assert_eq!(parent.frame().swizzled_children(), 1);
+        assert!(!parent.is_dirty());
+
+        // Following it again never looks at the page table, so it works even without an entry
+        bm.page_table.remove(PageID(2));
+        let child = bm.fetch_child(&parent, b"a").unwrap().unwrap();
+        assert_eq!(stamp(&child), 2);
+
+        // Unswizzled children still go through the page table
+        let other = bm.fetch_child(&parent, b"c").unwrap().unwrap();
+        assert_eq!(stamp(&other), 3);
+        assert_eq!(parent.frame().swizzled_children(), 2);
+    }
+
+    #[test]
+    fn swizzled_pointers_never_reach_disk() {
+        let pager = Arc::new(MemPager::new());
+        pager.put(PageID(1), internal_page(&[(b"b", 2), (b"d", 3)]));
+        let bm = swizzling_pool(4, pager.clone());
+
+        // Dirtied before swizzling since a write guard would unswizzle the children
+        let parent = bm.fetch_page(PageID(1)).unwrap();
+        set_lsn(&parent, 0);
+        drop(bm.fetch_child(&parent, b"a").unwrap().unwrap());
+        assert!(Swip::is_swizzled(child_ptr(&parent.read_guard(), 0)));
+
+        bm.flush_page(PageID(1)).unwrap();
+
+        let stored = pager.stored(PageID(1)).unwrap();
+        assert_eq!(child_ptr(&stored, 0), PageID(2));
+        assert_eq!(child_ptr(&stored, 1), PageID(3));
+        // The resident copy keeps its swizzled pointer
+        assert!(Swip::is_swizzled(child_ptr(&parent.read_guard(), 0)));
+    }
+
+    #[test]
+    fn splitting_a_parent_with_swizzled_children() {
+        let pager = Arc::new(MemPager::new());
+        let keys: [&[u8]; 4] = [b"b", b"d", b"f", b"h"];
+        let cells: Vec<(&[u8], u64)> = keys.iter().zip(2..).map(|(key, id)| (*key, id)).collect();
+        pager.put(PageID(1), internal_page(&cells));
+        let bm = swizzling_pool(6, pager.clone());
+
+        let parent = bm.fetch_page(PageID(1)).unwrap();
+        for key in [b"a", b"c", b"e", b"g"] {
+            drop(bm.fetch_child(&parent, key).unwrap().unwrap());
+        }
+        assert_eq!(parent.frame().swizzled_children(), 4);
+
+        // Readers through the cache only ever see page ids, and a swizzled pointer is no page id
+        let ptrs = PageCache::read(&bm, PageID(1), |bytes| {
+            Ok::<_, PageCacheError>(
+                IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(bytes)).child_slots(),
+            )
+        })
+        .unwrap();
+        assert!(ptrs.iter().all(|(_, ptr)| !Swip::is_swizzled(*ptr)));
+        let swizzled = child_ptr(&parent.read_guard(), 0);
+        assert!(matches!(
+            bm.fetch_page(swizzled),
+            Err(BufferError::SwizzledPageID(_))
+        ));
+
+        // Move the upper half into a new right page the way a split would, copying the pointers as found
+        let moved = {
+            let guard = parent.write_guard();
+            let slots =
+                IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(&guard)).child_slots();
+            slots[2..].iter().map(|(_, ptr)| *ptr).collect::<Vec<_>>()
+        };
+        let right: Vec<(&[u8], u64)> = keys[2..]
+            .iter()
+            .zip(&moved)
+            .map(|(key, ptr)| (*key, ptr.0))
+            .collect();
+        drop(bm.create_page(PageID(7), PageKind::IndexInternal, &internal_page(&right)));
+
+        assert_eq!(moved, vec![PageID(4), PageID(5)]);
+        assert_eq!(parent.frame().swizzled_children(), 0);
+        for child_id in 2..6 {
+            let child = bm.fetch_page(PageID(child_id)).unwrap();
+            assert_eq!(child.frame().parent(), None);
+        }
+
+        // Nothing ties the children to the parent any more so they can all be evicted around it - holding
+        // the new pins leaves the children and the right page as the only victims
+        let _held: Vec<_> = (10..15)
+            .map(|page_id| bm.fetch_page(PageID(page_id)).unwrap())
+            .collect();
+        for child_id in 2..6 {
+            assert_eq!(state(&bm, PageID(child_id)), PT_ON_DISK);
+        }
+    }
+
+    #[test]
+    fn evicting_a_child_unswizzles_its_parent() {
+        let pager = Arc::new(MemPager::new());
+        pager.put(PageID(1), internal_page(&[(b"b", 2), (b"d", 3)]));
+        let bm = swizzling_pool(2, pager.clone());
+
+        {
+            let parent = bm.fetch_page(PageID(1)).unwrap();
+            drop(bm.fetch_child(&parent, b"a").unwrap().unwrap());
+        }
+
+        // Neither page is pinned but the parent has a swizzled child so the child has to be the victim
+        drop(bm.fetch_page(PageID(10)).unwrap());
+        assert_eq!(state(&bm, PageID(1)), PT_IN_MEMORY);
+        assert_eq!(state(&bm, PageID(2)), PT_ON_DISK);
+
+        let parent = bm.fetch_page(PageID(1)).unwrap();
+        assert_eq!(child_ptr(&parent.read_guard(), 0), PageID(2));
+        assert_eq!(parent.frame().swizzled_children(), 0);
+        assert!(!parent.is_dirty());
+
+        // And it comes back through the page table and is swizzled again
+        let child = bm.fetch_child(&parent, b"a").unwrap().unwrap();
+        assert_eq!(stamp(&child), 2);
+        assert!(Swip::is_swizzled(child_ptr(&parent.read_guard(), 0)));
+    }
+
+    #[test]
+    fn concurrent_traversals_with_swizzling() {
+        let pager = Arc::new(MemPager::new());
+        let children: Vec<(Vec<u8>, u64)> = (0..8u8)
+            .map(|k| (vec![b'b' + 2 * k], 2 + k as u64))
+            .collect();
+        let cells: Vec<(&[u8], u64)> = children
+            .iter()
+            .map(|(key, id)| (key.as_slice(), *id))
+            .collect();
+        pager.put(PageID(1), internal_page(&cells));
+        let bm = Arc::new(swizzling_pool(5, pager));
+
+        // Each thread holds at most the parent and one child, so with three threads there is always a victim
+        let thread_count = 3;
+        let barrier = Arc::new(Barrier::new(thread_count));
+        let handles: Vec<_> = (0..thread_count)
+            .map(|t| {
+                let bm = bm.clone();
+                let b = barrier.clone();
+                std::thread::spawn(move || {
+                    b.wait();
+                    for i in 0..300u64 {
+                        let k = ((i * 3 + t as u64) % 8) as u8;
+                        let parent = bm.fetch_page(PageID(1)).unwrap();
+                        let child = bm.fetch_child(&parent, &[b'a' + 2 * k]).unwrap().unwrap();
+                        assert_eq!(stamp(&child), 2 + k as u64);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Whatever is still swizzled matches the count on the parent
+        let parent = bm.fetch_page(PageID(1)).unwrap();
+        let swizzled = (0..8)
+            .filter(|slot| Swip::is_swizzled(child_ptr(&parent.read_guard(), *slot)))
+            .count();
+        assert_eq!(parent.frame().swizzled_children() as usize, swizzled);
+    }
+
+    #[test]
+    fn bulk_read_ring_keeps_the_working_set() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(64, pager.clone());
+        let hot: Vec<PageID> = (0..32).map(PageID).collect();
+
+        let warm_up = |bm: &BufferManager| {
+            for _ in 0..3 {
+                for page_id in &hot {
+                    drop(bm.fetch_page(*page_id).unwrap());
+                }
+            }
+        };
+        let resident = |bm: &BufferManager| {
+            hot.iter()
+                .filter(|id| state(bm, **id) == PT_IN_MEMORY)
+                .count()
+        };
+
+        warm_up(&bm);
+        let ring = bm.access_strategy(AccessStrategy::BulkRead);
+        assert_eq!(ring.size(), 8);
+        for id in 1_000..2_000 {
+            let page = bm.fetch_page_with(PageID(id), &ring).unwrap();
+            assert_eq!(stamp(&page), id);
+        }
+
+        // The scan never had more than a ring's worth of frames
+        assert_eq!(resident(&bm), hot.len());
+        let scanned = (1_000..2_000)
+            .filter(|id| state(&bm, PageID(*id)) == PT_IN_MEMORY)
+            .count();
+        assert!(scanned <= ring.size(), "scan kept {} frames", scanned);
+
+        // Without the ring the same scan washes the hot pages out
+        let bm = BufferManager::new(64, pager);
+        warm_up(&bm);
+        for id in 1_000..2_000 {
+            drop(bm.fetch_page(PageID(id)).unwrap());
+        }
+        assert_eq!(resident(&bm), 0);
+    }
+
+    #[test]
+    fn ring_skips_frames_it_cannot_recycle() {
+        let pager = Arc::new(MemPager::new());
+        let bm = BufferManager::new(16, pager.clone());
+        let ring = bm.access_strategy(AccessStrategy::BulkWrite);
+        assert_eq!(ring.size(), 2);
+
+        // A bulk write dirties everything it touches, recycling a frame writes the old page out first
+        for id in 0..6 {
+            let page = bm.fetch_page_with(PageID(id), &ring).unwrap();
+            page.write(|bytes| bytes[200] = id as u8);
+        }
+        assert_eq!(pager.writes(), 4);
+        assert_eq!(pager.stored(PageID(0)).unwrap()[200], 0);
+
+        // A ring page somebody else still has pinned is left alone and the ring takes a new frame
+        let ring_frames = ring.frames();
+        let held = bm.fetch_page(PageID(4)).unwrap();
+        for id in 6..8 {
+            drop(bm.fetch_page_with(PageID(id), &ring).unwrap());
+        }
+        assert_eq!(state(&bm, PageID(4)), PT_IN_MEMORY);
+        assert_eq!(stamp(&held), 4);
+        assert_ne!(ring.frames(), ring_frames);
+
+        // Pages ahead of the log can't be recycled either
+        let ring = bm.access_strategy(AccessStrategy::BulkWrite);
+        set_lsn(&bm.fetch_page_with(PageID(20), &ring).unwrap(), 10);
+        drop(bm.fetch_page_with(PageID(21), &ring).unwrap());
+        drop(bm.fetch_page_with(PageID(22), &ring).unwrap());
+        assert_eq!(state(&bm, PageID(20)), PT_IN_MEMORY);
+    }
+
+    #[test]
+    fn prefetched_pages_are_loaded_unpinned_and_tracked() {
+        let bm = Arc::new(BufferManager::new(4, Arc::new(MemPager::new())));
+
+        // Nothing to hand the request to
+        bm.prefetch(&[PageID(1)]);
+        assert!(bm.page_table.get(PageID(1)).is_none());
+
+        bm.start_prefetcher(PrefetchConfig::default());
+        bm.prefetch(&[PageID(1), PageID(2), PageID(3)]);
+        wait_for("prefetch", || bm.prefetch_stats().loaded == 3);
+
+        for id in 1..=3 {
+            assert_eq!(state(&bm, PageID(id)), PT_IN_MEMORY);
+        }
+        assert!(bm.frames.iter().all(|frame| !frame.is_pinned()));
+
+        // Only the first fetch counts as using the prefetch
+        assert_eq!(stamp(&bm.fetch_page(PageID(1)).unwrap()), 1);
+        drop(bm.fetch_page(PageID(1)).unwrap());
+        assert_eq!(bm.prefetch_stats().used, 1);
+
+        // Cycle the pool so the other two are evicted without being asked for
+        for id in 10..18 {
+            drop(bm.fetch_page(PageID(id)).unwrap());
+        }
+        assert_eq!(
+            bm.prefetch_stats(),
+            PrefetchStats {
+                loaded: 3,
+                used: 1,
+                wasted: 2
+            }
+        );
+    }
+
+    #[test]
+    fn scans_read_ahead_along_right_siblings() {
+        let pager = Arc::new(MemPager::new());
+        for id in 20..=40 {
+            let sibling = (id < 40).then_some(id + 1);
+            pager.put(PageID(id), leaf_page(id, sibling));
+        }
+        let bm = Arc::new(BufferManager::new(64, pager));
+
+        // Without a prefetcher the scan still walks the chain, it just loads every page itself
+        let mut page = bm.fetch_page(PageID(20)).unwrap();
+        while let Some(next) = bm.fetch_right_sibling(&page).unwrap() {
+            page = next;
+        }
+        assert_eq!(stamp(&page), 40);
+        assert_eq!(bm.prefetch_stats().loaded, 0);
+        drop(page);
+
+        let bm = Arc::new(BufferManager::new(64, bm.pager.clone()));
+        bm.start_prefetcher(PrefetchConfig {
+            readahead: 4,
+            ..Default::default()
+        });
+
+        let mut visited = vec![];
+        let mut page = bm.fetch_page(PageID(20)).unwrap();
+        loop {
+            visited.push(stamp(&page));
+            // Some work per page so the prefetcher gets to run ahead
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            match bm.fetch_right_sibling(&page).unwrap() {
+                Some(next) => page = next,
+                None => break,
+            }
+        }
+        drop(page);
+
+        assert_eq!(visited, (20..=40).collect::<Vec<_>>());
+        let stats = bm.prefetch_stats();
+        assert!(stats.used > 0);
+        assert!(stats.used + stats.wasted <= stats.loaded);
+        // Everything loaded ahead lies on the chain after the first page
+        assert!(stats.loaded <= 20);
+    }
+
+    #[test]
+    fn prefetch_windows_are_read_through_an_async_pager() {
+        let path = std::env::temp_dir().join(format!("inkdb-prefetch-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let pager = Arc::new(FilePager::open(&path).unwrap());
+        for id in 0..8 {
+            pager.allocate_page().unwrap();
+            let sibling = (id < 7).then_some(id + 1);
+            pager
+                .write_page(PageID(id), &leaf_page(id, sibling))
+                .unwrap();
+        }
+        let bm = BufferManager::new(8, pager.clone());
+        // Shallower than the window so the ring has to reuse its slots
+        let mut io = AsyncPager::for_file(pager.clone(), 2);
+
+        // One page reads ahead along its chain, the others only load themselves
+        let window: Vec<_> = (1..5)
+            .map(|id| (PageID(id), if id == 4 { 2 } else { 0 }))
+            .collect();
+        assert_eq!(
+            bm.prefetch_window(&mut io, &window).unwrap(),
+            vec![(PageID(5), 1)]
+        );
+        assert_eq!(io.in_flight(), 0);
+        assert_eq!(bm.prefetch_stats().loaded, 4);
+        for id in 1..5 {
+            assert_eq!(state(&bm, PageID(id)), PT_IN_MEMORY);
+        }
+        assert!(
+            bm.frames
+                .iter()
+                .all(|frame| !frame.is_pinned() && !frame.is_dirty())
+        );
+
+        // Prefetched and not used yet, so neither loaded again nor followed further
+        assert_eq!(
+            bm.prefetch_window(&mut io, &[(PageID(4), 2)]).unwrap(),
+            vec![]
+        );
+        assert_eq!(stamp(&bm.fetch_page(PageID(3)).unwrap()), 3);
+        assert_eq!(bm.prefetch_stats().used, 1);
+
+        // A read that fails gives its frame back, and with nobody waiting on it the entry goes again
+        let free = bm.free_list.lock().unwrap().len();
+        assert_eq!(
+            bm.prefetch_window(&mut io, &[(PageID(5), 0), (PageID(20), 0)])
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(bm.prefetch_stats().loaded, 5);
+        assert_eq!(bm.free_list.lock().unwrap().len(), free - 1);
+        assert!(bm.page_table.get(PageID(20)).is_none());
+
+        // A fetch waiting on the load sees the failure on the entry like any failed load
+        let waiter = bm.page_table.get_or_insert(PageID(20));
+        bm.prefetch_window(&mut io, &[(PageID(20), 0)]).unwrap();
+        assert_eq!(waiter.latch().failures(), 1);
+        assert_eq!(waiter.latch().state(), PT_ON_DISK);
+
+        // With every frame pinned there is nothing to read into - the claim is handed back without a failure
+        let pinned: Vec<_> = (0..8)
+            .map(|id| bm.fetch_page(PageID(id)).unwrap())
+            .collect();
+        assert_eq!(
+            bm.prefetch_window(&mut io, &[(PageID(20), 0)]).unwrap(),
+            vec![]
+        );
+        assert_eq!(waiter.latch().failures(), 1);
+        assert_eq!(waiter.latch().state(), PT_ON_DISK);
+        assert_eq!(bm.prefetch_stats().loaded, 5);
+        drop(pinned);
+
+        drop(bm);
+        drop(io);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn concurrent_fetches_never_see_the_wrong_page() {
+        let policies = [
+            EvictionKind::ClockSweep,
+            EvictionKind::LruK { k: 2 },
+            EvictionKind::TwoQ,
+            EvictionKind::Arc,
+        ];
+
+        for eviction in policies {
+            let config = BufferConfig {
+                pool_size: 4,
+                eviction,
+                ..Default::default()
+            };
+            let bm = Arc::new(BufferManager::with_config(
+                config,
+                Arc::new(MemPager::new()),
+            ));
+
+            // Each thread holds at most one pin so with fewer threads than frames there is always a victim
+            let thread_count = 3;
+            let barrier = Arc::new(Barrier::new(thread_count));
+            let mut handles = Vec::with_capacity(thread_count);
+
+            for t in 0..thread_count {
+                let bm = bm.clone();
+                let b = barrier.clone();
+                handles.push(std::thread::spawn(move || {
+                    b.wait();
+                    for i in 0..500u64 {
+                        let id = (i * 7 + t as u64) % 16;
+                        if let Ok(seen) = bm.read_optimistic(PageID(id), |bytes| {
+                            u64::from_le_bytes(bytes[100..108].try_into().unwrap())
+                        }) {
+                            assert_eq!(seen, id);
+                        }
+                        let page = bm.fetch_page(PageID(id)).unwrap();
+                        assert_eq!(stamp(&page), id);
+                    }
+                }));
+            }
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        }
+    }
+}
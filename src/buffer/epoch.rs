@@ -0,0 +1,218 @@
+// Epoch based reclamation for frames that are read without a pin.
+//
+// The fetch path does not need this - a pin is published before the reader checks the page table entry and the
+// evictor checks pins after moving the entry out of in-memory, so pins already work as hazard pointers. Optimistic
+// readers skip the pin though: they peek a PageTableResult::Memory(frame) and read the frame straight away. To
+// keep them from reading a frame that has been handed to another page, they run inside an epoch and the evictor
+// waits for every reader that might still have the old mapping before the frame is reused.
+//
+// The scheme is the two-parity variant of classic EBR:
+//   - readers count themselves into active[global % 2] for the length of the critical section
+//   - the global epoch can only move from e to e + 1 once nobody is left in e - 1 (same parity as e + 1)
+//   - anything unlinked while the global epoch was e is unreachable once the global epoch reaches e + 2
+//
+// Counters are sharded by thread so entering an epoch does not write to a cache line every reader shares.
+//
+// synchronize waits for every reader, so a thread that calls it while holding a guard of its own waits for itself
+// forever. Nothing that can evict (fetching, allocating, discarding a page) may run inside an epoch - debug builds
+// remember which managers each thread holds a guard from and assert on it.
+
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+const SHARDS: usize = 32;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+}
+
+// Managers this thread holds guards from, one entry per guard
+#[cfg(debug_assertions)]
+thread_local! {
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+#[repr(align(64))]
+struct ActiveCounts([AtomicUsize; 2]);
+
+pub(crate) struct EpochManager {
+    global: AtomicU64,
+    active: Box<[ActiveCounts]>,
+}
+
+impl EpochManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            global: AtomicU64::new(0),
+            active: (0..SHARDS)
+                .map(|_| ActiveCounts([AtomicUsize::new(0), AtomicUsize::new(0)]))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn current(&self) -> u64 {
+        self.global.load(Ordering::SeqCst)
+    }
+
+    // Anything the caller reaches through shared mappings while holding the guard stays valid until it is dropped
+    pub(crate) fn enter(&self) -> EpochGuard<'_> {
+        let shard = SHARD.with(|shard| *shard);
+        loop {
+            let epoch = self.global.load(Ordering::SeqCst);
+            let parity = (epoch % 2) as usize;
+            let count = &self.active[shard].0[parity];
+            count.fetch_add(1, Ordering::SeqCst);
+
+            // The epoch moved before we were counted - count ourselves into the new one instead
+            if self.global.load(Ordering::SeqCst) == epoch {
+                #[cfg(debug_assertions)]
+                HELD.with(|held| held.borrow_mut().push(self.id()));
+                return EpochGuard {
+                    epochs: self,
+                    shard,
+                    parity,
+                };
+            }
+            count.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn readers_in(&self, parity: usize) -> usize {
+        self.active
+            .iter()
+            .map(|counts| counts.0[parity].load(Ordering::SeqCst))
+            .sum()
+    }
+
+    // Moves the global epoch on if nobody is still inside the previous one
+    fn try_advance(&self) -> bool {
+        let epoch = self.global.load(Ordering::SeqCst);
+        let previous = ((epoch + 1) % 2) as usize;
+        if self.readers_in(previous) != 0 {
+            return false;
+        }
+        // Losing the CAS means somebody else advanced it which is just as good
+        let _ = self
+            .global
+            .compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::SeqCst);
+        true
+    }
+
+    // Waits until every reader that was inside an epoch when we were called has left. Must not be called while
+    // holding a guard from the same manager or it waits for itself.
+    pub(crate) fn synchronize(&self) {
+        #[cfg(debug_assertions)]
+        HELD.with(|held| {
+            assert!(
+                !held.borrow().contains(&self.id()),
+                "synchronize called while holding an epoch guard, it would wait for itself"
+            )
+        });
+
+        let target = self.current() + 2;
+        let mut spins = 0u32;
+        while self.current() < target {
+            if !self.try_advance() {
+                if spins < 10 {
+                    std::hint::spin_loop();
+                } else {
+                    std::thread::yield_now();
+                }
+                spins = spins.saturating_add(1);
+            }
+        }
+    }
+}
+
+pub(crate) struct EpochGuard<'a> {
+    epochs: &'a EpochManager,
+    shard: usize,
+    parity: usize,
+}
+
+impl Drop for EpochGuard<'_> {
+    fn drop(&mut self) {
+        self.epochs.active[self.shard].0[self.parity].fetch_sub(1, Ordering::SeqCst);
+        #[cfg(debug_assertions)]
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(idx) = held.iter().position(|id| *id == self.epochs.id()) {
+                held.swap_remove(idx);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn synchronize_waits_for_readers_inside_an_epoch() {
+        let epochs = Arc::new(EpochManager::new());
+
+        // Nobody inside so this returns straight away having moved the epoch twice
+        epochs.synchronize();
+        assert_eq!(epochs.current(), 2);
+
+        let entered = Arc::new(Barrier::new(2));
+        let released = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let epochs = epochs.clone();
+            let entered = entered.clone();
+            let released = released.clone();
+            std::thread::spawn(move || {
+                let guard = epochs.enter();
+                entered.wait();
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                released.store(true, Ordering::SeqCst);
+                drop(guard);
+            })
+        };
+
+        entered.wait();
+        epochs.synchronize();
+        assert!(
+            released.load(Ordering::SeqCst),
+            "synchronize returned while a reader was inside"
+        );
+        reader.join().unwrap();
+
+        // A reader that arrives after the call does not hold it up
+        let _late = epochs.enter();
+        let before = epochs.current();
+        assert!(epochs.try_advance());
+        assert_eq!(epochs.current(), before + 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn synchronize_refuses_to_wait_for_its_own_thread() {
+        let epochs = EpochManager::new();
+        let other = EpochManager::new();
+
+        // A guard from another manager is no reason to wait
+        let guard = other.enter();
+        epochs.synchronize();
+        drop(guard);
+
+        let guard = epochs.enter();
+        let waited =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| epochs.synchronize()));
+        assert!(waited.is_err());
+        drop(guard);
+
+        // Once the guard is gone it goes through
+        epochs.synchronize();
+    }
+}
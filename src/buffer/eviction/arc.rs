@@ -0,0 +1,164 @@
+// ARC - Adaptive Replacement Cache (Megiddo & Modha).
+//
+//   T1  frames holding pages seen once recently
+//   T2  frames holding pages seen at least twice
+//   B1  ghosts of pages evicted from T1
+//   B2  ghosts of pages evicted from T2
+//
+// `p` is the target size of T1 and moves on every ghost hit - a miss on a page remembered in B1 means T1
+// was too small so p grows, a miss on a page in B2 means T2 was too small so p shrinks. That lets the cache
+// shift between recency and frequency as the workload changes without any tuning.
+//
+// Two differences from the paper, both forced by the buffer manager:
+//   - the victim is chosen before the incoming page is known (the frame is claimed before the read), so p
+//     adapts on the load that follows rather than before the replacement
+//   - pinned frames cannot be taken, so when the preferred list has nothing evictable we use the other one
+
+use crate::buffer::eviction::EvictionPolicy;
+use crate::buffer::eviction::frame_list::{FrameList, GhostList};
+use crate::page::PageID;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub(crate) struct ArcPolicy {
+    capacity: AtomicUsize,
+    inner: Mutex<ArcInner>,
+}
+
+struct ArcInner {
+    p: usize,
+    t1: FrameList,
+    t2: FrameList,
+    b1: GhostList,
+    b2: GhostList,
+}
+
+impl ArcPolicy {
+    pub(crate) fn new(frames: usize) -> Self {
+        Self {
+            capacity: AtomicUsize::new(frames),
+            inner: Mutex::new(ArcInner {
+                p: 0,
+                t1: FrameList::new(frames),
+                t2: FrameList::new(frames),
+                b1: GhostList::new(frames),
+                b2: GhostList::new(frames),
+            }),
+        }
+    }
+
+    pub(crate) fn target_t1(&self) -> usize {
+        self.inner.lock().unwrap().p
+    }
+}
+
+impl EvictionPolicy for ArcPolicy {
+    fn record_load(&self, frame: usize, page_id: PageID) {
+        let mut inner = self.inner.lock().unwrap();
+        let c = self.capacity.load(Ordering::Relaxed);
+
+        if inner.b1.contains(page_id) {
+            let delta = (inner.b2.len() / inner.b1.len()).max(1);
+            inner.p = (inner.p + delta).min(c);
+            inner.b1.remove(page_id);
+            inner.t2.push_front(frame);
+        } else if inner.b2.contains(page_id) {
+            let delta = (inner.b1.len() / inner.b2.len()).max(1);
+            inner.p = inner.p.saturating_sub(delta);
+            inner.b2.remove(page_id);
+            inner.t2.push_front(frame);
+        } else {
+            inner.t1.push_front(frame);
+        }
+
+        // Keep the directory within the paper's bounds - |T1| + |B1| <= c and the whole thing within 2c
+        while inner.t1.len() + inner.b1.len() > c && inner.b1.pop_oldest().is_some() {}
+        while inner.t1.len() + inner.t2.len() + inner.b1.len() + inner.b2.len() > 2 * c
+            && inner.b2.pop_oldest().is_some()
+        {}
+    }
+
+    fn record_access(&self, frame: usize, _page_id: PageID) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.t1.remove(frame) || inner.t2.contains(frame) {
+            inner.t2.push_front(frame);
+        }
+    }
+
+    fn record_evict(&self, frame: usize, page_id: PageID) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.t1.remove(frame) {
+            inner.b1.push(page_id);
+        } else if inner.t2.remove(frame) {
+            inner.b2.push(page_id);
+        }
+    }
+
+    fn remove(&self, frame: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.t1.remove(frame);
+        inner.t2.remove(frame);
+    }
+
+    fn victim(&self, is_evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let inner = self.inner.lock().unwrap();
+
+        let (first, second) = if inner.t1.len() > 0 && (inner.t1.len() > inner.p || inner.t2.len() == 0)
+        {
+            (&inner.t1, &inner.t2)
+        } else {
+            (&inner.t2, &inner.t1)
+        };
+
+        first
+            .find_from_back(is_evictable)
+            .or_else(|| second.find_from_back(is_evictable))
+    }
+
+    fn resize(&self, frames: usize) {
+        self.capacity.store(frames, Ordering::Relaxed);
+        let mut inner = self.inner.lock().unwrap();
+        inner.p = inner.p.min(frames);
+        inner.b1.set_capacity(frames);
+        inner.b2.set_capacity(frames);
+    }
+
+    fn memory_usage(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        size_of::<Self>()
+            + inner.t1.memory_usage()
+            + inner.t2.memory_usage()
+            + inner.b1.memory_usage()
+            + inner.b2.memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ghost_hits_move_the_target() {
+        let arc = ArcPolicy::new(2);
+
+        arc.record_load(0, PageID(1));
+        arc.record_load(1, PageID(2));
+        assert_eq!(arc.target_t1(), 0);
+
+        // Both pages sit in T1, the oldest goes first and is remembered in B1
+        assert_eq!(arc.victim(&|_| true), Some(0));
+        arc.record_evict(0, PageID(1));
+
+        // Coming back while in B1 grows T1's target and lands the page in T2
+        arc.record_load(0, PageID(1));
+        assert_eq!(arc.target_t1(), 1);
+
+        // T1 is at its target so the victim now comes from T2, which only holds page 1
+        assert_eq!(arc.victim(&|_| true), Some(0));
+
+        // Page 2 gets a second hit and moves to T2 as well
+        arc.record_access(1, PageID(2));
+        assert_eq!(arc.victim(&|_| true), Some(0));
+        assert_eq!(arc.victim(&|f| f != 0), Some(1));
+    }
+}
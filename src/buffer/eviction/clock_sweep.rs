@@ -0,0 +1,158 @@
+// Clock-sweep replacement - the same approach Postgres takes for shared buffers.
+//
+// Every frame has a small usage counter which is bumped on access, saturating at MAX_USAGE_COUNT. A single
+// hand sweeps round the pool; frames the buffer manager cannot evict (pinned or not holding a page) are
+// skipped, a frame with a non-zero count has it decremented and is passed over, and the first evictable frame
+// found with a count of zero is the victim. Hot pages survive several passes of the hand while pages that
+// were touched once are reclaimed on the next one.
+//
+// The counters live here rather than in the PageFrame so the frame only carries what every policy needs.
+
+use crate::buffer::eviction::EvictionPolicy;
+use crate::page::PageID;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+pub(crate) const MAX_USAGE_COUNT: u8 = 5;
+
+pub(crate) struct ClockSweep {
+    hand: AtomicUsize,
+    usage: Box<[AtomicU8]>,
+    // Frames in use, the hand only sweeps these
+    active: AtomicUsize,
+}
+
+impl ClockSweep {
+    pub(crate) fn new(frames: usize) -> Self {
+        assert!(frames > 0, "clock sweep needs at least one frame");
+        Self {
+            hand: AtomicUsize::new(0),
+            usage: (0..frames).map(|_| AtomicU8::new(0)).collect(),
+            active: AtomicUsize::new(frames),
+        }
+    }
+
+    pub(crate) fn record_access(&self, frame: usize) {
+        let _ = self.usage[frame].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+            (count < MAX_USAGE_COUNT).then_some(count + 1)
+        });
+    }
+
+    // Called when a frame is emptied so the next page loaded into it starts cold
+    pub(crate) fn reset(&self, frame: usize) {
+        self.usage[frame].store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn usage(&self, frame: usize) -> u8 {
+        self.usage[frame].load(Ordering::Relaxed)
+    }
+
+    // Current position of the hand in the pool
+    pub(crate) fn hand(&self) -> usize {
+        self.hand.load(Ordering::Relaxed) % self.active.load(Ordering::Relaxed)
+    }
+
+    // Advances the hand until it finds a victim. `is_candidate` is asked about every frame the hand passes and
+    // frames it rejects keep their usage count.
+    //
+    // The victim is only a candidate - the caller still has to win the page table entry and check pins, and
+    // simply asks again if it loses. Several threads may sweep at once, each tick of the hand goes to one of them.
+    pub(crate) fn next_victim(&self, is_candidate: impl Fn(usize) -> bool) -> Option<usize> {
+        let frames = self.active.load(Ordering::Relaxed);
+
+        // After MAX_USAGE_COUNT + 1 full rotations every evictable frame has been brought down to zero, so if
+        // we still have nothing then every frame is pinned
+        for _ in 0..frames * (MAX_USAGE_COUNT as usize + 1) {
+            let frame = self.hand.fetch_add(1, Ordering::Relaxed) % frames;
+
+            if !is_candidate(frame) {
+                continue;
+            }
+
+            let decremented = self.usage[frame]
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                    count.checked_sub(1)
+                })
+                .is_ok();
+
+            if decremented {
+                continue;
+            }
+
+            return Some(frame);
+        }
+
+        None
+    }
+}
+
+impl EvictionPolicy for ClockSweep {
+    fn record_load(&self, frame: usize, _page_id: PageID) {
+        self.reset(frame);
+        self.record_access(frame);
+    }
+
+    fn record_access(&self, frame: usize, _page_id: PageID) {
+        ClockSweep::record_access(self, frame);
+    }
+
+    fn record_evict(&self, frame: usize, _page_id: PageID) {
+        self.reset(frame);
+    }
+
+    fn remove(&self, frame: usize) {
+        self.reset(frame);
+    }
+
+    fn victim(&self, is_evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        self.next_victim(is_evictable)
+    }
+
+    fn hand(&self) -> Option<usize> {
+        Some(ClockSweep::hand(self))
+    }
+
+    fn resize(&self, frames: usize) {
+        debug_assert!(frames > 0 && frames <= self.usage.len());
+        self.active.store(frames, Ordering::Relaxed);
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.usage.len() * size_of::<AtomicU8>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_count_gives_second_chance() {
+        let clock = ClockSweep::new(4);
+
+        // Frame 0 is hot, frame 1 was touched once and frames 2 and 3 are cold
+        for _ in 0..10 {
+            clock.record_access(0);
+        }
+        clock.record_access(1);
+        assert_eq!(clock.usage(0), MAX_USAGE_COUNT);
+
+        assert_eq!(clock.next_victim(|_| true), Some(2));
+        assert_eq!(clock.next_victim(|_| true), Some(3));
+
+        // The first pass took frame 1 down to zero so it goes next, frame 0 is still warm
+        assert_eq!(clock.next_victim(|_| true), Some(1));
+        assert!(clock.usage(0) > 0);
+    }
+
+    #[test]
+    fn pinned_frames_are_never_chosen() {
+        let clock = ClockSweep::new(3);
+        clock.record_access(2);
+
+        // Only frame 2 is evictable, the sweep must wear its count down and then take it
+        assert_eq!(clock.next_victim(|frame| frame == 2), Some(2));
+
+        // Nothing is evictable so the sweep gives up instead of spinning forever
+        assert_eq!(clock.next_victim(|_| false), None);
+    }
+}
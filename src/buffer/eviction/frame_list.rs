@@ -0,0 +1,207 @@
+// Small building blocks shared by the list based policies (2Q and ARC).
+//
+// FrameList is an intrusive doubly linked list over frame indices - the links are stored in vectors sized to
+// the pool so moving a frame to the front or unlinking it is O(1) with no allocation on the hot path.
+// Front is most recently used, back is least recently used.
+//
+// GhostList remembers the PageIDs of recently evicted pages (no frames, no data) up to a capacity. Removal
+// is lazy - the ordering queue keeps stale entries which are skipped when they reach the front.
+
+use crate::page::PageID;
+use std::collections::{HashMap, VecDeque};
+
+const NIL: usize = usize::MAX;
+
+pub(super) struct FrameList {
+    head: usize,
+    tail: usize,
+    prev: Vec<usize>,
+    next: Vec<usize>,
+    member: Vec<bool>,
+    len: usize,
+}
+
+impl FrameList {
+    pub(super) fn new(frames: usize) -> Self {
+        Self {
+            head: NIL,
+            tail: NIL,
+            prev: vec![NIL; frames],
+            next: vec![NIL; frames],
+            member: vec![false; frames],
+            len: 0,
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(super) fn memory_usage(&self) -> usize {
+        self.prev.capacity() * size_of::<usize>() * 2 + self.member.capacity()
+    }
+
+    pub(super) fn contains(&self, frame: usize) -> bool {
+        self.member[frame]
+    }
+
+    pub(super) fn push_front(&mut self, frame: usize) {
+        if self.member[frame] {
+            self.remove(frame);
+        }
+
+        self.prev[frame] = NIL;
+        self.next[frame] = self.head;
+        if self.head != NIL {
+            self.prev[self.head] = frame;
+        } else {
+            self.tail = frame;
+        }
+        self.head = frame;
+        self.member[frame] = true;
+        self.len += 1;
+    }
+
+    pub(super) fn remove(&mut self, frame: usize) -> bool {
+        if !self.member[frame] {
+            return false;
+        }
+
+        let (prev, next) = (self.prev[frame], self.next[frame]);
+        if prev != NIL {
+            self.next[prev] = next;
+        } else {
+            self.head = next;
+        }
+        if next != NIL {
+            self.prev[next] = prev;
+        } else {
+            self.tail = prev;
+        }
+
+        self.prev[frame] = NIL;
+        self.next[frame] = NIL;
+        self.member[frame] = false;
+        self.len -= 1;
+        true
+    }
+
+    // Walks from the least recently used end and returns the first frame that passes the filter
+    pub(super) fn find_from_back(&self, filter: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let mut cur = self.tail;
+        while cur != NIL {
+            if filter(cur) {
+                return Some(cur);
+            }
+            cur = self.prev[cur];
+        }
+        None
+    }
+}
+
+pub(super) struct GhostList {
+    capacity: usize,
+    seq: u64,
+    members: HashMap<PageID, u64>,
+    order: VecDeque<(PageID, u64)>,
+}
+
+impl GhostList {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seq: 0,
+            members: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    // Shrinking forgets the oldest pages straight away
+    pub(super) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.members.len() > self.capacity {
+            self.pop_oldest();
+        }
+    }
+
+    pub(super) fn memory_usage(&self) -> usize {
+        self.members.capacity() * (size_of::<PageID>() + size_of::<u64>())
+            + self.order.capacity() * size_of::<(PageID, u64)>()
+    }
+
+    pub(super) fn contains(&self, page_id: PageID) -> bool {
+        self.members.contains_key(&page_id)
+    }
+
+    pub(super) fn push(&mut self, page_id: PageID) {
+        self.seq += 1;
+        self.members.insert(page_id, self.seq);
+        self.order.push_back((page_id, self.seq));
+        while self.members.len() > self.capacity {
+            self.pop_oldest();
+        }
+        // Stale entries pile up when pages are removed out of order, compact once they dominate the queue
+        if self.order.len() > self.capacity.max(16) * 4 {
+            let members = &self.members;
+            self.order
+                .retain(|(page_id, seq)| members.get(page_id) == Some(seq));
+        }
+    }
+
+    pub(super) fn remove(&mut self, page_id: PageID) -> bool {
+        self.members.remove(&page_id).is_some()
+    }
+
+    pub(super) fn pop_oldest(&mut self) -> Option<PageID> {
+        while let Some((page_id, seq)) = self.order.pop_front() {
+            if self.members.get(&page_id) == Some(&seq) {
+                self.members.remove(&page_id);
+                return Some(page_id);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_list_keeps_recency_order() {
+        let mut list = FrameList::new(4);
+        list.push_front(0);
+        list.push_front(1);
+        list.push_front(2);
+
+        // 0 is the least recently used until it is touched again
+        assert_eq!(list.find_from_back(&|_| true), Some(0));
+        list.push_front(0);
+        assert_eq!(list.find_from_back(&|_| true), Some(1));
+        assert_eq!(list.find_from_back(&|f| f != 1), Some(2));
+
+        assert!(list.remove(1));
+        assert!(!list.remove(1));
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.find_from_back(&|_| true), Some(2));
+    }
+
+    #[test]
+    fn ghost_list_forgets_oldest_beyond_capacity() {
+        let mut ghosts = GhostList::new(2);
+        ghosts.push(PageID(1));
+        ghosts.push(PageID(2));
+        ghosts.push(PageID(3));
+
+        assert!(!ghosts.contains(PageID(1)));
+        assert_eq!(ghosts.len(), 2);
+
+        assert!(ghosts.remove(PageID(2)));
+        assert_eq!(ghosts.pop_oldest(), Some(PageID(3)));
+        assert_eq!(ghosts.pop_oldest(), None);
+    }
+}
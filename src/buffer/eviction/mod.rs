@@ -0,0 +1,72 @@
+// Replacement policies for the buffer pool.
+//
+// The buffer manager owns the frames, the pins and the page table - a policy only keeps whatever ordering or
+// history it needs to rank frames and is told about everything that happens to them:
+//
+//   record_load    a page was read into an empty frame (a miss)
+//   record_access  a resident page was asked for again (a hit)
+//   record_pin     a PinnedPage was handed out for the frame
+//   record_unpin   that PinnedPage was dropped
+//   record_evict   the page in the frame was evicted
+//   remove         the frame was emptied without an eviction (e.g. a failed load)
+//   resize         the pool now uses frames [0, frames) - per-frame state is built for the most the pool can
+//                  ever hold, so only tuning that follows the pool size changes
+//
+// A policy that sweeps the pool in order can report where it is through hand() so the background writer can
+// clean frames just before they are reached.
+//
+// victim() only proposes a frame. The manager still has to win the page table entry and find the frame
+// unpinned, and asks again if it loses, so a policy must not assume its proposal was taken until it hears
+// record_evict. Frames the manager cannot evict right now are filtered out through `is_evictable`.
+
+pub(crate) mod arc;
+pub(crate) mod clock_sweep;
+mod frame_list;
+pub(crate) mod lru_k;
+#[cfg(test)]
+mod trace;
+pub(crate) mod two_q;
+
+use crate::page::PageID;
+use arc::ArcPolicy;
+use clock_sweep::ClockSweep;
+use lru_k::LruK;
+use two_q::TwoQ;
+
+pub(crate) trait EvictionPolicy: Send + Sync {
+    fn record_load(&self, frame: usize, page_id: PageID);
+    fn record_access(&self, frame: usize, page_id: PageID);
+    fn record_pin(&self, _frame: usize) {}
+    fn record_unpin(&self, _frame: usize) {}
+    fn record_evict(&self, frame: usize, page_id: PageID);
+    fn remove(&self, frame: usize);
+    fn victim(&self, is_evictable: &dyn Fn(usize) -> bool) -> Option<usize>;
+    fn hand(&self) -> Option<usize> {
+        None
+    }
+    fn resize(&self, _frames: usize) {}
+    // Rough bytes held, counted against the buffer manager's memory budget
+    fn memory_usage(&self) -> usize;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum EvictionKind {
+    #[default]
+    ClockSweep,
+    LruK {
+        k: usize,
+    },
+    TwoQ,
+    Arc,
+}
+
+impl EvictionKind {
+    pub(crate) fn build(&self, frames: usize) -> Box<dyn EvictionPolicy> {
+        match *self {
+            EvictionKind::ClockSweep => Box::new(ClockSweep::new(frames)),
+            EvictionKind::LruK { k } => Box::new(LruK::new(frames, k)),
+            EvictionKind::TwoQ => Box::new(TwoQ::new(frames)),
+            EvictionKind::Arc => Box::new(ArcPolicy::new(frames)),
+        }
+    }
+}
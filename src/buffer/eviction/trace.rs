@@ -0,0 +1,188 @@
+// Trace replay harness for comparing replacement policies.
+//
+// A trace is a sequence of PageIDs in the order they were asked for. We replay it against a simulated pool -
+// residency bookkeeping only, no pager and no page bytes - driving the policy through the same calls the
+// buffer manager makes, and count hits and misses.
+//
+// Set INKDB_TRACE to a file of whitespace separated page ids to replay a recorded trace alongside the
+// synthetic ones, and INKDB_TRACE_FRAMES to choose the pool size for it.
+
+use crate::buffer::eviction::{EvictionKind, EvictionPolicy};
+use crate::page::PageID;
+use std::collections::HashMap;
+
+const POLICIES: &[EvictionKind] = &[
+    EvictionKind::ClockSweep,
+    EvictionKind::LruK { k: 2 },
+    EvictionKind::TwoQ,
+    EvictionKind::Arc,
+];
+
+#[derive(Debug, Clone, Copy)]
+struct ReplayResult {
+    hits: u64,
+    misses: u64,
+}
+
+impl ReplayResult {
+    fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+fn replay(policy: &dyn EvictionPolicy, frames: usize, trace: &[PageID]) -> ReplayResult {
+    let mut resident: HashMap<PageID, usize> = HashMap::new();
+    let mut frame_pages: Vec<Option<PageID>> = vec![None; frames];
+    let mut free: Vec<usize> = (0..frames).rev().collect();
+    let mut result = ReplayResult { hits: 0, misses: 0 };
+
+    for &page_id in trace {
+        let frame = match resident.get(&page_id) {
+            Some(&frame) => {
+                result.hits += 1;
+                policy.record_access(frame, page_id);
+                frame
+            }
+            None => {
+                result.misses += 1;
+                let frame = match free.pop() {
+                    Some(frame) => frame,
+                    None => {
+                        // Nothing is pinned between accesses in the simulation
+                        let frame = policy
+                            .victim(&|_| true)
+                            .expect("policy found no victim in a full unpinned pool");
+                        let old = frame_pages[frame].take().expect("victim frame was empty");
+                        resident.remove(&old);
+                        policy.record_evict(frame, old);
+                        frame
+                    }
+                };
+                frame_pages[frame] = Some(page_id);
+                resident.insert(page_id, frame);
+                policy.record_load(frame, page_id);
+                frame
+            }
+        };
+
+        // Every access is a pin held for the duration of the access
+        policy.record_pin(frame);
+        policy.record_unpin(frame);
+    }
+
+    result
+}
+
+fn replay_all(name: &str, frames: usize, trace: &[PageID]) -> Vec<(EvictionKind, ReplayResult)> {
+    println!("==============================");
+    println!("trace: {} ({} accesses, {} frames)", name, trace.len(), frames);
+    let results = POLICIES
+        .iter()
+        .map(|kind| {
+            let result = replay(kind.build(frames).as_ref(), frames, trace);
+            println!(
+                "  {:<16} hits {:>7} misses {:>7} hit ratio {:.3}",
+                format!("{:?}", kind),
+                result.hits,
+                result.misses,
+                result.hit_ratio()
+            );
+            (*kind, result)
+        })
+        .collect();
+    println!("==============================");
+    results
+}
+
+fn parse_trace(text: &str) -> Vec<PageID> {
+    text.split_whitespace()
+        .filter_map(|id| id.parse::<u64>().ok())
+        .map(PageID)
+        .collect()
+}
+
+// Small deterministic generator so traces are the same on every run
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+}
+
+// Point lookups on a small hot set with a full sequential scan of cold pages every so often
+fn hot_set_with_scans() -> Vec<PageID> {
+    let mut rng = Lcg(42);
+    let mut trace = Vec::new();
+    for round in 0..20 {
+        for _ in 0..500 {
+            trace.push(PageID(rng.next() % 32));
+        }
+        if round % 4 == 0 {
+            trace.extend((1_000..1_200).map(PageID));
+        }
+    }
+    trace
+}
+
+// Skewed lookups - roughly 80% of accesses go to 20% of the pages
+fn skewed_lookups() -> Vec<PageID> {
+    let mut rng = Lcg(7);
+    (0..20_000)
+        .map(|_| {
+            if rng.next() % 10 < 8 {
+                PageID(rng.next() % 100)
+            } else {
+                PageID(100 + rng.next() % 400)
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn replay_synthetic_traces() {
+    let looping: Vec<PageID> = (0..10).flat_map(|_| (0..48).map(PageID)).collect();
+    for (kind, result) in replay_all("loop within pool", 64, &looping) {
+        // Everything fits so only the first pass misses
+        assert_eq!(result.misses, 48, "{:?}", kind);
+    }
+
+    for (kind, result) in replay_all("hot set with scans", 64, &hot_set_with_scans()) {
+        assert!(result.hit_ratio() > 0.5, "{:?} hit ratio too low", kind);
+    }
+
+    for (kind, result) in replay_all("skewed lookups", 128, &skewed_lookups()) {
+        assert!(result.hit_ratio() > 0.5, "{:?} hit ratio too low", kind);
+    }
+}
+
+#[test]
+fn replay_recorded_trace() {
+    let Ok(path) = std::env::var("INKDB_TRACE") else {
+        return;
+    };
+    let text = std::fs::read_to_string(&path).expect("could not read INKDB_TRACE");
+    let frames = std::env::var("INKDB_TRACE_FRAMES")
+        .ok()
+        .and_then(|f| f.parse().ok())
+        .unwrap_or(128);
+
+    replay_all(&path, frames, &parse_trace(&text));
+}
+
+#[test]
+fn parses_recorded_traces() {
+    assert_eq!(
+        parse_trace("1 2\n3\t40 nope 5"),
+        vec![PageID(1), PageID(2), PageID(3), PageID(40), PageID(5)]
+    );
+}
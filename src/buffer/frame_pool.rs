@@ -0,0 +1,179 @@
+// Frame storage for a pool that can change size while it is running.
+//
+// Frames live in fixed-size chunks which are allocated the first time the pool grows into them, up to a capacity
+// fixed at startup from the memory budget. A frame never moves once its chunk exists, so the `&PageFrame`s the
+// buffer manager hands out (and frame indexes in swizzled pointers, rings and page table entries) stay valid
+// however often the pool is resized.
+//
+// The page bytes themselves live in the frame arena (see arena.rs) - frame i latches page slot i - so chunks
+// only hold frame headers.
+//
+// Only frames [0, active) are in use. Shrinking retires frames from the top down once the buffer manager has
+// emptied them, but their chunks stay allocated and are reused if the pool grows again - a stale frame index
+// (a ring slot, an optimistic reader between peeking an entry and validating it) may still look at a retired
+// frame, and it has to find an empty frame there rather than freed memory. The page memory of retired frames
+// is given back to the OS, the slots stay mapped and read as zeroes.
+
+use crate::buffer::arena::FrameArena;
+use crate::buffer::page_frame::PageFrame;
+use crate::page::PAGE_SIZE;
+use std::ops::Range;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub(super) const CHUNK_FRAMES: usize = 64;
+
+pub(super) struct FramePool {
+    chunks: Box<[OnceLock<Box<[PageFrame]>>]>,
+    // After the chunks so the frames pointing into it go first
+    arena: FrameArena,
+    capacity: usize,
+    active: AtomicUsize,
+}
+
+impl FramePool {
+    pub(super) fn new(active: usize, capacity: usize) -> Self {
+        assert!(
+            active > 0 && active <= capacity,
+            "pool size outside 1..={}",
+            capacity
+        );
+        let pool = Self {
+            chunks: (0..capacity.div_ceil(CHUNK_FRAMES))
+                .map(|_| OnceLock::new())
+                .collect(),
+            arena: FrameArena::new(capacity),
+            capacity,
+            active: AtomicUsize::new(active),
+        };
+        pool.allocate(active);
+        pool
+    }
+
+    // The most frames the pool can ever hold
+    pub(super) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(super) fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    pub(super) fn set_active(&self, frames: usize) {
+        debug_assert!(frames > 0 && frames <= self.allocated());
+        self.active.store(frames, Ordering::Release);
+    }
+
+    // Frames with memory behind them, in use or not
+    pub(super) fn allocated(&self) -> usize {
+        let chunks = self
+            .chunks
+            .iter()
+            .take_while(|chunk| chunk.get().is_some())
+            .count();
+        (chunks * CHUNK_FRAMES).min(self.capacity)
+    }
+
+    // Makes sure frames [0, frames) exist
+    pub(super) fn allocate(&self, frames: usize) {
+        debug_assert!(frames <= self.capacity);
+        for (chunk_idx, chunk) in self
+            .chunks
+            .iter()
+            .enumerate()
+            .take(frames.div_ceil(CHUNK_FRAMES))
+        {
+            chunk.get_or_init(|| {
+                let start = chunk_idx * CHUNK_FRAMES;
+                let len = CHUNK_FRAMES.min(self.capacity - start);
+                // SAFETY: Each chunk is only initialised once so every slot gets exactly one frame, and the
+                // arena is dropped after the chunks
+                (start..start + len)
+                    .map(|frame_idx| unsafe { PageFrame::in_arena(self.arena.page(frame_idx)) })
+                    .collect()
+            });
+        }
+    }
+
+    // Bytes taken up with frames [0, frames) in use - headers come in whole chunks (bar the last one), pages
+    // only for the frames in use
+    pub(super) fn bytes_for(&self, frames: usize) -> usize {
+        let headers = (frames.div_ceil(CHUNK_FRAMES) * CHUNK_FRAMES)
+            .min(self.capacity)
+            .max(self.allocated());
+        headers * size_of::<PageFrame>() + frames * PAGE_SIZE
+    }
+
+    pub(super) fn memory_usage(&self) -> usize {
+        self.bytes_for(self.active())
+    }
+
+    // Hands the page memory of retired frames back, they must already be empty and above `active`
+    pub(super) fn release(&self, frames: Range<usize>) {
+        debug_assert!(frames.start >= self.active());
+        self.arena.release(frames);
+    }
+
+    pub(super) fn get(&self, frame_idx: usize) -> &PageFrame {
+        let chunk = self.chunks[frame_idx / CHUNK_FRAMES]
+            .get()
+            .expect("frame index beyond the allocated pool");
+        &chunk[frame_idx % CHUNK_FRAMES]
+    }
+
+    pub(super) fn index_of(&self, frame: &PageFrame) -> usize {
+        let addr = frame as *const PageFrame as usize;
+        for (chunk_idx, chunk) in self.chunks.iter().enumerate() {
+            let Some(chunk) = chunk.get() else {
+                break;
+            };
+            let start = chunk.as_ptr() as usize;
+            if addr >= start && addr < start + chunk.len() * size_of::<PageFrame>() {
+                return chunk_idx * CHUNK_FRAMES + (addr - start) / size_of::<PageFrame>();
+            }
+        }
+        panic!("frame does not belong to this pool");
+    }
+
+    // Frames in use
+    pub(super) fn iter(&self) -> impl Iterator<Item = &PageFrame> {
+        (0..self.active()).map(|frame_idx| self.get(frame_idx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_keep_their_address_as_the_pool_grows() {
+        let pool = FramePool::new(10, 200);
+        assert_eq!(pool.allocated(), CHUNK_FRAMES);
+
+        let frame = pool.get(5) as *const PageFrame;
+        pool.allocate(150);
+        pool.set_active(150);
+        assert_eq!(pool.allocated(), 3 * CHUNK_FRAMES);
+        assert_eq!(pool.get(5) as *const PageFrame, frame);
+
+        for frame_idx in [0, 63, 64, 149] {
+            assert_eq!(pool.index_of(pool.get(frame_idx)), frame_idx);
+        }
+
+        // Pages sit side by side in the arena, whichever chunk the frame is in
+        let page_addr = |frame_idx: usize| {
+            let mut addr = 0;
+            pool.get(frame_idx)
+                .pin()
+                .read(|page| addr = page.as_ptr() as usize);
+            addr
+        };
+        assert_eq!(page_addr(0) % PAGE_SIZE, 0);
+        assert_eq!(page_addr(149) - page_addr(0), 149 * PAGE_SIZE);
+
+        // The last chunk only goes up to the capacity
+        pool.allocate(200);
+        assert_eq!(pool.allocated(), 200);
+        assert_eq!(pool.index_of(pool.get(199)), 199);
+    }
+}
@@ -0,0 +1,252 @@
+// Hybrid latch for page frames, after the versioned latches in LeanStore and optimistic lock coupling.
+//
+// Three ways in:
+//   exclusive  - writers. The version is bumped to odd when the latch is taken and to the next even value
+//                when it is released, so any optimistic read that overlapped the write sees it moved.
+//   shared     - a normal reader lock for callers that need a stable view for a while
+//   optimistic - no lock at all. Read the version, copy the data out, then check the version did not change.
+//                Nothing is written, so hot pages (internal nodes near the root) can be read by every thread
+//                without their cache lines bouncing between cores.
+//
+// An optimistic reader can overlap a write, so it never hands out a reference to the data itself. The data is
+// copied with volatile reads into a buffer of the reader's own and the copy is only looked at once the version
+// shows no writer was active - a torn copy is thrown away and the caller gets OptimisticRetry instead. The
+// closure therefore always runs on a whole, private copy.
+//
+// The data is normally boxed by the latch itself. Frames in the buffer pool instead latch a page slot in the
+// frame arena (see arena.rs) which the latch only points at.
+
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU64, Ordering, fence};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Returned when an optimistic read overlapped a writer, the caller should retry or fall back to a shared read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OptimisticRetry;
+
+pub(crate) struct HybridLatch<T> {
+    version: AtomicU64,
+    lock: RwLock<()>,
+    data: NonNull<T>,
+    // False when the data belongs to somebody else, see `over`
+    owned: bool,
+}
+
+// SAFETY: Shared and exclusive access to the data is arbitrated by the inner RwLock exactly like RwLock<T>.
+// Optimistic readers never get a reference, they copy the data out and discard the copy unless the version
+// proves no writer was active.
+unsafe impl<T: Send> Send for HybridLatch<T> {}
+unsafe impl<T: Send + Sync> Sync for HybridLatch<T> {}
+
+impl<T> HybridLatch<T> {
+    pub(crate) fn new(data: T) -> Self {
+        Self {
+            version: AtomicU64::new(0),
+            lock: RwLock::new(()),
+            data: NonNull::from(Box::leak(Box::new(data))),
+            owned: true,
+        }
+    }
+
+    // A latch over data it does not own.
+    //
+    // SAFETY: `data` must stay valid for as long as the latch lives and must not be reached other than through
+    // this latch.
+    pub(crate) unsafe fn over(data: NonNull<T>) -> Self {
+        Self {
+            version: AtomicU64::new(0),
+            lock: RwLock::new(()),
+            data,
+            owned: false,
+        }
+    }
+
+    pub(crate) fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn read(&self) -> HybridReadGuard<'_, T> {
+        let lock = self.lock.read().unwrap();
+        HybridReadGuard {
+            _lock: lock,
+            // SAFETY: We hold the shared lock so no writer can be active
+            data: unsafe { self.data.as_ref() },
+        }
+    }
+
+    pub(crate) fn write(&self) -> HybridWriteGuard<'_, T> {
+        let lock = self.lock.write().unwrap();
+        // Odd version - optimistic readers that start now bail out immediately, readers that started before
+        // will see the version moved when they validate. The fence keeps our data writes after the bump.
+        let v = self.version.load(Ordering::Relaxed);
+        self.version.store(v + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        HybridWriteGuard {
+            _lock: lock,
+            latch: self,
+        }
+    }
+
+    // For callers that may already hold this latch further up their own stack and must not wait on it
+    pub(crate) fn try_write(&self) -> Option<HybridWriteGuard<'_, T>> {
+        let lock = self.lock.try_write().ok()?;
+        let v = self.version.load(Ordering::Relaxed);
+        self.version.store(v + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        Some(HybridWriteGuard {
+            _lock: lock,
+            latch: self,
+        })
+    }
+
+    pub(crate) fn read_optimistic<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, OptimisticRetry>
+    where
+        T: Copy,
+    {
+        self.copy_optimistic(|| true).map(|copy| f(&copy))
+    }
+
+    // Copies the data out without taking the latch. `check` runs before validation, for state kept beside the
+    // latch which only changes together with a write (the identity of the page in a frame) - if it says no the
+    // copy is thrown away just like a torn one.
+    pub(crate) fn copy_optimistic(&self, check: impl FnOnce() -> bool) -> Result<T, OptimisticRetry>
+    where
+        T: Copy,
+    {
+        let before = self.version.load(Ordering::Acquire);
+        if before & 1 == 1 {
+            return Err(OptimisticRetry);
+        }
+
+        // SAFETY: The pointer is valid for reads as long as the latch lives. A writer may be changing the data
+        // as we copy it, which is why the copy stays uninitialised until the version below shows there was none.
+        let copy = unsafe { ptr::read_volatile(self.data.as_ptr() as *const MaybeUninit<T>) };
+        let checked = check();
+
+        fence(Ordering::Acquire);
+        if checked && self.version.load(Ordering::Relaxed) == before {
+            // SAFETY: No writer was active while we copied, so the copy is the value the last writer left
+            Ok(unsafe { copy.assume_init() })
+        } else {
+            Err(OptimisticRetry)
+        }
+    }
+}
+
+impl<T> Drop for HybridLatch<T> {
+    fn drop(&mut self) {
+        if self.owned {
+            // SAFETY: Boxed in new, and nobody can hold a guard past the latch
+            drop(unsafe { Box::from_raw(self.data.as_ptr()) });
+        }
+    }
+}
+
+pub(crate) struct HybridReadGuard<'a, T> {
+    _lock: RwLockReadGuard<'a, ()>,
+    data: &'a T,
+}
+
+impl<T> Deref for HybridReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+pub(crate) struct HybridWriteGuard<'a, T> {
+    _lock: RwLockWriteGuard<'a, ()>,
+    latch: &'a HybridLatch<T>,
+}
+
+impl<T> Deref for HybridWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: We hold the exclusive lock
+        unsafe { self.latch.data.as_ref() }
+    }
+}
+
+impl<T> DerefMut for HybridWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: We hold the exclusive lock
+        unsafe { &mut *self.latch.data.as_ptr() }
+    }
+}
+
+impl<T> Drop for HybridWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Back to even, publishing our writes to the next optimistic reader. Runs before the RwLock guard
+        // field is dropped so the next writer can't start until the version is even again.
+        self.latch.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn optimistic_read_validates_against_writers() {
+        let latch = HybridLatch::new([0u8; 64]);
+
+        assert_eq!(latch.read_optimistic(|data| data[0]), Ok(0));
+
+        // A writer holding the latch makes optimistic reads bail straight away
+        let mut w = latch.write();
+        w[0] = 1;
+        assert_eq!(latch.read_optimistic(|data| data[0]), Err(OptimisticRetry));
+        drop(w);
+        assert_eq!(latch.version(), 2);
+
+        // A write that starts and finishes after the copy is caught on validation
+        let result = latch.copy_optimistic(|| {
+            latch.write()[0] = 2;
+            true
+        });
+        assert_eq!(result, Err(OptimisticRetry));
+        assert_eq!(*latch.read(), {
+            let mut expected = [0u8; 64];
+            expected[0] = 2;
+            expected
+        });
+    }
+
+    #[test]
+    fn validated_optimistic_reads_are_never_torn() {
+        let latch = Arc::new(HybridLatch::new([0u8; 4096]));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let writer = {
+            let latch = latch.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let mut value = 0u8;
+                while !stop.load(Ordering::Relaxed) {
+                    value = value.wrapping_add(1);
+                    latch.write().fill(value);
+                    // Leave gaps between writes for readers to validate in
+                    std::thread::yield_now();
+                }
+            })
+        };
+
+        let mut validated = 0;
+        for _ in 0..20_000 {
+            if let Ok(torn) = latch.read_optimistic(|data| data.iter().any(|b| *b != data[0])) {
+                assert!(!torn, "validated read saw a torn page");
+                validated += 1;
+            }
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        writer.join().unwrap();
+        assert!(validated > 0);
+    }
+}
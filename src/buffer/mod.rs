@@ -1,5 +1,16 @@
+pub(super) mod arena;
+pub(super) mod bg_writer;
 pub mod buffer_manager;
+pub(super) mod epoch;
+pub(super) mod eviction;
+pub(super) mod frame_pool;
+pub(super) mod hybrid_latch;
 pub(super) mod page_cache;
 pub(super) mod page_frame;
 pub(super) mod page_table;
 pub(super) mod page_table_latch;
+pub(super) mod pin_tracker;
+pub(super) mod prefetch;
+pub(super) mod stats;
+pub(super) mod strategy;
+pub(super) mod swip;
@@ -6,8 +6,10 @@
 
 //NOTE: We can also further optimise by
 
-use crate::buffer::page_frame::PageFrame;
-use crate::page::{PageID, PageKind};
+use crate::buffer::buffer_manager::{BufferError, BufferManager};
+use crate::buffer::page_frame::{PageFrame, PinnedPage};
+use crate::page::{PageID, PageKind, RawPage, page_kind_of};
+use crate::pager::pager::{Pager, PagerError};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
@@ -16,29 +18,468 @@ pub(crate) type Result<T> = std::result::Result<T, PageCacheError>;
 #[derive(Debug)]
 pub(crate) enum PageCacheError {
     PageAllocationFailed,
+    PageExists(PageID),
+    PagePinned(PageID),
+    Pager(PagerError),
+    Buffer(BufferError),
+}
+
+impl From<PagerError> for PageCacheError {
+    fn from(err: PagerError) -> Self {
+        PageCacheError::Pager(err)
+    }
+}
+
+impl From<BufferError> for PageCacheError {
+    fn from(err: BufferError) -> Self {
+        match err {
+            BufferError::NoFreeFrames => PageCacheError::PageAllocationFailed,
+            BufferError::PageExists(page_id) => PageCacheError::PageExists(page_id),
+            BufferError::PagePinned(page_id) => PageCacheError::PagePinned(page_id),
+            err => PageCacheError::Buffer(err),
+        }
+    }
 }
 
 // Cache owns the lock/access caller owns the result
 //
 // We pass to the closure the raw bytes meaning that only the cache interacts with the page frame and manages the locks, dirty, flags etc
 // it is then the responsibility of the caller to interpret the bytes and use them accordingly
-// FnMut() is used here as it allows mutability within the scope of the closure NOT on the bytes itself which are under their respective lock from the cache
+//
+// Closures return their own Result so the page type layers (IndexPageError etc) can fail through the cache without
+// wrapping - the caller's error type only has to be able to hold a PageCacheError for when the cache itself fails.
+// The methods are generic so the trait is used as a bound (tree code is generic over its cache) rather than as dyn.
 
 pub trait PageCache {
-    fn get(&self, page_id: PageID, f: &mut dyn FnMut(&[u8]));
-    fn fetch(&self, page_id: PageID) -> Result<Arc<PageFrame>>;
-    fn put(&self, page: ()) -> Result<()>;
+    fn read<R, E>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&RawPage) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E>
+    where
+        E: From<PageCacheError>;
+
+    fn write<R, E>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&mut RawPage) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E>
+    where
+        E: From<PageCacheError>;
+
+    fn fetch(&self, page_id: PageID) -> Result<PinnedPage<'_>>;
+
+    // Inserts a page the caller has just built (a split, a new root) and hands it back pinned. It has never been
+    // written so it starts out dirty.
+    fn put(&self, page_id: PageID, kind: PageKind, page: RawPage) -> Result<PinnedPage<'_>>;
+
+    // Drops the page from the cache without writing it out - for pages that are being freed. Fails while anyone
+    // has it pinned.
     fn remove(&self, page_id: PageID) -> Result<()>;
 }
 
+// ---------- Base File Cache ----------//
+
+// The simplest cache over real frames - a fixed pool, no eviction and one lock around the index. Pages are read in
+// on first fetch and stay until removed, running out of frames is PageAllocationFailed. Misses are read while
+// holding the index lock which keeps loads and removals trivially ordered at the cost of serialising them - this
+// is for tests and small tools, the BufferManager is the real thing.
+
 pub struct BaseFileCache {
-    pub cache: Mutex<HashMap<PageID, Arc<()>>>,
+    frames: Box<[PageFrame]>,
+    index: Mutex<HashMap<PageID, usize>>,
+    free: Mutex<Vec<usize>>,
+    pager: Arc<dyn Pager>,
 }
 
 impl BaseFileCache {
-    pub fn new() -> Self {
+    pub(crate) fn new(capacity: usize, pager: Arc<dyn Pager>) -> Self {
         Self {
-            cache: Mutex::new(HashMap::new()),
+            frames: (0..capacity).map(|_| PageFrame::empty()).collect(),
+            index: Mutex::new(HashMap::new()),
+            free: Mutex::new((0..capacity).rev().collect()),
+            pager,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.index.lock().unwrap().len()
+    }
+
+    // Writes out every dirty page and syncs the pager, stopping at the first failure
+    pub(crate) fn flush(&self) -> Result<()> {
+        let resident: Vec<(PageID, usize)> = self
+            .index
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(page_id, frame_idx)| (*page_id, *frame_idx))
+            .collect();
+
+        for (page_id, frame_idx) in resident {
+            let page = self.frames[frame_idx].pin();
+            // Removed (and maybe reused) since we looked
+            if page.page_id() != Some(page_id) || !page.is_dirty() {
+                continue;
+            }
+            let guard = page.read_guard();
+            self.pager.write_page(page_id, &guard)?;
+            page.frame().clear_dirty();
+        }
+        Ok(self.pager.sync()?)
+    }
+
+    fn take_free_frame(&self) -> Result<usize> {
+        self.free
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or(PageCacheError::PageAllocationFailed)
+    }
+}
+
+impl PageCache for BaseFileCache {
+    fn read<R, E>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&RawPage) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E>
+    where
+        E: From<PageCacheError>,
+    {
+        let page = self.fetch(page_id)?;
+        let guard = page.read_guard();
+        f(&guard)
+    }
+
+    fn write<R, E>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&mut RawPage) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E>
+    where
+        E: From<PageCacheError>,
+    {
+        let page = self.fetch(page_id)?;
+        let mut guard = page.write_guard();
+        f(&mut guard)
+    }
+
+    fn fetch(&self, page_id: PageID) -> Result<PinnedPage<'_>> {
+        let mut index = self.index.lock().unwrap();
+
+        // Pinned under the index lock so remove can't slip in between the lookup and the pin
+        if let Some(&frame_idx) = index.get(&page_id) {
+            return Ok(self.frames[frame_idx].pin());
+        }
+
+        let frame_idx = self.take_free_frame()?;
+        let frame = &self.frames[frame_idx];
+        let page = frame.pin();
+
+        let loaded = {
+            let mut guard = page.write_guard();
+            self.pager
+                .read_page(page_id, &mut guard)
+                .map(|_| page_kind_of(&guard))
+        };
+
+        match loaded {
+            Ok(kind) => {
+                // Matches disk, the write guard only marked it dirty because we read into it
+                frame.clear_dirty();
+                frame.set_page(page_id, kind);
+                index.insert(page_id, frame_idx);
+                Ok(page)
+            }
+            Err(err) => {
+                drop(page);
+                frame.clear_page();
+                self.free.lock().unwrap().push(frame_idx);
+                Err(err.into())
+            }
+        }
+    }
+
+    fn put(&self, page_id: PageID, kind: PageKind, bytes: RawPage) -> Result<PinnedPage<'_>> {
+        let mut index = self.index.lock().unwrap();
+        if index.contains_key(&page_id) {
+            return Err(PageCacheError::PageExists(page_id));
+        }
+
+        let frame_idx = self.take_free_frame()?;
+        let frame = &self.frames[frame_idx];
+        let page = frame.pin();
+        page.write(|raw| raw.copy_from_slice(&bytes));
+        frame.set_page(page_id, kind);
+        index.insert(page_id, frame_idx);
+        Ok(page)
+    }
+
+    fn remove(&self, page_id: PageID) -> Result<()> {
+        let mut index = self.index.lock().unwrap();
+        let Some(&frame_idx) = index.get(&page_id) else {
+            return Ok(());
+        };
+
+        // Pins are only taken under the index lock so nobody can pin it between this check and the clear
+        let frame = &self.frames[frame_idx];
+        if frame.is_pinned() {
+            return Err(PageCacheError::PagePinned(page_id));
+        }
+
+        index.remove(&page_id);
+        frame.clear_page();
+        self.free.lock().unwrap().push(frame_idx);
+        Ok(())
+    }
+}
+
+// ---------- Buffer Manager ----------//
+
+impl PageCache for BufferManager {
+    #[track_caller]
+    fn read<R, E>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&RawPage) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E>
+    where
+        E: From<PageCacheError>,
+    {
+        let page = PageCache::fetch(self, page_id)?;
+        let guard = page.read_guard();
+        // Readers get page ids too - a swizzled pointer means nothing outside fetch_child
+        if page.frame().swizzled_children() > 0 {
+            return f(&self.unswizzled_copy(&guard));
+        }
+        f(&guard)
+    }
+
+    #[track_caller]
+    fn write<R, E>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&mut RawPage) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E>
+    where
+        E: From<PageCacheError>,
+    {
+        let page = PageCache::fetch(self, page_id)?;
+        let mut guard = page.write_guard();
+        f(&mut guard)
+    }
+
+    #[track_caller]
+    fn fetch(&self, page_id: PageID) -> Result<PinnedPage<'_>> {
+        Ok(self.fetch_page(page_id)?)
+    }
+
+    #[track_caller]
+    fn put(&self, page_id: PageID, kind: PageKind, page: RawPage) -> Result<PinnedPage<'_>> {
+        Ok(self.create_page(page_id, kind, &page)?)
+    }
+
+    fn remove(&self, page_id: PageID) -> Result<()> {
+        Ok(self.discard_page(page_id)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::buffer_manager::BufferConfig;
+    use crate::page::internal_page::{IndexCellOwned, IndexPageError, IndexPageMut, IndexPageRef};
+    use crate::page::{SlottedPageMut, SlottedPageRef};
+    use crate::pager::pager;
+
+    struct MapPager {
+        pages: Mutex<HashMap<PageID, RawPage>>,
+    }
+
+    impl Pager for MapPager {
+        fn read_page(&self, page_id: PageID, buf: &mut RawPage) -> pager::Result<()> {
+            let pages = self.pages.lock().unwrap();
+            let page = pages.get(&page_id).ok_or(PagerError::ReadFailed(page_id))?;
+            buf.copy_from_slice(page);
+            Ok(())
+        }
+
+        fn write_page(&self, page_id: PageID, buf: &RawPage) -> pager::Result<()> {
+            self.pages.lock().unwrap().insert(page_id, *buf);
+            Ok(())
+        }
+
+        fn allocate_page(&self) -> pager::Result<PageID> {
+            let mut pages = self.pages.lock().unwrap();
+            let page_id = PageID(pages.len() as u64);
+            pages.insert(page_id, [0u8; 4096]);
+            Ok(page_id)
+        }
+
+        fn page_count(&self) -> u64 {
+            self.pages.lock().unwrap().len() as u64
+        }
+
+        fn sync(&self) -> pager::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn pager() -> Arc<MapPager> {
+        Arc::new(MapPager {
+            pages: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // What tree code looks like against the trait - its own error type with room for the cache's
+    #[derive(Debug)]
+    enum TreeError {
+        Cache(PageCacheError),
+        Index(IndexPageError),
+    }
+
+    impl From<PageCacheError> for TreeError {
+        fn from(err: PageCacheError) -> Self {
+            TreeError::Cache(err)
+        }
+    }
+
+    impl From<IndexPageError> for TreeError {
+        fn from(err: IndexPageError) -> Self {
+            TreeError::Index(err)
+        }
+    }
+
+    fn new_internal_page(children: &[(&[u8], u64)]) -> RawPage {
+        let mut raw = [0u8; 4096];
+        let mut index = IndexPageMut::from_slotted_page(SlottedPageMut::init_new(
+            &mut raw,
+            PageKind::IndexInternal.into(),
+        ));
+        index.init_in_place(0).unwrap();
+        for (key, child) in children {
+            index
+                .add_cell_append_slot_entry(IndexCellOwned::new(key, PageID(*child)))
+                .unwrap();
+        }
+        raw
+    }
+
+    fn find_child<C: PageCache>(
+        cache: &C,
+        page_id: PageID,
+        key: &[u8],
+    ) -> std::result::Result<Option<PageID>, TreeError> {
+        cache.read(page_id, |bytes| {
+            Ok(
+                IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(bytes))
+                    .find_child_ptr(key)?,
+            )
+        })
+    }
+
+    fn add_child<C: PageCache>(
+        cache: &C,
+        page_id: PageID,
+        key: &[u8],
+        child: u64,
+    ) -> std::result::Result<(), TreeError> {
+        cache.write(page_id, |bytes| {
+            let mut index = IndexPageMut::from_slotted_page(SlottedPageMut::from_bytes(bytes));
+            Ok(index.add_cell_append_slot_entry(IndexCellOwned::new(key, PageID(child)))?)
+        })
+    }
+
+    // Runs the same tree-style operations against any cache
+    fn exercise<C: PageCache>(cache: &C) {
+        let root = cache
+            .put(
+                PageID(1),
+                PageKind::IndexInternal,
+                new_internal_page(&[(b"m", 2)]),
+            )
+            .unwrap();
+        assert!(root.is_dirty());
+        assert!(matches!(
+            cache.put(PageID(1), PageKind::IndexInternal, [0u8; 4096]),
+            Err(PageCacheError::PageExists(PageID(1)))
+        ));
+
+        assert_eq!(find_child(cache, PageID(1), b"a").unwrap(), Some(PageID(2)));
+        add_child(cache, PageID(1), b"z", 3).unwrap();
+        assert_eq!(find_child(cache, PageID(1), b"q").unwrap(), Some(PageID(3)));
+
+        // Errors from the closure and from the cache both come back through the caller's type
+        let err = cache.read(PageID(1), |_| {
+            Err::<(), _>(TreeError::Index(IndexPageError::InvalidLevel))
+        });
+        assert!(matches!(
+            err,
+            Err(TreeError::Index(IndexPageError::InvalidLevel))
+        ));
+        assert!(matches!(
+            find_child(cache, PageID(99), b"a"),
+            Err(TreeError::Cache(_))
+        ));
+
+        // Can't remove what somebody is still using
+        assert!(matches!(
+            cache.remove(PageID(1)),
+            Err(PageCacheError::PagePinned(PageID(1)))
+        ));
+        drop(root);
+        cache.remove(PageID(1)).unwrap();
+
+        // Removed without being written so the id is free to be put again
+        cache
+            .put(PageID(1), PageKind::IndexInternal, new_internal_page(&[]))
+            .unwrap();
+        assert_eq!(find_child(cache, PageID(1), b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn base_file_cache_implements_page_cache() {
+        let pager = pager();
+        let cache = BaseFileCache::new(2, pager.clone());
+        exercise(&cache);
+        assert_eq!(cache.len(), 1);
+
+        cache.flush().unwrap();
+        assert!(pager.pages.lock().unwrap().contains_key(&PageID(1)));
+
+        // No eviction, the third page has nowhere to go
+        cache
+            .put(PageID(2), PageKind::IndexInternal, new_internal_page(&[]))
+            .unwrap();
+        assert!(matches!(
+            cache.put(PageID(3), PageKind::IndexInternal, new_internal_page(&[])),
+            Err(PageCacheError::PageAllocationFailed)
+        ));
+
+        // Pages come back from the pager once removed
+        cache.remove(PageID(1)).unwrap();
+        assert_eq!(find_child(&cache, PageID(1), b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn buffer_manager_implements_page_cache() {
+        let config = BufferConfig {
+            pool_size: 4,
+            ..Default::default()
+        };
+        let bm = BufferManager::with_config(config, pager());
+        exercise(&bm);
+
+        // A page created in the pool survives eviction - it was dirty so it went to the pager on the way out
+        for id in 10..20 {
+            bm.put(
+                PageID(id),
+                PageKind::IndexInternal,
+                new_internal_page(&[(b"k", id)]),
+            )
+            .unwrap();
         }
+        assert_eq!(find_child(&bm, PageID(10), b"a").unwrap(), Some(PageID(10)));
     }
 }
@@ -1,40 +1,311 @@
-use crate::page::{PageKind, RawPage};
+use crate::buffer::eviction::EvictionPolicy;
+use crate::buffer::frame_pool::FramePool;
+use crate::buffer::hybrid_latch::{
+    HybridLatch, HybridReadGuard, HybridWriteGuard, OptimisticRetry,
+};
+use crate::buffer::pin_tracker::{PinToken, PinTracker};
+use crate::buffer::swip::Swip;
+use crate::page::internal_page::{IndexPageMut, IndexPageRef};
+use crate::page::{PageID, PageKind, RawPage};
 use crate::page::{SlottedPageMut, SlottedPageRef};
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicBool, AtomicU16};
-use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::ptr::NonNull;
+use std::sync::atomic::{
+    AtomicBool, AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering,
+};
 
-pub(super) type Result<T> = std::result::Result<T, PageFrameError>;
+pub(crate) type Result<T> = std::result::Result<T, PageFrameError>;
 
-pub(super) enum PageFrameError {
+pub(crate) enum PageFrameError {
     InvalidPageKind,
 }
 
+// Frames are reused for different pages over their lifetime so the identity of the page they currently
+// hold is kept in atomics which the buffer manager swaps when a frame is loaded or evicted.
+const NO_PAGE: u64 = u64::MAX;
+const NO_PARENT: usize = usize::MAX;
+
 pub(crate) struct PageFrame {
     checksum: u32,
-    kind: PageKind,
+    page_id: AtomicU64,
+    kind: AtomicU8,
     dirty: AtomicBool,
-    latch: RwLock<RawPage>,
+    // Loaded by the prefetcher and not fetched by anyone since
+    prefetched: AtomicBool,
+    latch: HybridLatch<RawPage>,
     pin: AtomicU16,
+    // Swizzling bookkeeping - the frame of the parent holding a swizzled pointer to us, and how many of our own
+    // child pointers are swizzled. Both are SeqCst for the same reason as pins, the evictor checks them after
+    // moving the entry to evicting.
+    parent: AtomicUsize,
+    swizzled_children: AtomicU32,
 }
 
 impl PageFrame {
     pub(crate) fn new(checksum: u32, kind: PageKind, raw_page: RawPage) -> Self {
+        Self::with_latch(checksum, kind, HybridLatch::new(raw_page))
+    }
+
+    // An empty frame over a page slot in the frame arena, which is where every frame of the buffer pool keeps
+    // its page (see arena.rs)
+    //
+    // SAFETY: The slot has to outlive the frame and belong to no other frame
+    pub(super) unsafe fn in_arena(page: NonNull<RawPage>) -> Self {
+        Self::with_latch(0, PageKind::Undefined, unsafe { HybridLatch::over(page) })
+    }
+
+    fn with_latch(checksum: u32, kind: PageKind, latch: HybridLatch<RawPage>) -> Self {
         Self {
             checksum,
-            kind,
+            page_id: AtomicU64::new(NO_PAGE),
+            kind: AtomicU8::new(kind.into()),
             dirty: AtomicBool::new(false),
-            latch: RwLock::new(raw_page),
+            prefetched: AtomicBool::new(false),
+            latch,
             pin: AtomicU16::new(0),
+            parent: AtomicUsize::new(NO_PARENT),
+            swizzled_children: AtomicU32::new(0),
+        }
+    }
+
+    // An empty frame for the buffer pool which does not hold any page yet
+    pub(crate) fn empty() -> Self {
+        Self::new(0, PageKind::Undefined, [0u8; 4096])
+    }
+
+    pub(crate) fn page_id(&self) -> Option<PageID> {
+        match self.page_id.load(Ordering::Acquire) {
+            NO_PAGE => None,
+            id => Some(PageID(id)),
+        }
+    }
+
+    pub(crate) fn kind(&self) -> PageKind {
+        PageKind::from_u8(self.kind.load(Ordering::Acquire)).unwrap_or(PageKind::Undefined)
+    }
+
+    // Only the buffer manager assigns identities, and only while it holds the frame pinned and the page
+    // table entry for the page in a non in-memory state so no other thread can validate against the frame.
+    pub(super) fn set_page(&self, page_id: PageID, kind: PageKind) {
+        self.kind.store(kind.into(), Ordering::Release);
+        self.page_id.store(page_id.into(), Ordering::Release);
+    }
+
+    pub(super) fn clear_page(&self) {
+        debug_assert_eq!(
+            self.parent(),
+            None,
+            "clearing a frame that is still swizzled"
+        );
+        debug_assert_eq!(
+            self.swizzled_children(),
+            0,
+            "clearing a frame with swizzled children"
+        );
+        self.page_id.store(NO_PAGE, Ordering::Release);
+        self.kind
+            .store(PageKind::Undefined.into(), Ordering::Release);
+        self.dirty.store(false, Ordering::Release);
+        self.prefetched.store(false, Ordering::Release);
+    }
+
+    pub(super) fn set_prefetched(&self) {
+        self.prefetched.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_prefetched(&self) -> bool {
+        self.prefetched.load(Ordering::Acquire)
+    }
+
+    // Returns whether the flag was set so exactly one fetch (or the eviction) gets to count it
+    pub(super) fn take_prefetched(&self) -> bool {
+        self.prefetched.swap(false, Ordering::AcqRel)
+    }
+
+    pub(crate) fn parent(&self) -> Option<usize> {
+        match self.parent.load(Ordering::SeqCst) {
+            NO_PARENT => None,
+            frame_idx => Some(frame_idx),
         }
     }
 
-    pub(crate) fn read_guard(&self) -> FrameReadGuard {
-        FrameReadGuard::new(self.latch.read().unwrap(), self.kind.clone())
+    // Parent bookkeeping is only changed while holding the parent's exclusive latch
+    pub(super) fn set_parent(&self, parent: Option<usize>) {
+        self.parent
+            .store(parent.unwrap_or(NO_PARENT), Ordering::SeqCst);
+    }
+
+    pub(crate) fn swizzled_children(&self) -> u32 {
+        self.swizzled_children.load(Ordering::SeqCst)
     }
 
-    pub(crate) fn write_guard(&self) -> FrameWriteGuard {
-        FrameWriteGuard::new(self.latch.write().unwrap(), self.kind.clone())
+    pub(super) fn add_swizzled_child(&self) {
+        self.swizzled_children.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn remove_swizzled_child(&self) {
+        let prev = self.swizzled_children.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(prev > 0, "no swizzled child to remove");
+    }
+
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    pub(super) fn clear_dirty(&self) {
+        self.dirty.store(false, Ordering::Release);
+    }
+
+    // Pinning is separate from latching - a pin only says the frame must stay resident and hold the same page,
+    // it says nothing about who may read or write the bytes. The latch is only taken through a PinnedPage so
+    // that a caller can never hold a guard on a frame which the buffer pool is free to reuse.
+    //
+    // Pins use SeqCst because they pair with the page table latch state: a reader pins and then checks the
+    // entry is still in-memory, while an evictor moves the entry out of in-memory and then checks the pin
+    // count. With a single total order at least one of them is guaranteed to see the other.
+    pub(crate) fn pin(&self) -> PinnedPage<'_> {
+        let prev = self.pin.fetch_add(1, Ordering::SeqCst);
+        debug_assert!(prev < u16::MAX, "pin count overflow");
+        PinnedPage {
+            frame: self,
+            policy: None,
+            tracked: None,
+            frames: None,
+        }
+    }
+
+    fn unpin(&self) {
+        let prev = self.pin.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(prev > 0, "unpin on a frame that is not pinned");
+    }
+
+    pub(crate) fn pin_count(&self) -> u16 {
+        self.pin.load(Ordering::SeqCst)
+    }
+
+    // Eviction must skip any frame for which this is true
+    pub(crate) fn is_pinned(&self) -> bool {
+        self.pin_count() > 0
+    }
+
+    fn read_guard(&self) -> FrameReadGuard<'_> {
+        FrameReadGuard::new(self.latch.read(), self.kind())
+    }
+
+    // Taking the exclusive latch is what marks a frame dirty - we assume anyone asking to write does so. The
+    // flag is set while we hold the latch so a flush, which holds the shared latch while writing and clearing,
+    // can never clear it over a change it did not write out.
+    fn write_guard(&self) -> FrameWriteGuard<'_> {
+        let page = self.latch.write();
+        self.mark_dirty();
+        FrameWriteGuard::new(page, self.kind())
+    }
+
+    // Swizzling rewrites child pointers in place but the page is logically unchanged (write-back unswizzles
+    // before writing), so unlike write_guard this does not mark the frame dirty. Only try - swizzling is an
+    // optimisation and the caller may be evicting on behalf of a thread that already holds this latch.
+    pub(super) fn try_swizzle_guard(&self) -> Option<FrameWriteGuard<'_>> {
+        Some(FrameWriteGuard::new(self.latch.try_write()?, self.kind()))
+    }
+
+    // Reads the page without a pin or the latch, for hot pages where even the pin count is a contended cache
+    // line. Loading a different page into the frame goes through the exclusive latch and so moves the version,
+    // which together with checking the identity before the copy is validated means a validated copy really came
+    // from `page_id`. Nothing stops the frame being evicted meanwhile - we just retry.
+    pub(crate) fn read_optimistic<R>(
+        &self,
+        page_id: PageID,
+        f: impl FnOnce(&RawPage) -> R,
+    ) -> std::result::Result<R, OptimisticRetry> {
+        self.copy_optimistic(page_id).map(|page| f(&page))
+    }
+
+    // The validated copy read_optimistic runs its closure on, for callers that want to leave whatever keeps the
+    // frame alive before looking at it
+    pub(super) fn copy_optimistic(
+        &self,
+        page_id: PageID,
+    ) -> std::result::Result<RawPage, OptimisticRetry> {
+        self.latch
+            .copy_optimistic(|| self.page_id() == Some(page_id))
+    }
+}
+
+// ---------- Pinned Page ----------//
+
+// RAII pin over a frame. The pin is taken on creation and released on drop, so as long as a PinnedPage
+// is alive the frame cannot be chosen as a victim. Fetch APIs hand these out and all access to the page
+// bytes (and the slotted views on top of them) goes through the guards obtained from here.
+
+pub(crate) struct PinnedPage<'a> {
+    frame: &'a PageFrame,
+    // Set when the buffer manager hands the pin out so the replacement policy hears about the unpin
+    policy: Option<(&'a dyn EvictionPolicy, usize)>,
+    // Set when the pin is recorded for leak detection, see pin_tracker.rs
+    tracked: Option<(&'a PinTracker, PinToken)>,
+    // Set when the pin comes from a pool that swizzles, so a writer can turn our swizzled children back into
+    // page ids before it sees the bytes
+    frames: Option<&'a FramePool>,
+}
+
+impl<'a> PinnedPage<'a> {
+    pub(super) fn with_policy(mut self, policy: &'a dyn EvictionPolicy, frame_idx: usize) -> Self {
+        policy.record_pin(frame_idx);
+        self.policy = Some((policy, frame_idx));
+        self
+    }
+
+    #[track_caller]
+    pub(super) fn tracked(mut self, tracker: &'a PinTracker, frame_idx: usize) -> Self {
+        self.tracked = Some((tracker, tracker.acquire(frame_idx, self.frame.page_id())));
+        self
+    }
+
+    pub(super) fn with_frames(mut self, frames: &'a FramePool) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    pub(crate) fn read_guard(&self) -> FrameReadGuard<'_> {
+        self.frame.read_guard()
+    }
+
+    // Writers only ever see page ids in child pointers - a split or a cell move would otherwise carry frame
+    // indexes into another page and leave the children's parent links pointing at this one
+    pub(crate) fn write_guard(&self) -> FrameWriteGuard<'_> {
+        let mut guard = self.frame.write_guard();
+        if let Some(frames) = self.frames
+            && self.frame.swizzled_children() > 0
+        {
+            self.unswizzle_children(&mut guard, frames);
+        }
+        guard
+    }
+
+    // We hold the exclusive latch, which is what every swizzle and unswizzle of our children takes, and a child
+    // can't be evicted while it is swizzled - so each frame we find still holds the page it points at
+    fn unswizzle_children(&self, guard: &mut FrameWriteGuard<'_>, frames: &FramePool) {
+        let slots =
+            IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(guard)).child_slots();
+        let Ok(page) = guard.slotted_mut() else {
+            return;
+        };
+        let mut index = IndexPageMut::from_slotted_page(page);
+        for (slot_id, child_ptr) in slots {
+            if let Swip::Frame(child_idx) = Swip::decode(child_ptr) {
+                let child = frames.get(child_idx);
+                if let Some(child_id) = child.page_id()
+                    && index.set_child_ptr(slot_id, child_id).is_ok()
+                {
+                    child.set_parent(None);
+                    self.frame.remove_swizzled_child();
+                }
+            }
+        }
     }
 
     pub(crate) fn read<F>(&self, f: F)
@@ -52,17 +323,54 @@ impl PageFrame {
         let mut w = self.write_guard();
         f(w.raw());
     }
+
+    // The pin keeps the page in the frame so only concurrent writers can fail the read. Callers that get
+    // OptimisticRetry back either try again or fall back to read().
+    pub(crate) fn read_optimistic<R>(
+        &self,
+        f: impl FnOnce(&RawPage) -> R,
+    ) -> std::result::Result<R, OptimisticRetry> {
+        self.frame.latch.read_optimistic(f)
+    }
+
+    pub(crate) fn kind(&self) -> PageKind {
+        self.frame.kind()
+    }
+
+    pub(crate) fn page_id(&self) -> Option<PageID> {
+        self.frame.page_id()
+    }
+
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.frame.is_dirty()
+    }
+
+    pub(super) fn frame(&self) -> &'a PageFrame {
+        self.frame
+    }
+}
+
+impl Drop for PinnedPage<'_> {
+    fn drop(&mut self) {
+        self.frame.unpin();
+        if let Some((policy, frame_idx)) = self.policy {
+            policy.record_unpin(frame_idx);
+        }
+        if let Some((tracker, token)) = self.tracked.take() {
+            tracker.release(token);
+        }
+    }
 }
 
 // Need read and write guards to return slotted page views
 
-pub(super) struct FrameReadGuard<'a> {
-    page: RwLockReadGuard<'a, RawPage>,
+pub(crate) struct FrameReadGuard<'a> {
+    page: HybridReadGuard<'a, RawPage>,
     kind: PageKind,
 }
 
 impl<'a> FrameReadGuard<'a> {
-    fn new(page: RwLockReadGuard<'a, RawPage>, kind: PageKind) -> Self {
+    fn new(page: HybridReadGuard<'a, RawPage>, kind: PageKind) -> Self {
         Self { page, kind }
     }
 
@@ -87,13 +395,13 @@ impl<'a> Deref for FrameReadGuard<'a> {
     }
 }
 
-pub(super) struct FrameWriteGuard<'a> {
-    page: RwLockWriteGuard<'a, RawPage>,
+pub(crate) struct FrameWriteGuard<'a> {
+    page: HybridWriteGuard<'a, RawPage>,
     kind: PageKind,
 }
 
 impl<'a> FrameWriteGuard<'a> {
-    fn new(page: RwLockWriteGuard<'a, RawPage>, kind: PageKind) -> Self {
+    fn new(page: HybridWriteGuard<'a, RawPage>, kind: PageKind) -> Self {
         Self { page, kind }
     }
 
@@ -142,9 +450,79 @@ mod tests {
 
         // We take a read only view of the page inside the frame
 
-        frame.read(|rp| {
+        frame.pin().read(|rp| {
             let ref_guard = IndexPageRef::from_slotted_page(SlottedPageRef::from_bytes(rp));
             println!("Page Kind {:?}", ref_guard.kind())
         });
     }
+
+    #[test]
+    fn pinned_page_releases_pin_on_drop() {
+        let frame = PageFrame::new(0, PageKind::IndexInternal, [0u8; 4096]);
+        assert!(!frame.is_pinned());
+
+        {
+            let first = frame.pin();
+            let second = frame.pin();
+            assert_eq!(frame.pin_count(), 2);
+
+            // Guards borrow from the pin so the pin must outlive them
+            let guard = first.read_guard();
+            assert!(guard.slotted_ref().is_ok());
+            drop(guard);
+
+            drop(second);
+            assert_eq!(frame.pin_count(), 1);
+        }
+
+        assert_eq!(frame.pin_count(), 0);
+        assert!(!frame.is_pinned());
+    }
+
+    #[test]
+    fn write_guard_marks_frame_dirty() {
+        let frame = PageFrame::new(0, PageKind::IndexLeaf, [0u8; 4096]);
+        let page = frame.pin();
+
+        drop(page.read_guard());
+        assert!(!page.is_dirty());
+
+        page.write(|bytes| bytes[100] = 1);
+        assert!(page.is_dirty());
+
+        frame.clear_dirty();
+        assert!(!page.is_dirty());
+    }
+
+    #[test]
+    fn optimistic_reads_check_the_frame_still_holds_the_page() {
+        let frame = PageFrame::empty();
+        frame.set_page(PageID(7), PageKind::IndexInternal);
+
+        let page = frame.pin();
+        page.write(|bytes| bytes[100] = 3);
+        assert_eq!(page.read_optimistic(|bytes| bytes[100]), Ok(3));
+        assert_eq!(frame.read_optimistic(PageID(7), |bytes| bytes[100]), Ok(3));
+
+        // Asking for a page the frame does not hold, or a frame that was emptied, is never validated
+        assert_eq!(
+            frame.read_optimistic(PageID(8), |bytes| bytes[100]),
+            Err(OptimisticRetry)
+        );
+        frame.clear_page();
+        assert_eq!(
+            frame.read_optimistic(PageID(7), |bytes| bytes[100]),
+            Err(OptimisticRetry)
+        );
+
+        // Nor is one a writer holds
+        frame.set_page(PageID(7), PageKind::IndexInternal);
+        let guard = page.write_guard();
+        assert_eq!(
+            frame.read_optimistic(PageID(7), |bytes| bytes[100]),
+            Err(OptimisticRetry)
+        );
+        drop(guard);
+        assert_eq!(frame.read_optimistic(PageID(7), |bytes| bytes[100]), Ok(3));
+    }
 }
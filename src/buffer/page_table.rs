@@ -31,17 +31,55 @@
 //
 // Future implementations can be sharded hash table which will still implement the PageTable trait. Further to this, we can optimize page handle structures
 // to be more memory efficient as well as making latches smaller and faster
+//
+// ShardedMappingTable below is the first of those - the same map split over independently locked shards so
+// lookups and inserts on different pages stop contending on one RwLock. NaiveMappingTable stays as the
+// reference implementation.
 
-use crate::buffer::page_table_latch::PageTableLatch;
+use crate::buffer::page_table_latch::{PT_ON_DISK, PageTableLatch};
 use crate::page::PageID;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 // ---------- PageTable Trait ----------//
 
-pub(crate) trait PageTable {
+pub(crate) trait PageTable: Send + Sync {
     fn get(&self, page_id: PageID) -> Option<PageTableHandle>; // We return a handle here so the buffer manager can load from disk and flip the state and change the frame address
     fn insert(&self, page_id: PageID, entry: PageTableHandle);
+    // Must be atomic - two threads missing on the same page have to end up racing on the same latch, otherwise
+    // both would load the page into different frames
+    fn get_or_insert(&self, page_id: PageID) -> PageTableHandle;
+    // Only drops the table's reference - threads already holding the handle keep using it, so the caller must
+    // make sure nobody can still load the page through a removed entry
+    fn remove(&self, page_id: PageID) -> Option<PageTableHandle>;
+    // Removes the entry only if it is on disk and nobody but the table holds a handle to it. Checked under the
+    // same lock every handle is cloned under, so nobody can pick the entry up in between and go on to load the
+    // page through it while somebody else loads it through a new one. Returns whether it was removed.
+    fn remove_idle(&self, page_id: PageID) -> bool;
+    fn len(&self) -> usize;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PageTableKind {
+    Naive,
+    Sharded { shards: usize },
+}
+
+impl Default for PageTableKind {
+    fn default() -> Self {
+        PageTableKind::Sharded {
+            shards: DEFAULT_SHARDS,
+        }
+    }
+}
+
+impl PageTableKind {
+    pub(crate) fn build(&self) -> Box<dyn PageTable> {
+        match *self {
+            PageTableKind::Naive => Box::new(NaiveMappingTable::new()),
+            PageTableKind::Sharded { shards } => Box::new(ShardedMappingTable::new(shards)),
+        }
+    }
 }
 
 // For now we return a PageTableHandle because hash tables can move entries around so we need to be able to be sure where our entries are located and not give out references to them as they can move moved so
@@ -65,13 +103,27 @@ impl PageTableEntry {
             state: PageTableLatch::new(PageTableResult::Disk(id.to_offset())),
         }
     }
+
+    pub(super) fn latch(&self) -> &PageTableLatch<PageTableResult> {
+        &self.state
+    }
 }
 
 pub(crate) type PageTableHandle = Arc<PageTableEntry>;
 
+fn remove_if_idle(map: &mut HashMap<PageID, PageTableHandle>, page_id: PageID) -> bool {
+    let idle = map
+        .get(&page_id)
+        .is_some_and(|entry| Arc::strong_count(entry) == 1 && entry.latch().state() == PT_ON_DISK);
+    if idle {
+        map.remove(&page_id);
+    }
+    idle
+}
+
 // --------------- Naive Implementation ------------ //
 
-struct NaiveMappingTable {
+pub(crate) struct NaiveMappingTable {
     map: Arc<RwLock<HashMap<PageID, PageTableHandle>>>,
 }
 
@@ -90,6 +142,96 @@ impl PageTable for NaiveMappingTable {
     fn insert(&self, page_id: PageID, entry: PageTableHandle) {
         self.map.write().unwrap().insert(page_id, entry);
     }
+    fn get_or_insert(&self, page_id: PageID) -> PageTableHandle {
+        if let Some(entry) = self.get(page_id) {
+            return entry;
+        }
+        self.map
+            .write()
+            .unwrap()
+            .entry(page_id)
+            .or_insert_with(|| Arc::new(PageTableEntry::new(page_id)))
+            .clone()
+    }
+    fn remove(&self, page_id: PageID) -> Option<PageTableHandle> {
+        self.map.write().unwrap().remove(&page_id)
+    }
+    fn remove_idle(&self, page_id: PageID) -> bool {
+        remove_if_idle(&mut self.map.write().unwrap(), page_id)
+    }
+    fn len(&self) -> usize {
+        self.map.read().unwrap().len()
+    }
+}
+
+// --------------- Sharded Implementation ------------ //
+
+const DEFAULT_SHARDS: usize = 64;
+
+// Each shard sits on its own cache line so taking one shard's lock does not bounce the lines of its neighbours
+#[repr(align(64))]
+struct Shard {
+    map: RwLock<HashMap<PageID, PageTableHandle>>,
+}
+
+pub(crate) struct ShardedMappingTable {
+    shards: Box<[Shard]>,
+    shift: u32,
+}
+
+impl ShardedMappingTable {
+    pub(crate) fn new(shards: usize) -> Self {
+        let shards = shards.max(1).next_power_of_two();
+        ShardedMappingTable {
+            shards: (0..shards)
+                .map(|_| Shard {
+                    map: RwLock::new(HashMap::new()),
+                })
+                .collect(),
+            shift: u64::BITS - shards.trailing_zeros(),
+        }
+    }
+
+    // PageIDs are mostly sequential so we spread them with a fibonacci hash and take the top bits
+    #[inline]
+    fn shard(&self, page_id: PageID) -> &RwLock<HashMap<PageID, PageTableHandle>> {
+        let hash = page_id.into().wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let idx = hash.checked_shr(self.shift).unwrap_or(0) as usize;
+        &self.shards[idx].map
+    }
+}
+
+impl PageTable for ShardedMappingTable {
+    fn get(&self, page_id: PageID) -> Option<PageTableHandle> {
+        self.shard(page_id).read().unwrap().get(&page_id).cloned()
+    }
+    fn insert(&self, page_id: PageID, entry: PageTableHandle) {
+        self.shard(page_id).write().unwrap().insert(page_id, entry);
+    }
+    fn get_or_insert(&self, page_id: PageID) -> PageTableHandle {
+        let shard = self.shard(page_id);
+        if let Some(entry) = shard.read().unwrap().get(&page_id) {
+            return entry.clone();
+        }
+        shard
+            .write()
+            .unwrap()
+            .entry(page_id)
+            .or_insert_with(|| Arc::new(PageTableEntry::new(page_id)))
+            .clone()
+    }
+    fn remove(&self, page_id: PageID) -> Option<PageTableHandle> {
+        self.shard(page_id).write().unwrap().remove(&page_id)
+    }
+    fn remove_idle(&self, page_id: PageID) -> bool {
+        remove_if_idle(&mut self.shard(page_id).write().unwrap(), page_id)
+    }
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.map.read().unwrap().len())
+            .sum()
+    }
 }
 
 #[cfg(test)]
@@ -142,4 +284,146 @@ mod tests {
             let _ = handle.join();
         }
     }
+
+    #[test]
+    fn sharded_map_insert_get_remove() {
+        let tables: [Box<dyn PageTable>; 3] = [
+            Box::new(NaiveMappingTable::new()),
+            Box::new(ShardedMappingTable::new(8)),
+            // A single shard must still work
+            Box::new(ShardedMappingTable::new(1)),
+        ];
+
+        for table in tables {
+            for id in 0..1000 {
+                table.get_or_insert(PageID(id));
+            }
+
+            let first = table.get(PageID(10)).unwrap();
+            // get_or_insert hands back the existing entry rather than replacing it
+            assert!(Arc::ptr_eq(&first, &table.get_or_insert(PageID(10))));
+
+            assert!(table.remove(PageID(10)).is_some());
+            assert!(table.get(PageID(10)).is_none());
+            assert!(table.remove(PageID(10)).is_none());
+
+            // A removed page gets a fresh entry
+            assert!(!Arc::ptr_eq(&first, &table.get_or_insert(PageID(10))));
+            assert!(table.get(PageID(999)).is_some());
+
+            // Idle entries only - not while somebody holds a handle, nor while the page is loaded
+            let held = table.get(PageID(20)).unwrap();
+            assert!(!table.remove_idle(PageID(20)));
+            assert_eq!(
+                held.latch().load(|_| Ok(PageTableResult::Memory(0))),
+                Ok(PageTableResult::Memory(0))
+            );
+            drop(held);
+            assert!(!table.remove_idle(PageID(20)));
+            assert!(table.remove_idle(PageID(21)));
+            assert!(!table.remove_idle(PageID(21)));
+            assert_eq!(table.len(), 999);
+        }
+    }
+
+    #[test]
+    fn concurrent_get_or_insert_agrees_on_one_entry() {
+        let table = Arc::new(ShardedMappingTable::new(4));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let t = table.clone();
+                let b = barrier.clone();
+                thread::spawn(move || {
+                    b.wait();
+                    (0..200)
+                        .map(|id| t.get_or_insert(PageID(id)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        for id in 0..200 {
+            for other in &results[1..] {
+                assert!(Arc::ptr_eq(&results[0][id], &other[id]));
+            }
+        }
+    }
+
+    // Not a correctness test - prints throughput of each table for a lookup heavy and an insert heavy mix
+    #[test]
+    fn page_table_thread_benches() {
+        const THREADS: &[usize] = &[1, 2, 4, 8, 16];
+        const OPS_PER_THREAD: u64 = 20_000;
+        const KEY_SPACE: u64 = 4096;
+
+        // (name, percentage of operations that insert or remove rather than look up)
+        const MIXES: &[(&str, u64)] = &[("lookup-heavy", 5), ("insert-heavy", 50)];
+
+        for &(mix, write_pct) in MIXES {
+            for &threads in THREADS {
+                let tables: [(&str, Arc<dyn PageTable>); 2] = [
+                    ("naive", Arc::new(NaiveMappingTable::new())),
+                    (
+                        "sharded",
+                        Arc::new(ShardedMappingTable::new(DEFAULT_SHARDS)),
+                    ),
+                ];
+
+                for (name, table) in tables {
+                    for id in 0..KEY_SPACE {
+                        table.get_or_insert(PageID(id));
+                    }
+
+                    let barrier = Arc::new(Barrier::new(threads + 1));
+                    let handles: Vec<_> = (0..threads)
+                        .map(|t| {
+                            let table = table.clone();
+                            let b = barrier.clone();
+                            thread::spawn(move || {
+                                // Cheap per thread LCG so threads don't share a generator
+                                let mut x = 0x2545_F491_4F6C_DD1D_u64 ^ (t as u64 + 1);
+                                b.wait();
+                                for _ in 0..OPS_PER_THREAD {
+                                    x = x
+                                        .wrapping_mul(6364136223846793005)
+                                        .wrapping_add(1442695040888963407);
+                                    let id = PageID((x >> 33) % KEY_SPACE);
+                                    if (x >> 20) % 100 < write_pct {
+                                        if (x >> 10) & 1 == 0 {
+                                            table.remove(id);
+                                        } else {
+                                            table.get_or_insert(id);
+                                        }
+                                    } else {
+                                        std::hint::black_box(table.get(id));
+                                    }
+                                }
+                            })
+                        })
+                        .collect();
+
+                    let now = std::time::Instant::now();
+                    barrier.wait();
+                    for handle in handles {
+                        handle.join().unwrap();
+                    }
+                    let elapsed = now.elapsed();
+
+                    let ops = threads as u64 * OPS_PER_THREAD;
+                    println!(
+                        "{:<12} {:<8} threads: {:>2} ops: {:>7} time: {:?} ({:.0} ops/ms)",
+                        mix,
+                        name,
+                        threads,
+                        ops,
+                        elapsed,
+                        ops as f64 / elapsed.as_secs_f64() / 1000.0
+                    );
+                }
+            }
+        }
+    }
 }
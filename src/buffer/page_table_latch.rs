@@ -1,21 +1,167 @@
 use std::cell::UnsafeCell;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicU8, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 // For table entry we can use a small atomic state to allow threads to do double-checking for any misses and loading to disk,
 // along with CAS and Ordering
 // https://preshing.com/20130930/double-checked-locking-is-fixed-in-cpp11/
+//
+// A load that fails (or panics) rolls the entry back to PT_ON_DISK so the page can be tried again, and bumps
+// `failures` with the error kept in `last_error`. Threads that were waiting on that load see the count move
+// and return the loader's error rather than piling in to try again straight away - the next fetch does that.
+//
+// How a loader that lost the CAS waits is down to the WaitStrategy it passes in. By default it spins for a
+// little and then parks until the winner publishes (or gives up, or an evictor finishes) - see Parking below.
+
+const SPIN_LIMIT: u32 = 10;
+const YIELD_LIMIT: u32 = 50;
+
+pub(super) const PT_ON_DISK: u8 = 0;
+pub(super) const PT_LOADING: u8 = 1;
+pub(super) const PT_IN_MEMORY: u8 = 2;
+pub(super) const PT_EVICTING: u8 = 3;
+pub(super) const PT_INVALID: u8 = 4;
+
+// ---------- Wait Stats ----------//
+
+// Where loaders spend their time waiting on somebody else's load (or an eviction). Shared by every entry of a
+// page table - only the back-off path touches it, so the fast path doesn't pay for the Instant::now() calls.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BackoffPhase {
+    Spin = 0,
+    Yield = 1,
+    Sleep = 2,
+    Park = 3,
+}
+
+#[derive(Default)]
+pub(super) struct LatchWaitStats {
+    // Loads that had to back off at least once
+    waits: AtomicU64,
+    rounds: [AtomicU64; 4],
+    nanos: [AtomicU64; 4],
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct WaitPhase {
+    pub(crate) rounds: u64,
+    pub(crate) time: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct LatchWaitSnapshot {
+    pub(crate) waits: u64,
+    pub(crate) spins: WaitPhase,
+    pub(crate) yields: WaitPhase,
+    pub(crate) sleeps: WaitPhase,
+    pub(crate) parks: WaitPhase,
+}
+
+impl LatchWaitSnapshot {
+    pub(crate) fn total_time(&self) -> Duration {
+        self.spins.time + self.yields.time + self.sleeps.time + self.parks.time
+    }
+}
+
+impl LatchWaitStats {
+    fn record(&self, phase: BackoffPhase, elapsed: Duration) {
+        self.rounds[phase as usize].fetch_add(1, Ordering::Relaxed);
+        self.nanos[phase as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn phase(&self, phase: BackoffPhase) -> WaitPhase {
+        WaitPhase {
+            rounds: self.rounds[phase as usize].load(Ordering::Relaxed),
+            time: Duration::from_nanos(self.nanos[phase as usize].load(Ordering::Relaxed)),
+        }
+    }
+
+    pub(super) fn snapshot(&self) -> LatchWaitSnapshot {
+        LatchWaitSnapshot {
+            waits: self.waits.load(Ordering::Relaxed),
+            spins: self.phase(BackoffPhase::Spin),
+            yields: self.phase(BackoffPhase::Yield),
+            sleeps: self.phase(BackoffPhase::Sleep),
+            parks: self.phase(BackoffPhase::Park),
+        }
+    }
+}
+
+// ---------- Wait Strategy ----------//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WaitStrategy {
+    // Busy-wait until the entry moves - lowest latency if loads are short and there are cores to spare, burns a
+    // core per waiter otherwise
+    Spin,
+    // Spin, then yield, then sleep 1ms at a time. What the latch always did - a waiter can oversleep the load it
+    // was waiting on by up to a millisecond, which is what hurts the tail with many threads
+    SpinYieldSleep,
+    // Spin for `spins` rounds then park until the entry moves
+    SpinThenPark { spins: u32 },
+}
+
+impl Default for WaitStrategy {
+    fn default() -> Self {
+        WaitStrategy::SpinThenPark { spins: SPIN_LIMIT }
+    }
+}
+
+impl WaitStrategy {
+    fn phase(&self, round: u32) -> BackoffPhase {
+        match *self {
+            WaitStrategy::Spin => BackoffPhase::Spin,
+            WaitStrategy::SpinYieldSleep if round < SPIN_LIMIT => BackoffPhase::Spin,
+            WaitStrategy::SpinYieldSleep if round < YIELD_LIMIT => BackoffPhase::Yield,
+            WaitStrategy::SpinYieldSleep => BackoffPhase::Sleep,
+            WaitStrategy::SpinThenPark { spins } if round < spins => BackoffPhase::Spin,
+            WaitStrategy::SpinThenPark { .. } => BackoffPhase::Park,
+        }
+    }
+}
+
+// ---------- Parking ----------//
 
-const SPIN_LIMIT: u8 = 10;
-const YIELD_LIMIT: u8 = 50;
+// Parked loaders wait on one of a fixed set of buckets picked by the latch's address, after parking_lot, so a
+// page table entry only carries a waiter count rather than a mutex and condvar of its own. Latches that share a
+// bucket wake each other's waiters now and then - they look at their state again and park again.
+//
+// A waiter bumps `waiters` and then checks the state under the bucket lock before it sleeps, and a thread moving
+// the state out of loading/evicting stores it and then checks `waiters` (all SeqCst). Either the waiter sees the
+// new state and doesn't park, or the publisher sees the waiter and takes the bucket lock to notify - which it
+// can't get until the waiter is asleep on the condvar.
 
-const PT_ON_DISK: u8 = 0;
-const PT_LOADING: u8 = 1;
-const PT_IN_MEMORY: u8 = 2;
-const PT_INVALID: u8 = 3;
+const PARKING_BUCKETS: usize = 64;
+
+struct ParkingBucket {
+    lock: Mutex<()>,
+    cond: Condvar,
+}
+
+static PARKING: [ParkingBucket; PARKING_BUCKETS] = [const {
+    ParkingBucket {
+        lock: Mutex::new(()),
+        cond: Condvar::new(),
+    }
+}; PARKING_BUCKETS];
+
+fn parking_bucket(addr: usize) -> &'static ParkingBucket {
+    // Latches are at least word aligned, drop the low bits so neighbouring entries spread out
+    &PARKING[(addr >> 3) % PARKING_BUCKETS]
+}
+
+// ---------- Page Table Latch ----------//
 
 pub(super) struct PageTableLatch<T: Clone> {
     state: AtomicU8,
     data: UnsafeCell<T>,
+    failures: AtomicU32,
+    // Loaders parked on this entry, so publishing only goes near the parking buckets when somebody is there
+    waiters: AtomicU32,
+    // Only touched when a load fails so a lock is fine here
+    last_error: Mutex<Option<String>>,
 }
 
 unsafe impl<T: Clone> Sync for PageTableLatch<T> {}
@@ -26,15 +172,60 @@ impl<T: Clone> PageTableLatch<T> {
         Self {
             state: AtomicU8::new(PT_ON_DISK),
             data: UnsafeCell::new(data),
+            failures: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            last_error: Mutex::new(None),
         }
     }
 
+    // Moves the entry out of loading or evicting and wakes anyone parked on it
+    fn publish(&self, state: u8) {
+        self.state.store(state, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let bucket = parking_bucket(self as *const Self as usize);
+            let _guard = bucket.lock.lock().unwrap();
+            bucket.cond.notify_all();
+        }
+    }
+
+    // Sleeps until the entry is no longer in `seen`, or somebody sharing the bucket wakes us - either way the
+    // caller looks at the state again
+    fn park(&self, seen: u8) {
+        let bucket = parking_bucket(self as *const Self as usize);
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let guard = bucket.lock.lock().unwrap();
+        if self.state.load(Ordering::SeqCst) == seen {
+            drop(bucket.cond.wait(guard).unwrap());
+        } else {
+            drop(guard);
+        }
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // Number of loads of this entry that have failed so far
+    pub(super) fn failures(&self) -> u32 {
+        self.failures.load(Ordering::Acquire)
+    }
+
+    pub(super) fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    // Called by the loading thread while it still owns PT_LOADING. The error is published before the state so a
+    // waiter that sees the failure count move can always read it.
+    fn fail_load(&self, err: &str) {
+        *self.last_error.lock().unwrap() = Some(err.to_string());
+        self.failures.fetch_add(1, Ordering::AcqRel);
+        self.publish(PT_ON_DISK);
+    }
+
     pub(super) fn state(&self) -> u8 {
         let state = self.state.load(Ordering::Acquire);
 
         match state {
             PT_IN_MEMORY => PT_IN_MEMORY,
             PT_LOADING => PT_LOADING,
+            PT_EVICTING => PT_EVICTING,
             PT_ON_DISK => PT_ON_DISK,
             _ => PT_INVALID,
         }
@@ -53,10 +244,89 @@ impl<T: Clone> PageTableLatch<T> {
         (state, data)
     }
 
+    // SeqCst so it orders against frame pins - see PageFrame::pin
+    pub(super) fn is_in_memory(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == PT_IN_MEMORY
+    }
+
+    // Eviction is in-memory -> evicting -> on-disk, or back to in-memory if the evictor gives up.
+    //
+    // While the entry is PT_EVICTING the evictor has it exclusively - loaders back off the same way they do for
+    // PT_LOADING and nobody can validate a pin against it. `is_pinned` runs after the CAS so it pairs with the
+    // reader's pin-then-check (both SeqCst): either the reader sees PT_EVICTING and backs out, or we see its pin
+    // and the eviction fails.
+    pub(super) fn begin_evict(&self, is_pinned: impl FnOnce() -> bool) -> bool {
+        if self
+            .state
+            .compare_exchange(
+                PT_IN_MEMORY,
+                PT_EVICTING,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            return false;
+        }
+
+        if is_pinned() {
+            self.abort_evict();
+            return false;
+        }
+        true
+    }
+
+    // Publishes the on-disk data. Only the thread that won begin_evict may call this.
+    pub(super) fn finish_evict(&self, data: T) {
+        debug_assert_eq!(self.state.load(Ordering::Acquire), PT_EVICTING);
+        // SAFETY: We won the CAS into PT_EVICTING so we have the same exclusivity a loader has in PT_LOADING
+        unsafe { *self.data.get() = data };
+        self.publish(PT_ON_DISK);
+    }
+
+    // The page stays where it is - the data was never touched so readers see the same frame as before
+    pub(super) fn abort_evict(&self) {
+        debug_assert_eq!(self.state.load(Ordering::Acquire), PT_EVICTING);
+        self.publish(PT_IN_MEMORY);
+    }
+
+    // Load split in two, for a thread that wants several loads in flight at once (the prefetcher reading a
+    // window of pages through an AsyncPager). Takes the entry from on-disk to loading without waiting - None if
+    // it is anywhere else - and the claim it hands back must be published, failed or released. Loaders that
+    // find the entry claimed wait on it exactly as they would on load.
+    pub(super) fn try_claim(&self) -> Option<LoadClaim<'_, T>> {
+        self.state
+            .compare_exchange(PT_ON_DISK, PT_LOADING, Ordering::AcqRel, Ordering::Acquire)
+            .ok()?;
+        Some(LoadClaim { latch: self })
+    }
+
     pub(super) fn load(&self, work: impl FnOnce(T) -> Result<T, String>) -> Result<T, String> {
+        self.load_inner(WaitStrategy::default(), None, work)
+    }
+
+    // Same as load, waiting with `wait` and recording any time spent waiting into `stats`
+    pub(super) fn load_tracked(
+        &self,
+        wait: WaitStrategy,
+        stats: &LatchWaitStats,
+        work: impl FnOnce(T) -> Result<T, String>,
+    ) -> Result<T, String> {
+        self.load_inner(wait, Some(stats), work)
+    }
+
+    fn load_inner(
+        &self,
+        wait: WaitStrategy,
+        stats: Option<&LatchWaitStats>,
+        work: impl FnOnce(T) -> Result<T, String>,
+    ) -> Result<T, String> {
         // We need to loop and use CAS for one loader many writers - first thread gets the load
 
-        let mut spin_count = 0;
+        let mut spin_count: u32 = 0;
+        // Taken before we first see PT_LOADING so any load we end up waiting on fails after this snapshot
+        let failures = self.failures.load(Ordering::Acquire);
+        let mut waited = false;
 
         loop {
             //
@@ -71,23 +341,26 @@ impl<T: Clone> PageTableLatch<T> {
                     return Ok(res);
                 }
                 PT_ON_DISK => {
+                    // The load we were waiting on gave up - report it rather than hammering a failing pager
+                    if waited && self.failures.load(Ordering::Acquire) != failures {
+                        return Err(self
+                            .last_error()
+                            .unwrap_or_else(|| "load failed".to_string()));
+                    }
+
                     // We need to use double checking with CAS in order to compete for loading
-                    if let Ok(loading) = self.state.compare_exchange(
-                        PT_ON_DISK,
-                        PT_LOADING,
-                        Ordering::AcqRel,
-                        Ordering::Acquire,
-                    ) {
-                        // Do work
-                        let loaded = work(unsafe { &*self.data.get() }.clone())?;
-                        // We now need to update the loaded data to self
-                        //
-                        // SAFETY: We are the only thread that can access this data due to the fact that we won the CAS and the state is now PT_LOADING
-                        // We used the correct ordering to ensure that the data is loaded before the state is updated
-                        // And after we update the data, we ensure that we store using Ordering::Release to ensure that the data is visible to other threads
-                        unsafe { *self.data.get() = loaded.clone() };
-                        self.state.store(PT_IN_MEMORY, Ordering::Release);
-                        return Ok(loaded);
+                    if let Some(claim) = self.try_claim() {
+                        // Do work - if it panics the claim is dropped, which hands the entry back
+                        return match work(claim.data()) {
+                            Ok(loaded) => {
+                                claim.publish(loaded.clone());
+                                Ok(loaded)
+                            }
+                            Err(err) => {
+                                claim.fail(&err);
+                                Err(err)
+                            }
+                        };
                     } else {
                         continue;
                     }
@@ -95,7 +368,10 @@ impl<T: Clone> PageTableLatch<T> {
                 PT_LOADING => {
                     // spin/wait
                     // fall off into backoff below
+                    waited = true;
                 }
+                // Wait for the evictor to either finish, after which we load the page again, or give up
+                PT_EVICTING => {}
                 _ => {
                     println!("found invalid");
                     return Err("Invalid state".to_string());
@@ -104,31 +380,101 @@ impl<T: Clone> PageTableLatch<T> {
 
             // ----- Back off policy -------
 
-            if spin_count < SPIN_LIMIT {
-                std::hint::spin_loop();
-            } else if spin_count < YIELD_LIMIT {
-                std::thread::yield_now();
-            } else {
-                std::thread::sleep(std::time::Duration::from_millis(1));
+            let phase = wait.phase(spin_count);
+
+            let started = stats.map(|stats| {
+                if spin_count == 0 {
+                    stats.waits.fetch_add(1, Ordering::Relaxed);
+                }
+                Instant::now()
+            });
+
+            match phase {
+                BackoffPhase::Spin => std::hint::spin_loop(),
+                BackoffPhase::Yield => std::thread::yield_now(),
+                BackoffPhase::Sleep => std::thread::sleep(Duration::from_millis(1)),
+                BackoffPhase::Park => self.park(state),
+            }
+
+            if let (Some(stats), Some(started)) = (stats, started) {
+                stats.record(phase, started.elapsed());
             }
 
-            spin_count += 1;
+            // Saturating - a slow load keeps us in the last phase rather than overflowing the counter
+            spin_count = spin_count.saturating_add(1);
             continue;
         }
     }
 }
 
+// ---------- Load Claim ----------//
+
+// An entry held in PT_LOADING. Dropping the claim without finishing it fails the load, so a loader that unwinds
+// still hands the entry back rather than leaving every later load waiting forever.
+pub(super) struct LoadClaim<'a, T: Clone> {
+    latch: &'a PageTableLatch<T>,
+}
+
+impl<T: Clone> LoadClaim<'_, T> {
+    // The on-disk data the entry held when we claimed it
+    pub(super) fn data(&self) -> T {
+        // SAFETY: Nobody else touches the data while the entry is PT_LOADING and we are the loader
+        unsafe { &*self.latch.data.get() }.clone()
+    }
+
+    pub(super) fn publish(self, loaded: T) {
+        // SAFETY: We won the CAS into PT_LOADING so we are the only thread that can access the data. The
+        // publish stores the state with SeqCst after the write so anyone who sees PT_IN_MEMORY sees the data.
+        unsafe { *self.latch.data.get() = loaded };
+        self.latch.publish(PT_IN_MEMORY);
+        std::mem::forget(self);
+    }
+
+    // Rolls back to on-disk and reports the error to anyone waiting on us
+    pub(super) fn fail(self, err: &str) {
+        self.latch.fail_load(err);
+        std::mem::forget(self);
+    }
+
+    // Rolls back to on-disk without counting a failure, for a load given up before it started - waiters take
+    // the entry over and load it themselves
+    pub(super) fn release(self) {
+        self.latch.publish(PT_ON_DISK);
+        std::mem::forget(self);
+    }
+}
+
+impl<T: Clone> Drop for LoadClaim<'_, T> {
+    fn drop(&mut self) {
+        self.latch.fail_load("loader panicked");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::sync::{Arc, Barrier};
 
+    // Prints latencies for each wait strategy, run it with `cargo test -- --ignored page_latch_thread_benches`
     #[test]
+    #[ignore = "benchmark"]
     fn page_latch_thread_benches() {
-        let latch = Arc::new(PageTableLatch {
-            data: UnsafeCell::new(10),
-            state: AtomicU8::new(PT_IN_MEMORY),
-        });
+        const STRATEGIES: &[WaitStrategy] = &[
+            WaitStrategy::Spin,
+            WaitStrategy::SpinYieldSleep,
+            WaitStrategy::SpinThenPark { spins: 0 },
+            WaitStrategy::SpinThenPark { spins: SPIN_LIMIT },
+        ];
+
+        for &strategy in STRATEGIES {
+            println!("##### {:?} #####", strategy);
+            latch_thread_bench(strategy);
+        }
+    }
+
+    fn latch_thread_bench(strategy: WaitStrategy) {
+        let latch = Arc::new(PageTableLatch::new(10));
+        let stats = Arc::new(LatchWaitStats::default());
 
         const THREADS: &[usize] = &[1, 2, 10, 20, 50, 100, 250, 500, 750, 1000];
 
@@ -145,14 +491,15 @@ mod tests {
 
             for _ in 0..thread {
                 let latch_clone = latch.clone();
+                let stats = stats.clone();
                 let thread = barrier.clone();
                 handles.push(std::thread::spawn(move || {
                     thread.wait();
                     let thread_start = std::time::Instant::now();
                     latch_clone
-                        .load(|_| {
+                        .load_tracked(strategy, &stats, |_| {
                             std::thread::sleep(std::time::Duration::from_millis(30));
-                            return Ok(10);
+                            Ok(10)
                         })
                         .ok();
                     thread_start.elapsed()
@@ -172,7 +519,7 @@ mod tests {
             let mut results = times.iter().map(|t| t.as_nanos()).collect::<Vec<u128>>();
             results.sort();
 
-            let first = results.get(0).cloned().unwrap_or(0);
+            let first = results.first().cloned().unwrap_or(0);
             let p50 = results.get(results.len() / 2).cloned().unwrap_or(0);
             let p90 = results.get(results.len() * 9 / 10).cloned().unwrap_or(0);
             let p99 = results.get(results.len() * 99 / 100).cloned().unwrap_or(0);
@@ -185,7 +532,244 @@ mod tests {
             println!("  {} / {} / {} / {} / {}", first, p50, p90, p99, last);
             println!("==============================");
         }
+
+        println!("{:?}", stats.snapshot());
+    }
+
+    #[test]
+    fn failed_load_rolls_back_to_on_disk() {
+        let latch = PageTableLatch::new(0u64);
+
+        assert_eq!(
+            latch.load(|_| Err("read failed".to_string())),
+            Err("read failed".to_string())
+        );
+        assert_eq!(latch.state(), PT_ON_DISK);
+        assert_eq!(latch.failures(), 1);
+        assert_eq!(latch.last_error().as_deref(), Some("read failed"));
+
+        // A panicking loader is rolled back too
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = latch.load(|_| panic!("boom"));
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(latch.state(), PT_ON_DISK);
+        assert_eq!(latch.failures(), 2);
+
+        // And the next load gets to try again
+        assert_eq!(latch.load(|_| Ok(5)), Ok(5));
+        assert_eq!(latch.state(), PT_IN_MEMORY);
+    }
+
+    #[test]
+    fn waiters_see_failed_loads_under_contention() {
+        const THREADS: usize = 16;
+        const ROUNDS: usize = 20;
+
+        for round in 0..ROUNDS {
+            let latch = Arc::new(PageTableLatch::new(0u64));
+            let barrier = Arc::new(Barrier::new(THREADS));
+            let attempts = Arc::new(AtomicU32::new(0));
+
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let latch = latch.clone();
+                    let barrier = barrier.clone();
+                    let attempts = attempts.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        // Every thread keeps asking until the page is in, the first two loads fail
+                        let mut errors = 0;
+                        loop {
+                            let result = latch.load(|_| {
+                                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                                std::thread::sleep(std::time::Duration::from_millis(2));
+                                if attempt < 2 {
+                                    Err(format!("injected failure {}", attempt))
+                                } else {
+                                    Ok(42)
+                                }
+                            });
+                            match result {
+                                Ok(value) => return (value, errors),
+                                Err(err) => {
+                                    assert!(err.starts_with("injected failure"), "{}", err);
+                                    errors += 1;
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            let mut total_errors = 0;
+            for handle in handles {
+                let (value, errors) = handle.join().unwrap();
+                assert_eq!(value, 42);
+                total_errors += errors;
+            }
+
+            // Exactly two loads ran and failed, one succeeded, and nobody was left waiting
+            assert_eq!(attempts.load(Ordering::SeqCst), 3);
+            assert_eq!(latch.failures(), 2);
+            assert_eq!(latch.state(), PT_IN_MEMORY);
+            // Each failed load is seen by at least its own loader, and nobody sees more failures than there were
+            assert!(
+                (2..=2 * THREADS).contains(&total_errors),
+                "round {}: {} errors",
+                round,
+                total_errors
+            );
+        }
     }
 
-    // Measure with different strategies, backoff, just spin, no thread sleep etc
+    #[test]
+    fn eviction_fails_while_pinned_and_blocks_loaders() {
+        let latch = Arc::new(PageTableLatch::new(0u64));
+        assert_eq!(latch.load(|_| Ok(7)), Ok(7));
+
+        // Only an in-memory entry can be evicted, and not while somebody holds it
+        assert!(!latch.begin_evict(|| true));
+        assert_eq!(latch.state(), PT_IN_MEMORY);
+
+        assert!(latch.begin_evict(|| false));
+        assert_eq!(latch.state(), PT_EVICTING);
+        assert!(!latch.begin_evict(|| false));
+
+        // A loader arriving now waits for the eviction to finish and then loads the page back in
+        let loader = {
+            let latch = latch.clone();
+            std::thread::spawn(move || latch.load(|data| Ok(data + 1)))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!loader.is_finished());
+
+        latch.finish_evict(100);
+        assert_eq!(loader.join().unwrap(), Ok(101));
+
+        // Aborting leaves the data alone
+        assert!(latch.begin_evict(|| false));
+        latch.abort_evict();
+        assert_eq!(latch.peek(), (PT_IN_MEMORY, 101));
+    }
+
+    #[test]
+    fn waiting_loaders_are_counted_by_phase() {
+        let latch = Arc::new(PageTableLatch::new(0u64));
+        let stats = Arc::new(LatchWaitStats::default());
+
+        let wait = WaitStrategy::SpinYieldSleep;
+
+        // Nobody to wait for - the fast path records nothing
+        assert_eq!(latch.load_tracked(wait, &stats, |_| Ok(1)), Ok(1));
+        assert_eq!(stats.snapshot(), LatchWaitSnapshot::default());
+
+        latch.state.store(PT_ON_DISK, Ordering::Release);
+        let barrier = Arc::new(Barrier::new(2));
+        let loader = {
+            let (latch, barrier) = (latch.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                latch.load(|_| {
+                    barrier.wait();
+                    std::thread::sleep(std::time::Duration::from_millis(30));
+                    Ok(2)
+                })
+            })
+        };
+
+        // Long enough a load to go through every phase
+        barrier.wait();
+        assert_eq!(latch.load_tracked(wait, &stats, |_| Ok(3)), Ok(2));
+        loader.join().unwrap().unwrap();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.waits, 1);
+        assert_eq!(snapshot.spins.rounds, SPIN_LIMIT as u64);
+        assert_eq!(snapshot.yields.rounds, (YIELD_LIMIT - SPIN_LIMIT) as u64);
+        assert!(snapshot.sleeps.rounds > 0);
+        assert!(snapshot.sleeps.time >= std::time::Duration::from_millis(snapshot.sleeps.rounds));
+        assert_eq!(snapshot.parks, WaitPhase::default());
+        // Most of the 30ms load was spent backing off
+        assert!(snapshot.total_time() >= std::time::Duration::from_millis(10));
+    }
+
+    #[test]
+    fn parked_loaders_wake_when_the_load_is_published() {
+        const THREADS: usize = 8;
+
+        let latch = Arc::new(PageTableLatch::new(0u64));
+        let stats = Arc::new(LatchWaitStats::default());
+        let wait = WaitStrategy::SpinThenPark { spins: 2 };
+
+        // Hold the entry in PT_LOADING ourselves so every thread has to park behind it
+        latch.state.store(PT_LOADING, Ordering::SeqCst);
+        let waiters: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let (latch, stats) = (latch.clone(), stats.clone());
+                std::thread::spawn(move || latch.load_tracked(wait, &stats, |_| Ok(1)))
+            })
+            .collect();
+
+        while latch.waiters.load(Ordering::SeqCst) < THREADS as u32 {
+            std::thread::yield_now();
+        }
+        unsafe { *latch.data.get() = 9 };
+        latch.publish(PT_IN_MEMORY);
+
+        for waiter in waiters {
+            assert_eq!(waiter.join().unwrap(), Ok(9));
+        }
+        assert_eq!(latch.waiters.load(Ordering::SeqCst), 0);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.waits, THREADS as u64);
+        assert_eq!(snapshot.spins.rounds, 2 * THREADS as u64);
+        assert!(snapshot.parks.rounds >= THREADS as u64);
+        assert_eq!(snapshot.yields, WaitPhase::default());
+        assert_eq!(snapshot.sleeps, WaitPhase::default());
+    }
+
+    #[test]
+    fn claimed_loads_are_published_failed_or_released() {
+        let latch = Arc::new(PageTableLatch::new(0u64));
+        let waiter = |latch: &Arc<PageTableLatch<u64>>| {
+            let latch = latch.clone();
+            let handle = std::thread::spawn(move || latch.load(|data| Ok(data + 5)));
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            assert!(!handle.is_finished());
+            handle
+        };
+
+        // A claim holds the entry in PT_LOADING like a load does, and a released one is simply taken over
+        let claim = latch.try_claim().unwrap();
+        assert_eq!(latch.state(), PT_LOADING);
+        assert!(latch.try_claim().is_none());
+        let loader = waiter(&latch);
+        claim.release();
+        assert_eq!(loader.join().unwrap(), Ok(5));
+        assert_eq!(latch.failures(), 0);
+        assert!(latch.try_claim().is_none());
+
+        assert!(latch.begin_evict(|| false));
+        latch.finish_evict(10);
+
+        // A failed claim is reported to whoever was waiting on it
+        let claim = latch.try_claim().unwrap();
+        assert_eq!(claim.data(), 10);
+        let loader = waiter(&latch);
+        claim.fail("read failed");
+        assert_eq!(loader.join().unwrap(), Err("read failed".to_string()));
+        assert_eq!(latch.failures(), 1);
+
+        // One dropped unfinished fails too
+        drop(latch.try_claim().unwrap());
+        assert_eq!(latch.failures(), 2);
+        assert_eq!(latch.state(), PT_ON_DISK);
+
+        let claim = latch.try_claim().unwrap();
+        let loader = waiter(&latch);
+        claim.publish(42);
+        assert_eq!(loader.join().unwrap(), Ok(42));
+        assert_eq!(latch.peek(), (PT_IN_MEMORY, 42));
+    }
 }
@@ -0,0 +1,226 @@
+// Pin leak detection.
+//
+// A PinnedPage that is never dropped (mem::forget, a cycle, a pin stashed somewhere and forgotten about) leaves
+// its frame pinned for good - it can never be evicted and nothing says why. In debug builds, or with the
+// `pin-tracking` feature, every pin the buffer manager hands out is recorded with where it was taken (the caller
+// of fetch_page and friends, through #[track_caller]), and with the feature also a backtrace, subject to
+// RUST_BACKTRACE like any other.
+//
+// Outstanding pins are reported when a PinScope is dropped - a transaction holds one for its lifetime and
+// every pin taken on its thread while it is active is put down to it - and when the buffer manager is
+// dropped. Under cfg(test) a report is a panic so a test that leaks fails. Release builds without the feature
+// get the no-op version below, where the tracking calls compile away.
+
+#[cfg(any(debug_assertions, feature = "pin-tracking"))]
+pub(crate) use tracking::{PinScope, PinToken, PinTracker};
+
+#[cfg(not(any(debug_assertions, feature = "pin-tracking")))]
+pub(crate) use noop::{PinScope, PinToken, PinTracker};
+
+// Prints what leaked, and fails the test if we are in one (and not already unwinding from something else)
+#[cfg(any(debug_assertions, feature = "pin-tracking"))]
+fn report(leaks: &[tracking::PinRecord], owner: &str) {
+    if leaks.is_empty() {
+        return;
+    }
+    eprintln!("{} leaked {} pin(s):", owner, leaks.len());
+    for leak in leaks {
+        eprintln!("  {:?}", leak);
+    }
+    if cfg!(test) && !std::thread::panicking() {
+        panic!(
+            "{} leaked {} pin(s), first taken at {}",
+            owner,
+            leaks.len(),
+            leaks[0].location
+        );
+    }
+}
+
+#[cfg(any(debug_assertions, feature = "pin-tracking"))]
+mod tracking {
+    use crate::page::PageID;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::panic::Location;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // Scope ids are global so a scope can't pick up pins from another tracker's scope with the same number
+    static NEXT_SCOPE: AtomicU64 = AtomicU64::new(1);
+
+    type Scope = (u64, Arc<str>);
+
+    thread_local! {
+        static CURRENT_SCOPE: RefCell<Option<Scope>> = const { RefCell::new(None) };
+    }
+
+    pub(crate) struct PinRecord {
+        pub(crate) frame: usize,
+        pub(crate) page_id: Option<PageID>,
+        pub(crate) location: &'static Location<'static>,
+        pub(crate) thread: Option<String>,
+        scope: Option<Scope>,
+        #[cfg(feature = "pin-tracking")]
+        backtrace: std::backtrace::Backtrace,
+    }
+
+    impl std::fmt::Debug for PinRecord {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "frame {} page {:?} pinned at {} on thread {}",
+                self.frame,
+                self.page_id,
+                self.location,
+                self.thread.as_deref().unwrap_or("<unnamed>")
+            )?;
+            if let Some((_, name)) = &self.scope {
+                write!(f, " in {}", name)?;
+            }
+            #[cfg(feature = "pin-tracking")]
+            if self.backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+                write!(f, "\n{}", self.backtrace)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Pins {
+        next: u64,
+        live: HashMap<u64, PinRecord>,
+    }
+
+    // Cloned into every scope so a scope doesn't borrow the buffer manager
+    #[derive(Clone, Default)]
+    pub(crate) struct PinTracker {
+        pins: Arc<Mutex<Pins>>,
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct PinToken(u64);
+
+    impl PinTracker {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        #[track_caller]
+        pub(crate) fn acquire(&self, frame: usize, page_id: Option<PageID>) -> PinToken {
+            let scope = CURRENT_SCOPE.with(|current| current.borrow().clone());
+            let mut pins = self.pins.lock().unwrap();
+            pins.next += 1;
+            let id = pins.next;
+            pins.live.insert(
+                id,
+                PinRecord {
+                    frame,
+                    page_id,
+                    location: Location::caller(),
+                    thread: std::thread::current().name().map(str::to_string),
+                    scope,
+                    #[cfg(feature = "pin-tracking")]
+                    backtrace: std::backtrace::Backtrace::capture(),
+                },
+            );
+            PinToken(id)
+        }
+
+        pub(crate) fn release(&self, token: PinToken) {
+            self.pins.lock().unwrap().live.remove(&token.0);
+        }
+
+        pub(crate) fn outstanding(&self) -> usize {
+            self.pins.lock().unwrap().live.len()
+        }
+
+        fn take_leaks(&self, scope: Option<u64>) -> Vec<PinRecord> {
+            let mut pins = self.pins.lock().unwrap();
+            let leaked: Vec<u64> = pins
+                .live
+                .iter()
+                .filter(|(_, record)| match scope {
+                    Some(id) => record.scope.as_ref().is_some_and(|(s, _)| *s == id),
+                    None => true,
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            // Reported once - a leaked pin is forgotten about after it has been reported
+            leaked
+                .into_iter()
+                .filter_map(|id| pins.live.remove(&id))
+                .collect()
+        }
+
+        pub(crate) fn check_leaks(&self, owner: &str) {
+            super::report(&self.take_leaks(None), owner);
+        }
+
+        pub(crate) fn scope(&self, name: &str) -> PinScope {
+            let id = NEXT_SCOPE.fetch_add(1, Ordering::Relaxed);
+            let name: Arc<str> = Arc::from(name);
+            let previous = CURRENT_SCOPE.with(|current| current.replace(Some((id, name.clone()))));
+            PinScope {
+                tracker: self.clone(),
+                id,
+                name,
+                previous,
+            }
+        }
+    }
+
+    // Pins taken on this thread while the scope is the innermost one are put down to it. Scopes nest, dropping
+    // one puts the previous one back.
+    pub(crate) struct PinScope {
+        tracker: PinTracker,
+        id: u64,
+        name: Arc<str>,
+        previous: Option<Scope>,
+    }
+
+    impl Drop for PinScope {
+        fn drop(&mut self) {
+            CURRENT_SCOPE.with(|current| *current.borrow_mut() = self.previous.take());
+            let leaks = self.tracker.take_leaks(Some(self.id));
+            super::report(&leaks, &self.name);
+        }
+    }
+}
+
+#[cfg(not(any(debug_assertions, feature = "pin-tracking")))]
+mod noop {
+    use crate::page::PageID;
+
+    #[derive(Clone, Default)]
+    pub(crate) struct PinTracker;
+
+    #[derive(Debug)]
+    pub(crate) struct PinToken;
+
+    pub(crate) struct PinScope;
+
+    impl PinTracker {
+        pub(crate) fn new() -> Self {
+            Self
+        }
+
+        #[inline(always)]
+        pub(crate) fn acquire(&self, _frame: usize, _page_id: Option<PageID>) -> PinToken {
+            PinToken
+        }
+
+        #[inline(always)]
+        pub(crate) fn release(&self, _token: PinToken) {}
+
+        pub(crate) fn outstanding(&self) -> usize {
+            0
+        }
+
+        pub(crate) fn check_leaks(&self, _owner: &str) {}
+
+        pub(crate) fn scope(&self, _name: &str) -> PinScope {
+            PinScope
+        }
+    }
+}
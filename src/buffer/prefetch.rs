@@ -0,0 +1,158 @@
+// Prefetcher - loads pages in the background ahead of the threads that are going to ask for them.
+//
+// Two ways in:
+//   - BufferManager::prefetch(&[PageID]) for callers that know their page set up front (the pages a transaction
+//     is about to touch, the children of an internal page a range scan will visit)
+//   - readahead from fetch_right_sibling - a scan walking a leaf chain queues the pages after the one it just
+//     got, `readahead` deep, so the next few siblings are (hopefully) resident by the time it reaches them
+//
+// Requests are hints. The queue is bounded and drops what doesn't fit, pages that are already resident are
+// skipped and load failures are ignored - the thread that really needs the page will see the error itself.
+// Prefetched pages are loaded but not pinned, so they are ordinary eviction candidates until somebody uses them.
+//
+// The thread takes up to `window` requests off the queue at a time and reads them through its AsyncPager as one
+// batch - each page is claimed on its page table latch first and published as its read completes, so several
+// loads are in flight at once (see BufferManager::prefetch_window). Without an AsyncPager of its own it gets a
+// synchronous one over the pool's pager, and falls back to that if its own fails.
+//
+// Like the background writer the thread only holds a Weak reference to the pool between requests.
+
+use crate::buffer::buffer_manager::BufferManager;
+use crate::page::PageID;
+use crate::pager::async_pager::AsyncPager;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::JoinHandle;
+
+#[derive(Debug, Clone)]
+pub(crate) struct PrefetchConfig {
+    // How many siblings ahead of a scan to keep loaded
+    pub(crate) readahead: usize,
+    pub(crate) max_queued: usize,
+    // How many queued requests are read at once
+    pub(crate) window: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            readahead: 8,
+            max_queued: 1024,
+            window: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PrefetchStats {
+    // Pages the prefetcher read in
+    pub(crate) loaded: u64,
+    // Prefetched pages that were fetched before being evicted
+    pub(crate) used: u64,
+    // Prefetched pages that were evicted without ever being fetched
+    pub(crate) wasted: u64,
+}
+
+struct PrefetchQueue {
+    // Page and how much further along its right-sibling chain to read after it
+    requests: VecDeque<(PageID, usize)>,
+    shutdown: bool,
+}
+
+pub(crate) struct PrefetchHandle {
+    queue: Arc<(Mutex<PrefetchQueue>, Condvar)>,
+    config: PrefetchConfig,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PrefetchHandle {
+    pub(super) fn spawn(
+        bm: Weak<BufferManager>,
+        config: PrefetchConfig,
+        mut io: AsyncPager,
+    ) -> Self {
+        let queue = Arc::new((
+            Mutex::new(PrefetchQueue {
+                requests: VecDeque::new(),
+                shutdown: false,
+            }),
+            Condvar::new(),
+        ));
+        let shared = queue.clone();
+        let max_queued = config.max_queued;
+        let window = config.window.max(1);
+
+        let thread = std::thread::Builder::new()
+            .name("inkdb-prefetch".to_string())
+            .spawn(move || {
+                loop {
+                    let requests: Vec<_> = {
+                        let (lock, cvar) = &*shared;
+                        let mut queue = cvar
+                            .wait_while(lock.lock().unwrap(), |q| {
+                                q.requests.is_empty() && !q.shutdown
+                            })
+                            .unwrap();
+                        if queue.shutdown {
+                            return;
+                        }
+                        let take = queue.requests.len().min(window);
+                        queue.requests.drain(..take).collect()
+                    };
+
+                    let Some(bm) = bm.upgrade() else {
+                        return;
+                    };
+                    match bm.prefetch_window(&mut io, &requests) {
+                        Ok(next) => {
+                            for request in next {
+                                push(&shared, max_queued, request);
+                            }
+                        }
+                        // The queue failed under us - what it didn't read is back on disk for whoever needs it
+                        Err(_) => io = bm.sync_io(),
+                    }
+                }
+            })
+            .expect("failed to spawn prefetcher");
+
+        Self {
+            queue,
+            config,
+            thread: Some(thread),
+        }
+    }
+
+    pub(super) fn readahead(&self) -> usize {
+        self.config.readahead
+    }
+
+    pub(super) fn enqueue(&self, page_id: PageID, depth: usize) {
+        push(&self.queue, self.config.max_queued, (page_id, depth));
+    }
+
+    pub(super) fn stop(mut self) {
+        {
+            let (lock, cvar) = &*self.queue;
+            lock.lock().unwrap().shutdown = true;
+            cvar.notify_all();
+        }
+
+        if let Some(thread) = self.thread.take() {
+            // Same as the background writer - we may be dropping the pool on the prefetch thread itself
+            if thread.thread().id() != std::thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+fn push(queue: &(Mutex<PrefetchQueue>, Condvar), max_queued: usize, request: (PageID, usize)) {
+    let (lock, cvar) = queue;
+    let mut queue = lock.lock().unwrap();
+    if queue.requests.len() >= max_queued {
+        return;
+    }
+    queue.requests.push_back(request);
+    cvar.notify_one();
+}
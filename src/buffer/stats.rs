@@ -0,0 +1,104 @@
+// Buffer pool statistics.
+//
+// Counters are plain relaxed atomics bumped on the hot paths, BufferManager::stats() reads them into a
+// BufferStats snapshot. Individual counters are exact but a snapshot is not a consistent cut across them - a
+// fetch may be counted as a hit before the eviction it raced with is. Good enough for monitoring and tuning.
+//
+//   hits / misses      - fetches that found the page resident / had to read it (prefetch loads are not fetches)
+//   evictions          - pages pushed out to make room, discarded pages are not counted
+//   written_*          - dirty pages written back, by who wrote them
+//   pinned_frames      - frames pinned at the time of the snapshot
+//   latch              - time loaders spent waiting on page table entries (see page_table_latch.rs)
+//
+// BufferManager::buffer_cache() is the per-frame view, after Postgres's pg_buffercache.
+
+use crate::buffer::page_table_latch::{LatchWaitSnapshot, LatchWaitStats};
+use crate::buffer::prefetch::PrefetchStats;
+use crate::page::{PageID, PageKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Default)]
+pub(super) struct BufferCounters {
+    pub(super) hits: AtomicU64,
+    pub(super) misses: AtomicU64,
+    pub(super) evictions: AtomicU64,
+    pub(super) pages_written_bgwriter: AtomicU64,
+    pub(super) pages_written_backend: AtomicU64,
+    pub(super) prefetch_loaded: AtomicU64,
+    pub(super) prefetch_used: AtomicU64,
+    pub(super) prefetch_wasted: AtomicU64,
+    pub(super) latch: LatchWaitStats,
+}
+
+impl BufferCounters {
+    pub(super) fn prefetch(&self) -> PrefetchStats {
+        PrefetchStats {
+            loaded: self.prefetch_loaded.load(Ordering::Relaxed),
+            used: self.prefetch_used.load(Ordering::Relaxed),
+            wasted: self.prefetch_wasted.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(super) fn snapshot(&self, pinned_frames: usize) -> BufferStats {
+        BufferStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            written_backend: self.pages_written_backend.load(Ordering::Relaxed),
+            written_bgwriter: self.pages_written_bgwriter.load(Ordering::Relaxed),
+            pinned_frames,
+            prefetch: self.prefetch(),
+            latch: self.latch.snapshot(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct BufferStats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) evictions: u64,
+    pub(crate) written_backend: u64,
+    pub(crate) written_bgwriter: u64,
+    pub(crate) pinned_frames: usize,
+    pub(crate) prefetch: PrefetchStats,
+    pub(crate) latch: LatchWaitSnapshot,
+}
+
+impl BufferStats {
+    pub(crate) fn hit_ratio(&self) -> f64 {
+        let fetches = self.hits + self.misses;
+        if fetches == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / fetches as f64
+    }
+
+    pub(crate) fn dirty_writes(&self) -> u64 {
+        self.written_backend + self.written_bgwriter
+    }
+}
+
+// One row per resident frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FrameInfo {
+    pub(crate) frame: usize,
+    pub(crate) page_id: PageID,
+    pub(crate) kind: PageKind,
+    pub(crate) pin_count: u16,
+    pub(crate) dirty: bool,
+}
+
+// What the pool is holding against its memory budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct MemoryUsage {
+    pub(crate) frames: usize,
+    pub(crate) page_table: usize,
+    pub(crate) metadata: usize,
+}
+
+impl MemoryUsage {
+    pub(crate) fn total(&self) -> usize {
+        self.frames + self.page_table + self.metadata
+    }
+}
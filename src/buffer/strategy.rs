@@ -0,0 +1,92 @@
+// Buffer access strategies, after Postgres's BufferAccessStrategy.
+//
+// A large sequential scan touches every page once. Going through the normal replacement path it would load each
+// page into a fresh frame and push the whole hot working set out of the pool on the way. Operations which know
+// they are going to do that ask for a ring instead - a small set of frames that the operation recycles for
+// itself, so the scan only ever costs the pool a ring's worth of frames.
+//
+//   BulkRead   - sequential scans, a 256KB ring
+//   BulkWrite  - index builds and bulk loads, a 16MB ring since every page it recycles is dirty and has to be
+//                written out first, and a tiny ring would make that write the bottleneck
+//
+// Both are capped at an eighth of the pool. A ring frame is only reused if it still holds the page the ring put
+// there and nobody has it pinned - if another operation started using the page or it can't be written out yet
+// (WAL rule) we leave it to the pool and take a frame through the normal path instead.
+
+use crate::page::{PAGE_SIZE, PageID};
+use std::sync::Mutex;
+
+const BULK_READ_RING_BYTES: usize = 256 * 1024;
+const BULK_WRITE_RING_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AccessStrategy {
+    BulkRead,
+    BulkWrite,
+}
+
+impl AccessStrategy {
+    pub(crate) fn ring_size(self, pool_size: usize) -> usize {
+        let bytes = match self {
+            AccessStrategy::BulkRead => BULK_READ_RING_BYTES,
+            AccessStrategy::BulkWrite => BULK_WRITE_RING_BYTES,
+        };
+        (bytes / PAGE_SIZE).min(pool_size / 8).max(1)
+    }
+}
+
+pub(crate) struct BufferRing {
+    strategy: AccessStrategy,
+    inner: Mutex<RingInner>,
+}
+
+struct RingInner {
+    // Frame and the page we loaded into it
+    slots: Vec<Option<(usize, PageID)>>,
+    current: usize,
+}
+
+impl BufferRing {
+    pub(super) fn new(strategy: AccessStrategy, pool_size: usize) -> Self {
+        let size = strategy.ring_size(pool_size);
+        Self {
+            strategy,
+            inner: Mutex::new(RingInner {
+                slots: vec![None; size],
+                current: size - 1,
+            }),
+        }
+    }
+
+    pub(crate) fn strategy(&self) -> AccessStrategy {
+        self.strategy
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.inner.lock().unwrap().slots.len()
+    }
+
+    // Moves on to the next slot and returns what it held, the caller tries to recycle it
+    pub(super) fn advance(&self) -> Option<(usize, PageID)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.current = (inner.current + 1) % inner.slots.len();
+        let current = inner.current;
+        inner.slots[current]
+    }
+
+    // Whatever frame the load ended up in becomes the current slot
+    pub(super) fn install(&self, frame_idx: usize, page_id: PageID) {
+        let mut inner = self.inner.lock().unwrap();
+        let current = inner.current;
+        inner.slots[current] = Some((frame_idx, page_id));
+    }
+
+    pub(crate) fn frames(&self) -> Vec<usize> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .slots
+            .iter()
+            .filter_map(|slot| slot.map(|(frame_idx, _)| frame_idx))
+            .collect()
+    }
+}
@@ -0,0 +1,64 @@
+// Swips - child pointers in internal pages which are either a PageID on disk or, once the child is resident
+// and swizzling is on, the index of the frame holding it.
+//
+// The top bit tells the two apart. PageIDs never use it (that would be an offset beyond 2^75 bytes) so a page
+// read from disk never looks swizzled, and write-back unswizzles a copy of the page before it goes out so a
+// frame index can never reach disk.
+
+use crate::page::PageID;
+
+pub(crate) const SWIZZLED_TAG: u64 = 1 << 63;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Swip {
+    Page(PageID),
+    Frame(usize),
+}
+
+impl Swip {
+    pub(crate) fn decode(raw: PageID) -> Self {
+        let raw = raw.into();
+        if raw & SWIZZLED_TAG != 0 {
+            Swip::Frame((raw & !SWIZZLED_TAG) as usize)
+        } else {
+            Swip::Page(PageID(raw))
+        }
+    }
+
+    pub(crate) fn encode(self) -> PageID {
+        match self {
+            Swip::Page(page_id) => {
+                debug_assert_eq!(
+                    page_id.into() & SWIZZLED_TAG,
+                    0,
+                    "page id uses the swizzle bit"
+                );
+                page_id
+            }
+            Swip::Frame(frame_idx) => PageID(frame_idx as u64 | SWIZZLED_TAG),
+        }
+    }
+
+    pub(crate) fn is_swizzled(raw: PageID) -> bool {
+        raw.into() & SWIZZLED_TAG != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swips_round_trip() {
+        for swip in [
+            Swip::Page(PageID(0)),
+            Swip::Page(PageID(42)),
+            Swip::Frame(0),
+            Swip::Frame(7),
+        ] {
+            assert_eq!(Swip::decode(swip.encode()), swip);
+        }
+        assert!(Swip::is_swizzled(Swip::Frame(0).encode()));
+        assert!(!Swip::is_swizzled(PageID(u64::MAX >> 1)));
+    }
+}
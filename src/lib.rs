@@ -1,5 +1,7 @@
 mod buffer;
 mod index;
+mod meta;
 mod page;
+mod pager;
 mod transaction;
 mod tree;
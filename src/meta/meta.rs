@@ -1,7 +1,8 @@
 use crate::page::PageID;
 
 //NOTE: This is a meta page for btree indexes
-// Many meta-pages may be stored for different tables and are referenced in the main db meta page
+// Many meta-pages may be stored for different tables and are referenced from the catalog in the superblock
+// (see superblock.rs)
 
 pub(crate) struct BTreeMetaPage {
     version: u64,
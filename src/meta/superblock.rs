@@ -0,0 +1,514 @@
+use crate::page::{HEADER_SIZE, PAGE_SIZE, PageID, PageKind, RawPage, SlottedPageMut};
+use crate::pager::pager::{Pager, PagerError};
+
+//NOTE: Pages 0 and 1 of every database hold the superblock - the one thing we can find without knowing anything
+// else. It says what the file is (magic, format version, page size), where allocation stands (the next page id
+// never handed out, the head of the free list), how far the last checkpoint got, and where each tree lives (a
+// catalog of tree name -> BTreeMetaPage id).
+//
+// There are two copies so that a write torn by a crash can never lose it. Every store goes to the page holding
+// the older copy, with a sequence number one past the newer, and open takes the newest copy that passes every
+// check - a torn or failed write only ever damages the copy it was replacing.
+//
+// Pages 0 and 1 are never anything else, which is what lets PageID(0) mean "no page" - no right sibling, an
+// empty free list.
+//
+// It keeps the common page header so the buffer manager sees a Meta page, the body follows the header:
+//
+//   magic            8 bytes
+//   format version   4 bytes
+//   page size        4 bytes
+//   sequence         8 bytes (which copy is newer)
+//   next page id     8 bytes
+//   free list head   8 bytes (0 = empty)
+//   checkpoint LSN   8 bytes
+//   catalog length   2 bytes
+//   catalog entries  name length (1 byte), name, meta page id (8 bytes)
+//   ...
+//   checksum         last 4 bytes of the page, CRC-32 of everything before it
+//
+// Everything is validated on open, a copy that fails any check is not used at all.
+
+pub(crate) const SUPERBLOCK_PAGE: PageID = PageID(0);
+// Where the two copies live, a copy's sequence number picks its page
+pub(crate) const SUPERBLOCK_PAGES: [PageID; 2] = [SUPERBLOCK_PAGE, PageID(1)];
+// The first page that can be handed out
+pub(crate) const FIRST_DATA_PAGE: PageID = PageID(2);
+pub(crate) const FORMAT_VERSION: u32 = 2;
+pub(crate) const MAX_TREE_NAME: usize = 64;
+
+const MAGIC: [u8; 8] = *b"InkDB\0sb";
+
+const MAGIC_OFFSET: usize = HEADER_SIZE;
+const VERSION_OFFSET: usize = MAGIC_OFFSET + 8;
+const PAGE_SIZE_OFFSET: usize = VERSION_OFFSET + 4;
+const SEQUENCE_OFFSET: usize = PAGE_SIZE_OFFSET + 4;
+const NEXT_PAGE_OFFSET: usize = SEQUENCE_OFFSET + 8;
+const FREE_HEAD_OFFSET: usize = NEXT_PAGE_OFFSET + 8;
+const CHECKPOINT_OFFSET: usize = FREE_HEAD_OFFSET + 8;
+const CATALOG_LEN_OFFSET: usize = CHECKPOINT_OFFSET + 8;
+const CATALOG_OFFSET: usize = CATALOG_LEN_OFFSET + 2;
+const CHECKSUM_OFFSET: usize = PAGE_SIZE - 4;
+
+pub(crate) type Result<T> = std::result::Result<T, SuperblockError>;
+
+#[derive(Debug)]
+pub(crate) enum SuperblockError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    PageSizeMismatch { found: u32, expected: u32 },
+    ChecksumMismatch { found: u32, computed: u32 },
+    // Passed the checksum but does not make sense - next page id of 0, a catalog entry past the end
+    Corrupt(&'static str),
+    CatalogFull,
+    NameTooLong(usize),
+    TreeExists(String),
+    Pager(PagerError),
+}
+
+impl From<PagerError> for SuperblockError {
+    fn from(err: PagerError) -> Self {
+        SuperblockError::Pager(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Superblock {
+    pub(crate) version: u32,
+    pub(crate) page_size: u32,
+    // Bumped by every store
+    pub(crate) sequence: u64,
+    pub(crate) next_page_id: PageID,
+    pub(crate) free_list_head: Option<PageID>,
+    pub(crate) checkpoint_lsn: u64,
+    // In the order trees were added
+    catalog: Vec<(String, PageID)>,
+}
+
+impl Default for Superblock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Superblock {
+    // A fresh database - nothing but the superblock itself
+    pub(crate) fn new() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            page_size: PAGE_SIZE as u32,
+            sequence: 0,
+            next_page_id: FIRST_DATA_PAGE,
+            free_list_head: None,
+            checkpoint_lsn: 0,
+            catalog: Vec::new(),
+        }
+    }
+
+    // Reads both copies and takes the newest valid one, or writes a fresh superblock if the store is empty. Only
+    // fails if neither copy can be used, with what was wrong with the first.
+    pub(crate) fn open(pager: &dyn Pager) -> Result<Self> {
+        let mut newest: Option<Self> = None;
+        let mut failed = None;
+        for page_id in SUPERBLOCK_PAGES {
+            let mut bytes = [0u8; PAGE_SIZE];
+            let copy = match pager.read_page(page_id, &mut bytes) {
+                Ok(()) => Self::decode(&bytes),
+                Err(PagerError::ShortRead { read: 0, .. }) if page_id == SUPERBLOCK_PAGE => {
+                    return Self::create(pager);
+                }
+                Err(err) => Err(err.into()),
+            };
+            match copy {
+                Ok(copy) if newest.as_ref().is_none_or(|n| copy.sequence > n.sequence) => {
+                    newest = Some(copy)
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    failed.get_or_insert(err);
+                }
+            }
+        }
+        newest.ok_or_else(|| failed.unwrap())
+    }
+
+    // Both copies, so neither page is left for open to trip over
+    fn create(pager: &dyn Pager) -> Result<Self> {
+        let mut superblock = Self::new();
+        superblock.store(pager)?;
+        superblock.store(pager)?;
+        Ok(superblock)
+    }
+
+    // The page the next store goes to - whichever does not hold this copy
+    pub(crate) fn next_page(&self) -> PageID {
+        SUPERBLOCK_PAGES[((self.sequence + 1) % 2) as usize]
+    }
+
+    // Writes the superblock over the older copy and syncs, it is durable once this returns. If it fails the
+    // sequence number is left alone so the next store goes to the same page again - the newer copy is only ever
+    // overwritten once an even newer one is durable.
+    pub(crate) fn store(&mut self, pager: &dyn Pager) -> Result<()> {
+        let page_id = self.next_page();
+        self.sequence += 1;
+        let mut bytes = [0u8; PAGE_SIZE];
+        self.encode(&mut bytes);
+        let stored = pager
+            .write_page(page_id, &bytes)
+            .and_then(|()| pager.sync());
+        if stored.is_err() {
+            self.sequence -= 1;
+        }
+        Ok(stored?)
+    }
+
+    pub(crate) fn encode(&self, bytes: &mut RawPage) {
+        bytes.fill(0);
+        SlottedPageMut::init_new(bytes, PageKind::Meta.into());
+
+        bytes[MAGIC_OFFSET..MAGIC_OFFSET + 8].copy_from_slice(&MAGIC);
+        bytes[VERSION_OFFSET..VERSION_OFFSET + 4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[PAGE_SIZE_OFFSET..PAGE_SIZE_OFFSET + 4]
+            .copy_from_slice(&self.page_size.to_le_bytes());
+        put_u64(bytes, SEQUENCE_OFFSET, self.sequence);
+        put_u64(bytes, NEXT_PAGE_OFFSET, self.next_page_id.into());
+        put_u64(
+            bytes,
+            FREE_HEAD_OFFSET,
+            self.free_list_head.map_or(0, PageID::into),
+        );
+        put_u64(bytes, CHECKPOINT_OFFSET, self.checkpoint_lsn);
+        bytes[CATALOG_LEN_OFFSET..CATALOG_LEN_OFFSET + 2]
+            .copy_from_slice(&(self.catalog.len() as u16).to_le_bytes());
+
+        let mut offset = CATALOG_OFFSET;
+        for (name, meta) in &self.catalog {
+            bytes[offset] = name.len() as u8;
+            bytes[offset + 1..offset + 1 + name.len()].copy_from_slice(name.as_bytes());
+            offset += 1 + name.len();
+            put_u64(bytes, offset, meta.0);
+            offset += 8;
+        }
+
+        let checksum = crc32(&bytes[..CHECKSUM_OFFSET]);
+        bytes[CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    pub(crate) fn decode(bytes: &RawPage) -> Result<Self> {
+        if bytes[MAGIC_OFFSET..MAGIC_OFFSET + 8] != MAGIC {
+            return Err(SuperblockError::BadMagic);
+        }
+        // Checked before anything else we read is trusted
+        let found = u32::from_le_bytes(bytes[CHECKSUM_OFFSET..].try_into().unwrap());
+        let computed = crc32(&bytes[..CHECKSUM_OFFSET]);
+        if found != computed {
+            return Err(SuperblockError::ChecksumMismatch { found, computed });
+        }
+
+        let version = get_u32(bytes, VERSION_OFFSET);
+        if version != FORMAT_VERSION {
+            return Err(SuperblockError::UnsupportedVersion(version));
+        }
+        let page_size = get_u32(bytes, PAGE_SIZE_OFFSET);
+        if page_size != PAGE_SIZE as u32 {
+            return Err(SuperblockError::PageSizeMismatch {
+                found: page_size,
+                expected: PAGE_SIZE as u32,
+            });
+        }
+
+        let next_page_id = PageID(get_u64(bytes, NEXT_PAGE_OFFSET));
+        if next_page_id.into() < FIRST_DATA_PAGE.into() {
+            return Err(SuperblockError::Corrupt(
+                "next page id inside the superblock",
+            ));
+        }
+        // Any page id we are pointed at has to be one that was handed out
+        let valid =
+            |page_id: u64| page_id >= FIRST_DATA_PAGE.into() && page_id < next_page_id.into();
+
+        let free_list_head = match get_u64(bytes, FREE_HEAD_OFFSET) {
+            0 => None,
+            head if valid(head) => Some(PageID(head)),
+            _ => return Err(SuperblockError::Corrupt("free list head out of range")),
+        };
+
+        let entries = u16::from_le_bytes(
+            bytes[CATALOG_LEN_OFFSET..CATALOG_LEN_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        );
+        let mut catalog = Vec::with_capacity(entries as usize);
+        let mut offset = CATALOG_OFFSET;
+        for _ in 0..entries {
+            let len = bytes[offset] as usize;
+            if len == 0 || len > MAX_TREE_NAME || offset + 1 + len + 8 > CHECKSUM_OFFSET {
+                return Err(SuperblockError::Corrupt("catalog entry out of bounds"));
+            }
+            let name = std::str::from_utf8(&bytes[offset + 1..offset + 1 + len])
+                .map_err(|_| SuperblockError::Corrupt("tree name is not utf-8"))?;
+            offset += 1 + len;
+            let meta = get_u64(bytes, offset);
+            offset += 8;
+            if !valid(meta) {
+                return Err(SuperblockError::Corrupt("tree meta page out of range"));
+            }
+            catalog.push((name.to_string(), PageID(meta)));
+        }
+
+        Ok(Self {
+            version,
+            page_size,
+            sequence: get_u64(bytes, SEQUENCE_OFFSET),
+            next_page_id,
+            free_list_head,
+            checkpoint_lsn: get_u64(bytes, CHECKPOINT_OFFSET),
+            catalog,
+        })
+    }
+
+    // ---------- Catalog ----------//
+
+    pub(crate) fn tree(&self, name: &str) -> Option<PageID> {
+        self.catalog
+            .iter()
+            .find(|(tree, _)| tree == name)
+            .map(|(_, meta)| *meta)
+    }
+
+    pub(crate) fn trees(&self) -> impl Iterator<Item = (&str, PageID)> {
+        self.catalog
+            .iter()
+            .map(|(name, meta)| (name.as_str(), *meta))
+    }
+
+    pub(crate) fn add_tree(&mut self, name: &str, meta: PageID) -> Result<()> {
+        if name.is_empty() || name.len() > MAX_TREE_NAME {
+            return Err(SuperblockError::NameTooLong(name.len()));
+        }
+        if self.tree(name).is_some() {
+            return Err(SuperblockError::TreeExists(name.to_string()));
+        }
+        if self.catalog_end() + 1 + name.len() + 8 > CHECKSUM_OFFSET {
+            return Err(SuperblockError::CatalogFull);
+        }
+        self.catalog.push((name.to_string(), meta));
+        Ok(())
+    }
+
+    pub(crate) fn remove_tree(&mut self, name: &str) -> Option<PageID> {
+        let idx = self.catalog.iter().position(|(tree, _)| tree == name)?;
+        Some(self.catalog.remove(idx).1)
+    }
+
+    fn catalog_end(&self) -> usize {
+        CATALOG_OFFSET
+            + self
+                .catalog
+                .iter()
+                .map(|(name, _)| 1 + name.len() + 8)
+                .sum::<usize>()
+    }
+}
+
+fn put_u64(bytes: &mut RawPage, offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u64(bytes: &RawPage, offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn get_u32(bytes: &RawPage, offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// CRC-32 (IEEE), bit at a time - one page, only on open and store, so no table
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::page_kind_of;
+    use crate::pager::faulty_pager::{Fault, FaultOp, FaultRule, FaultyPager};
+    use crate::pager::mem_pager::InMemoryPager;
+    use std::sync::Arc;
+
+    #[test]
+    fn superblock_round_trips_through_pages_zero_and_one() {
+        let pager = InMemoryPager::new();
+
+        // An empty store gets a fresh superblock written to both pages
+        let mut superblock = Superblock::open(&pager).unwrap();
+        assert_eq!(
+            superblock,
+            Superblock {
+                sequence: 2,
+                ..Superblock::new()
+            }
+        );
+        assert_eq!(pager.page_count(), 2);
+
+        superblock.next_page_id = PageID(40);
+        superblock.free_list_head = Some(PageID(12));
+        superblock.checkpoint_lsn = 900;
+        superblock.add_tree("users", PageID(3)).unwrap();
+        superblock.add_tree("orders", PageID(7)).unwrap();
+        assert!(matches!(
+            superblock.add_tree("users", PageID(9)),
+            Err(SuperblockError::TreeExists(_))
+        ));
+        superblock.store(&pager).unwrap();
+        assert_eq!(superblock.sequence, 3);
+
+        let reopened = Superblock::open(&pager).unwrap();
+        assert_eq!(reopened, superblock);
+        assert_eq!(reopened.tree("orders"), Some(PageID(7)));
+        assert_eq!(
+            reopened.trees().collect::<Vec<_>>(),
+            vec![("users", PageID(3)), ("orders", PageID(7))]
+        );
+
+        let mut bytes = [0u8; PAGE_SIZE];
+        for page_id in SUPERBLOCK_PAGES {
+            pager.read_page(page_id, &mut bytes).unwrap();
+            assert_eq!(page_kind_of(&bytes), PageKind::Meta);
+        }
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn a_torn_store_falls_back_to_the_older_copy() {
+        let pager = FaultyPager::new(Arc::new(InMemoryPager::new()));
+        let mut superblock = Superblock::open(&pager).unwrap();
+        superblock.add_tree("users", PageID(3)).unwrap();
+        superblock.next_page_id = PageID(10);
+        superblock.store(&pager).unwrap();
+        let durable = superblock.clone();
+
+        // The power goes part way through writing page 0, only its first sector makes it
+        assert_eq!(superblock.next_page(), SUPERBLOCK_PAGE);
+        pager.inject(
+            FaultRule::new(FaultOp::Write, Fault::TornWrite { sectors: 1 }).page(SUPERBLOCK_PAGE),
+        );
+        superblock.add_tree("orders", PageID(7)).unwrap();
+        superblock.next_page_id = PageID(20);
+        superblock.store(&pager).unwrap();
+        pager.crash().unwrap();
+
+        let mut bytes = [0u8; PAGE_SIZE];
+        pager.read_page(SUPERBLOCK_PAGE, &mut bytes).unwrap();
+        assert!(matches!(
+            Superblock::decode(&bytes),
+            Err(SuperblockError::ChecksumMismatch { .. })
+        ));
+        let mut reopened = Superblock::open(&pager).unwrap();
+        assert_eq!(reopened, durable);
+
+        // The next store replaces the torn copy, not the one we fell back to
+        assert_eq!(reopened.next_page(), SUPERBLOCK_PAGE);
+        reopened.checkpoint_lsn = 5;
+        reopened.store(&pager).unwrap();
+        assert_eq!(Superblock::open(&pager).unwrap(), reopened);
+
+        // With neither copy usable there is nothing to open
+        for page_id in SUPERBLOCK_PAGES {
+            pager.write_page(page_id, &[0u8; PAGE_SIZE]).unwrap();
+        }
+        assert!(matches!(
+            Superblock::open(&pager),
+            Err(SuperblockError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn invalid_superblocks_are_rejected() {
+        let mut superblock = Superblock::new();
+        superblock.next_page_id = PageID(10);
+        superblock.add_tree("t", PageID(4)).unwrap();
+        let mut good = [0u8; PAGE_SIZE];
+        superblock.encode(&mut good);
+        assert!(Superblock::decode(&good).is_ok());
+
+        // Not ours at all
+        let mut bytes = good;
+        bytes[MAGIC_OFFSET] = b'X';
+        assert!(matches!(
+            Superblock::decode(&bytes),
+            Err(SuperblockError::BadMagic)
+        ));
+
+        // A flipped bit anywhere in the body
+        let mut bytes = good;
+        bytes[CATALOG_OFFSET + 2] ^= 1;
+        assert!(matches!(
+            Superblock::decode(&bytes),
+            Err(SuperblockError::ChecksumMismatch { .. })
+        ));
+
+        // Valid checksums over values we can't accept
+        let mut other = superblock.clone();
+        other.version = FORMAT_VERSION + 1;
+        other.encode(&mut bytes);
+        assert!(matches!(
+            Superblock::decode(&bytes),
+            Err(SuperblockError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+        ));
+
+        let mut other = superblock.clone();
+        other.page_size = 8192;
+        other.encode(&mut bytes);
+        assert!(matches!(
+            Superblock::decode(&bytes),
+            Err(SuperblockError::PageSizeMismatch { found: 8192, .. })
+        ));
+
+        // Neither past the last page handed out nor at the second copy
+        for head in [PageID(10), PageID(1)] {
+            let mut other = superblock.clone();
+            other.free_list_head = Some(head);
+            other.encode(&mut bytes);
+            assert!(matches!(
+                Superblock::decode(&bytes),
+                Err(SuperblockError::Corrupt(_))
+            ));
+        }
+
+        // The catalog stops taking trees once the page is full
+        let mut full = Superblock::new();
+        let name = "n".repeat(MAX_TREE_NAME);
+        let mut added = 0;
+        loop {
+            match full.add_tree(&format!("{}{}", &name[4..], added + 1000), PageID(2)) {
+                Ok(()) => added += 1,
+                Err(SuperblockError::CatalogFull) => break,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+        // Every entry is a length byte, the name and the meta page id
+        assert_eq!(
+            added,
+            (CHECKSUM_OFFSET - CATALOG_OFFSET) / (1 + MAX_TREE_NAME + 8)
+        );
+        full.next_page_id = PageID(3);
+        full.encode(&mut bytes);
+        let decoded = Superblock::decode(&bytes).unwrap();
+        assert_eq!(decoded, full);
+        let names: Vec<_> = decoded.trees().map(|(name, _)| name.to_string()).collect();
+        let expected: Vec<_> = (0..added)
+            .map(|n| format!("{}{}", &name[4..], n + 1000))
+            .collect();
+        assert_eq!(names, expected);
+        assert!(decoded.trees().all(|(_, meta)| meta == PageID(2)));
+    }
+}
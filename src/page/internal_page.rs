@@ -123,6 +123,7 @@ impl<'page> IndexPageMut<'page> {
         }
     }
 
+    // No sibling is stored as PageID(0), which is always the superblock so never a real sibling
     pub(crate) fn has_right_sibling(&self) -> bool {
         if let Ok(special) = self.page.get_special_ref() {
             special[RIGHT_SIBLING_OFFSET..RIGHT_SIBLING_OFFSET + 8] != [0u8; 8]
@@ -131,6 +132,14 @@ impl<'page> IndexPageMut<'page> {
         }
     }
 
+    // Overwrites the child pointer of an existing cell - the buffer manager uses this to swizzle pointers
+    pub(crate) fn set_child_ptr(&mut self, slot_id: SlotID, child_ptr: PageID) -> Result<()> {
+        let cell = self.page.cell_slice_mut_from_id(slot_id)?;
+        cell[CHILD_PTR_OFFSET..CHILD_PTR_OFFSET + 8]
+            .copy_from_slice(&child_ptr.into().to_le_bytes());
+        Ok(())
+    }
+
     pub(crate) fn add_cell_append_slot_entry(&mut self, cell: IndexCellOwned) -> Result<()> {
         // We take an owned IndexCell which we then consume and store as bytes
         let bytes = cell.0.as_ref();
@@ -166,30 +175,70 @@ impl<'page> IndexPageRef<'page> {
     }
 
     pub(crate) fn find_child_ptr(&self, key: &[u8]) -> Result<Option<PageID>> {
-        let mut high_key = false;
-        if self.has_right_sibling() {
-            //TODO - For now we are returning wrapped PageError. We may want to handle the PageError differently
-            // and give a wrapped error with context
-            let hkc = self.page.cell_slice_from_id(SlotID(0))?;
-            let high_key_cell = IndexCell::from(hkc);
-            high_key = true;
-            if key > high_key_cell.get_key() {
-                return Ok(self.get_right_sibling());
-            }
-        };
+        if self.key_beyond_high_key(key)? {
+            return Ok(self.get_right_sibling());
+        }
+
+        match self.find_child_slot(key)? {
+            Some(slot_id) => Ok(Some(self.child_ptr_at(slot_id)?)),
+            None => Ok(None),
+        }
+    }
+
+    // The slot of the child to descend into for the key, or None if the key belongs to the right sibling or
+    // there is no child for it on this page
+    pub(crate) fn find_child_slot(&self, key: &[u8]) -> Result<Option<SlotID>> {
+        if self.key_beyond_high_key(key)? {
+            return Ok(None);
+        }
 
-        let skip = if high_key { 1 } else { 0 };
+        let skip = self.first_child_slot();
 
-        for se in self.page.slot_dir_ref().iter().skip(skip) {
+        for (idx, se) in self.page.slot_dir_ref().iter().enumerate().skip(skip) {
             let cell = IndexCell::from(self.page.cell_slice_from_entry(se));
             let cell_key = cell.get_key();
             if key < cell_key {
-                return Ok(Some(cell.get_value_ptr()));
+                return Ok(Some(SlotID(idx as u16)));
             }
         }
         Ok(None)
     }
 
+    pub(crate) fn child_ptr_at(&self, slot_id: SlotID) -> Result<PageID> {
+        let cell = IndexCell::from(self.page.cell_slice_from_id(slot_id)?);
+        Ok(cell.get_value_ptr())
+    }
+
+    // Every child pointer on the page with its slot, skipping the high key
+    pub(crate) fn child_slots(&self) -> Vec<(SlotID, PageID)> {
+        self.page
+            .slot_dir_ref()
+            .iter()
+            .enumerate()
+            .skip(self.first_child_slot())
+            .map(|(idx, se)| {
+                let cell = IndexCell::from(self.page.cell_slice_from_entry(se));
+                (SlotID(idx as u16), cell.get_value_ptr())
+            })
+            .collect()
+    }
+
+    // When there is a right sibling slot 0 holds the high key rather than a child
+    fn first_child_slot(&self) -> usize {
+        if self.has_right_sibling() { 1 } else { 0 }
+    }
+
+    fn key_beyond_high_key(&self, key: &[u8]) -> Result<bool> {
+        if !self.has_right_sibling() {
+            return Ok(false);
+        }
+        //TODO - For now we are returning wrapped PageError. We may want to handle the PageError differently
+        // and give a wrapped error with context
+        let hkc = self.page.cell_slice_from_id(SlotID(0))?;
+        let high_key_cell = IndexCell::from(hkc);
+        Ok(key > high_key_cell.get_key())
+    }
+
     //
 
     pub(crate) fn has_right_sibling(&self) -> bool {
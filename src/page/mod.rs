@@ -30,6 +30,17 @@ impl From<u64> for PageID {
 
 // TODO May need to implement PageID resolver for pointer address and offset from page id
 
+// The page type byte lives in the common header so the buffer manager can tell what kind of page it just
+// read from disk without knowing anything else about the layout
+pub(crate) fn page_kind_of(bytes: &RawPage) -> PageKind {
+    PageType::from(SlottedPageRef::from_bytes(bytes).get_page_type()).page_kind()
+}
+
+// Same for the LSN of the last change to the page - the buffer manager needs it to follow the WAL rule
+pub(crate) fn page_lsn_of(bytes: &RawPage) -> u64 {
+    SlottedPageRef::from_bytes(bytes).get_lsn()
+}
+
 #[derive(Eq, Hash, PartialEq, Debug)]
 pub(crate) struct SlotID(pub u16);
 
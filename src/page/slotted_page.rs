@@ -134,6 +134,7 @@ impl<'a> SlottedPageMut<'a> {
         // TODO Finish
         // Need to take free space and then minus that from the total free space (free_start - 4096(including if special offset))
         // Then test against iterating through and manually counting
+        0
     }
 
     #[inline(always)]
@@ -445,6 +446,14 @@ impl<'a> SlottedPageMut<'a> {
         }
     }
 
+    // For type layers which rewrite part of a cell in place without changing its size (child pointers)
+    pub(super) fn cell_slice_mut_from_id(&mut self, slot_id: SlotID) -> Result<&'_ mut [u8]> {
+        let cell = self.cell_slice_from_id(slot_id)?;
+        let offset = cell.as_ptr() as usize - self.bytes.as_ptr() as usize;
+        let end = offset + cell.len();
+        Ok(self.bytes[offset..end].as_mut())
+    }
+
     pub(super) fn cell_slice_from_entry(&self, se: SlotEntry) -> &'_ [u8] {
         // We have a valid slot entry. The only way we would be able to get this is if there also exists a valid
         // cell area
@@ -0,0 +1,341 @@
+use crate::meta::superblock::{FIRST_DATA_PAGE, Superblock, SuperblockError};
+use crate::page::{
+    HEADER_SIZE, PAGE_SIZE, PageID, PageKind, RawPage, SlottedPageMut, page_kind_of,
+};
+use crate::pager::pager::{Pager, PagerError};
+use std::sync::{Arc, Mutex};
+
+//NOTE: Hands out page ids and takes them back. Allocation state lives in the superblock - the next page id
+// never handed out and the head of the free list - so it survives restarts with nothing else to recover.
+//
+// Freed pages form a singly linked list on disk: each is stamped PT_FREE in the common header with the id of
+// the next free page after it (0 ends the list, page 0 being the superblock). Allocation pops the head, and only
+// when the list is empty takes a new page from the end of the file, which grows in extents of `extent_pages`
+// at a time. New pages always come back zeroed.
+//
+// Crash consistency comes from ordering - a page is always written (and synced) before the superblock points at
+// it, and the superblock is synced before a popped page is reused:
+//
+//   free      stamp the page with next = head, sync, then the superblock with head = page
+//   allocate  the superblock with head = next (or next page id bumped), then zero the page
+//   extend    grow the file first, the superblock only counts pages already in it
+//
+// A crash between the steps can leak a page (stamped free but off the list, or handed out and never used) but
+// can never put a page on the list twice or hand out a page that is in use. Pages grown into the file but not
+// yet counted by the superblock are picked up again on open. A superblock write torn by the crash is no different
+// from one that never happened - open falls back to the other copy (see superblock.rs).
+//
+// Callers must have discarded a page from the buffer pool before freeing it - the allocator writes straight
+// through the pager.
+//
+// TODO: Every allocate and free syncs the superblock. Once the WAL exists these should be logged instead.
+
+const NEXT_FREE_OFFSET: usize = HEADER_SIZE;
+
+pub(crate) type Result<T> = std::result::Result<T, AllocatorError>;
+
+#[derive(Debug)]
+pub(crate) enum AllocatorError {
+    Superblock(SuperblockError),
+    Pager(PagerError),
+    // The superblock or a free page pointed at a page that is not free - the list is damaged
+    NotFree(PageID),
+    // Freeing a superblock page, a page never handed out or one that is already free
+    InvalidFree(PageID),
+}
+
+impl From<SuperblockError> for AllocatorError {
+    fn from(err: SuperblockError) -> Self {
+        match err {
+            SuperblockError::Pager(err) => AllocatorError::Pager(err),
+            err => AllocatorError::Superblock(err),
+        }
+    }
+}
+
+impl From<PagerError> for AllocatorError {
+    fn from(err: PagerError) -> Self {
+        AllocatorError::Pager(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AllocatorConfig {
+    // How many pages the file grows by when the free list is empty
+    pub(crate) extent_pages: u64,
+}
+
+impl Default for AllocatorConfig {
+    fn default() -> Self {
+        Self { extent_pages: 16 }
+    }
+}
+
+struct AllocState {
+    superblock: Superblock,
+    // Pages in the file - [next_page_id, file_pages) were grown into the file and not handed out yet
+    file_pages: u64,
+}
+
+pub(crate) struct PageAllocator {
+    pager: Arc<dyn Pager>,
+    config: AllocatorConfig,
+    state: Mutex<AllocState>,
+}
+
+impl PageAllocator {
+    pub(crate) fn open(pager: Arc<dyn Pager>, config: AllocatorConfig) -> Result<Self> {
+        assert!(config.extent_pages > 0, "extents of zero pages");
+        let superblock = Superblock::open(pager.as_ref())?;
+        // Anything the file holds past next_page_id is an extent we grew before a restart
+        let file_pages = pager.page_count().max(superblock.next_page_id.into());
+        Ok(Self {
+            pager,
+            config,
+            state: Mutex::new(AllocState {
+                superblock,
+                file_pages,
+            }),
+        })
+    }
+
+    pub(crate) fn superblock(&self) -> Superblock {
+        self.state.lock().unwrap().superblock.clone()
+    }
+
+    // Changes the superblock (the catalog, the checkpoint LSN) and makes it durable. Allocation fields are ours.
+    pub(crate) fn update_superblock(&self, f: impl FnOnce(&mut Superblock)) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut superblock = state.superblock.clone();
+        f(&mut superblock);
+        debug_assert_eq!(superblock.next_page_id, state.superblock.next_page_id);
+        debug_assert_eq!(superblock.free_list_head, state.superblock.free_list_head);
+        superblock.store(self.pager.as_ref())?;
+        state.superblock = superblock;
+        Ok(())
+    }
+
+    pub(crate) fn allocate(&self) -> Result<PageID> {
+        let mut state = self.state.lock().unwrap();
+        let mut superblock = state.superblock.clone();
+
+        let page_id = match superblock.free_list_head {
+            Some(head) => {
+                let page = self.read_free(head)?;
+                superblock.free_list_head = next_free(&page);
+                head
+            }
+            None => {
+                let page_id = superblock.next_page_id;
+                if page_id.into() >= state.file_pages {
+                    state.file_pages = self.extend(page_id)?;
+                }
+                superblock.next_page_id = PageID(page_id.into() + 1);
+                page_id
+            }
+        };
+
+        superblock.store(self.pager.as_ref())?;
+        state.superblock = superblock;
+        drop(state);
+
+        // Off the list for good now - a crash before this leaves a stamped page nobody points at
+        self.pager.write_page(page_id, &[0u8; PAGE_SIZE])?;
+        Ok(page_id)
+    }
+
+    pub(crate) fn free(&self, page_id: PageID) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if page_id.into() < FIRST_DATA_PAGE.into()
+            || page_id.into() >= state.superblock.next_page_id.into()
+        {
+            return Err(AllocatorError::InvalidFree(page_id));
+        }
+        let mut current = [0u8; PAGE_SIZE];
+        self.pager.read_page(page_id, &mut current)?;
+        if page_kind_of(&current) == PageKind::Free {
+            return Err(AllocatorError::InvalidFree(page_id));
+        }
+
+        let mut page = [0u8; PAGE_SIZE];
+        stamp_free(&mut page, state.superblock.free_list_head);
+        self.pager.write_page(page_id, &page)?;
+        self.pager.sync()?;
+
+        let mut superblock = state.superblock.clone();
+        superblock.free_list_head = Some(page_id);
+        superblock.store(self.pager.as_ref())?;
+        state.superblock = superblock;
+        Ok(())
+    }
+
+    // Pages on the free list, walking it from the head
+    pub(crate) fn free_pages(&self) -> Result<Vec<PageID>> {
+        let state = self.state.lock().unwrap();
+        let mut pages = Vec::new();
+        let mut next = state.superblock.free_list_head;
+        while let Some(page_id) = next {
+            // A cycle would have us walk forever
+            if pages.len() as u64 >= state.superblock.next_page_id.into() {
+                return Err(AllocatorError::NotFree(page_id));
+            }
+            pages.push(page_id);
+            next = next_free(&self.read_free(page_id)?);
+        }
+        Ok(pages)
+    }
+
+    // Grows the file by an extent starting at `from` and returns how many pages it holds now
+    fn extend(&self, from: PageID) -> Result<u64> {
+        let end = from.into() + self.config.extent_pages;
+        while self.pager.page_count() < end {
+            self.pager.allocate_page()?;
+        }
+        self.pager.sync()?;
+        Ok(self.pager.page_count())
+    }
+
+    fn read_free(&self, page_id: PageID) -> Result<RawPage> {
+        let mut page = [0u8; PAGE_SIZE];
+        self.pager.read_page(page_id, &mut page)?;
+        if page_kind_of(&page) != PageKind::Free {
+            return Err(AllocatorError::NotFree(page_id));
+        }
+        Ok(page)
+    }
+}
+
+fn stamp_free(page: &mut RawPage, next: Option<PageID>) {
+    SlottedPageMut::init_new(page, PageKind::Free.into());
+    page[NEXT_FREE_OFFSET..NEXT_FREE_OFFSET + 8]
+        .copy_from_slice(&next.map_or(0, PageID::into).to_le_bytes());
+}
+
+fn next_free(page: &RawPage) -> Option<PageID> {
+    match u64::from_le_bytes(
+        page[NEXT_FREE_OFFSET..NEXT_FREE_OFFSET + 8]
+            .try_into()
+            .unwrap(),
+    ) {
+        0 => None,
+        next => Some(PageID(next)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta::superblock::SUPERBLOCK_PAGE;
+    use crate::pager::faulty_pager::{Fault, FaultOp, FaultRule, FaultyPager};
+    use crate::pager::mem_pager::InMemoryPager;
+
+    fn config(extent_pages: u64) -> AllocatorConfig {
+        AllocatorConfig { extent_pages }
+    }
+
+    #[test]
+    fn freed_pages_are_reused_and_survive_a_restart() {
+        let store = Arc::new(InMemoryPager::new());
+        let allocator = PageAllocator::open(store.clone(), config(4)).unwrap();
+
+        // The file grows an extent at a time, pages 0 and 1 being the superblock
+        let first: Vec<_> = (0..3).map(|_| allocator.allocate().unwrap()).collect();
+        assert_eq!(first, vec![PageID(2), PageID(3), PageID(4)]);
+        assert_eq!(store.page_count(), 6);
+        allocator.allocate().unwrap();
+        allocator.allocate().unwrap();
+        assert_eq!(store.page_count(), 10);
+
+        store.write_page(PageID(3), &[7u8; PAGE_SIZE]).unwrap();
+        allocator.free(PageID(3)).unwrap();
+        allocator.free(PageID(5)).unwrap();
+        let mut page = [0u8; PAGE_SIZE];
+        store.read_page(PageID(3), &mut page).unwrap();
+        assert_eq!(page_kind_of(&page), PageKind::Free);
+        assert_eq!(allocator.free_pages().unwrap(), vec![PageID(5), PageID(3)]);
+
+        // Freeing twice, either superblock page, or pages that were never handed out, is refused
+        for page_id in [3, 0, 1, 7] {
+            assert!(matches!(
+                allocator.free(PageID(page_id)),
+                Err(AllocatorError::InvalidFree(_))
+            ));
+        }
+        drop(allocator);
+
+        let allocator = PageAllocator::open(store.clone(), config(4)).unwrap();
+        assert_eq!(allocator.free_pages().unwrap(), vec![PageID(5), PageID(3)]);
+        assert_eq!(allocator.allocate().unwrap(), PageID(5));
+        assert_eq!(allocator.allocate().unwrap(), PageID(3));
+        // Reused pages come back zeroed
+        store.read_page(PageID(3), &mut page).unwrap();
+        assert_eq!(page, [0u8; PAGE_SIZE]);
+
+        // The rest of the last extent is used before the file grows again
+        assert_eq!(allocator.allocate().unwrap(), PageID(7));
+        assert_eq!(store.page_count(), 10);
+        assert_eq!(allocator.superblock().next_page_id, PageID(8));
+    }
+
+    #[test]
+    fn crashes_leak_pages_but_never_corrupt_the_list() {
+        let store = Arc::new(InMemoryPager::new());
+        let pager = Arc::new(FaultyPager::new(store.clone()));
+        let allocator = PageAllocator::open(pager.clone(), config(2)).unwrap();
+        for _ in 0..4 {
+            allocator.allocate().unwrap();
+        }
+        allocator.free(PageID(2)).unwrap();
+        let next_superblock = |allocator: &PageAllocator| allocator.superblock().next_page();
+
+        // The page is stamped but the superblock write fails - it is not on the list
+        pager.inject(FaultRule::new(FaultOp::Write, Fault::Eio).page(next_superblock(&allocator)));
+        assert!(allocator.free(PageID(4)).is_err());
+        pager.crash().unwrap();
+
+        let allocator = PageAllocator::open(pager.clone(), config(2)).unwrap();
+        assert_eq!(allocator.free_pages().unwrap(), vec![PageID(2)]);
+
+        // The file grows but the superblock never records it - the extent is found again on open rather than
+        // growing the file a second time
+        let pages = store.page_count();
+        assert_eq!(allocator.allocate().unwrap(), PageID(2));
+        pager.inject(FaultRule::new(FaultOp::Write, Fault::Eio).page(next_superblock(&allocator)));
+        assert!(allocator.allocate().is_err());
+        assert_eq!(store.page_count(), pages + 2);
+
+        let allocator = PageAllocator::open(pager.clone(), config(2)).unwrap();
+        assert_eq!(allocator.allocate().unwrap(), PageID(6));
+        assert_eq!(allocator.allocate().unwrap(), PageID(7));
+        assert_eq!(store.page_count(), pages + 2);
+        assert_eq!(allocator.allocate().unwrap(), PageID(8));
+        assert_eq!(store.page_count(), pages + 4);
+
+        // The power goes while page 0 is half written, after a free and after an allocate. Each time open falls
+        // back to the copy in page 1 - the catalog is still there and the list is as it was before.
+        allocator
+            .update_superblock(|superblock| superblock.add_tree("users", PageID(3)).unwrap())
+            .unwrap();
+        let torn = || {
+            FaultRule::new(FaultOp::Write, Fault::TornWrite { sectors: 1 }).page(SUPERBLOCK_PAGE)
+        };
+        allocator.free(PageID(6)).unwrap();
+        assert_eq!(next_superblock(&allocator), SUPERBLOCK_PAGE);
+        let before = allocator.free_pages().unwrap();
+
+        pager.inject(torn());
+        allocator.free(PageID(7)).unwrap();
+        pager.crash().unwrap();
+        let allocator = PageAllocator::open(pager.clone(), config(2)).unwrap();
+        assert_eq!(allocator.free_pages().unwrap(), before);
+        assert_eq!(allocator.superblock().tree("users"), Some(PageID(3)));
+
+        pager.inject(torn());
+        let popped = allocator.allocate().unwrap();
+        pager.crash().unwrap();
+        let allocator = PageAllocator::open(pager.clone(), config(2)).unwrap();
+        assert_eq!(allocator.free_pages().unwrap(), before);
+        assert_eq!(allocator.allocate().unwrap(), popped);
+        assert_eq!(allocator.superblock().tree("users"), Some(PageID(3)));
+    }
+}
@@ -0,0 +1,544 @@
+use crate::page::{PageID, RawPage};
+use crate::pager::file_pager::FilePager;
+use crate::pager::pager::{IoOp, Pager, Result};
+use std::collections::VecDeque;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+//NOTE: Completion driven page I/O for the threads that want many pages moving at once - the background writer
+// writing out a batch of dirty frames, prefetch reading ahead of a scan. Requests are submitted in batches and
+// finish in whatever order the device gets to them; each carries a tag which its completion hands back.
+//
+// Requests point straight at the memory to move - normally a buffer manager frame, which sits page aligned in
+// the arena so O_DIRECT files need no bounce (anything else is bounced through an aligned copy). Nothing owns
+// that memory on our side, so submit is unsafe: the caller keeps every buffer alive, and keeps anyone from
+// writing to it (or, for a read, looking at it) until its completion comes back.
+//
+// Two backends:
+//   - Uring, with the `io-uring` feature on Linux over a FilePager. Requests go into the submission queue with
+//     the file's fd, at most `depth` in flight at once, and complete asynchronously
+//   - Sync, the fallback - over any pager, or when the kernel will not give us a ring (too old, io_uring
+//     disabled, seccomp). Each request is carried out with the pager's own read_page/write_page during submit
+//     and its completion queued, so callers drive both backends the same way
+//
+// A segmented store (segment_pager.rs) always gets Sync. The ring needs one fd that stays open for as long as
+// the queue does, and a SegmentedPager opens and closes segment files under us as its handle cache turns over.
+//
+// An AsyncPager belongs to the one thread driving it, which is why everything takes &mut self. Dropping it waits
+// for anything still in flight.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AsyncBackend {
+    Uring,
+    Sync,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IoRequest {
+    op: IoOp,
+    page_id: PageID,
+    buf: NonNull<RawPage>,
+    tag: u64,
+}
+
+impl IoRequest {
+    pub(crate) fn read(page_id: PageID, buf: NonNull<RawPage>, tag: u64) -> Self {
+        Self {
+            op: IoOp::Read,
+            page_id,
+            buf,
+            tag,
+        }
+    }
+
+    pub(crate) fn write(page_id: PageID, buf: NonNull<RawPage>, tag: u64) -> Self {
+        Self {
+            op: IoOp::Write,
+            page_id,
+            buf,
+            tag,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct IoCompletion {
+    pub(crate) tag: u64,
+    pub(crate) page_id: PageID,
+    pub(crate) op: IoOp,
+    pub(crate) result: Result<()>,
+}
+
+pub(crate) struct AsyncPager {
+    pager: Arc<dyn Pager>,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    ring: Option<uring::UringQueue>,
+    // Completions not handed out yet
+    ready: VecDeque<IoCompletion>,
+}
+
+// The only pointers we hold are to buffers the submitter vouched for until their completions come back, and
+// those come back to whichever thread owns us
+unsafe impl Send for AsyncPager {}
+
+impl AsyncPager {
+    // Synchronous over any pager
+    pub(crate) fn new(pager: Arc<dyn Pager>) -> Self {
+        Self {
+            pager,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            ring: None,
+            ready: VecDeque::new(),
+        }
+    }
+
+    // An io_uring of `depth` entries over the file where we can have one, synchronous where we can't. Only a
+    // single file pager can have one - anything else goes through new and is synchronous, see above.
+    pub(crate) fn for_file(file: Arc<FilePager>, depth: u32) -> Self {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Ok(ring) = uring::UringQueue::new(file.clone(), depth) {
+            return Self {
+                pager: file,
+                ring: Some(ring),
+                ready: VecDeque::new(),
+            };
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        let _ = depth;
+        Self::new(file)
+    }
+
+    pub(crate) fn backend(&self) -> AsyncBackend {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if self.ring.is_some() {
+            return AsyncBackend::Uring;
+        }
+        AsyncBackend::Sync
+    }
+
+    // Submitted and not yet handed back by complete
+    pub(crate) fn in_flight(&self) -> usize {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &self.ring {
+            return ring.in_flight() + self.ready.len();
+        }
+        self.ready.len()
+    }
+
+    // Starts every request. An error means the ring itself failed - requests queued before it are still in
+    // flight and complete as usual.
+    //
+    // Safety: each request's buffer must stay valid, and untouched by anyone else, until complete hands back
+    // its completion
+    pub(crate) unsafe fn submit(&mut self, requests: &[IoRequest]) -> Result<()> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &mut self.ring {
+            for request in requests {
+                unsafe { ring.push(request, &mut self.ready)? };
+            }
+            return ring.submit();
+        }
+
+        for request in requests {
+            let result = match request.op {
+                IoOp::Read => self
+                    .pager
+                    .read_page(request.page_id, unsafe { &mut *request.buf.as_ptr() }),
+                IoOp::Write => self
+                    .pager
+                    .write_page(request.page_id, unsafe { request.buf.as_ref() }),
+                IoOp::Extend => unreachable!("requests are reads or writes"),
+            };
+            self.ready.push_back(IoCompletion {
+                tag: request.tag,
+                page_id: request.page_id,
+                op: request.op,
+                result,
+            });
+        }
+        Ok(())
+    }
+
+    // Moves finished requests into `out`, waiting until at least `min` have (or nothing is left in flight).
+    // Returns how many were added.
+    pub(crate) fn complete(&mut self, min: usize, out: &mut Vec<IoCompletion>) -> Result<usize> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &mut self.ring {
+            let want = min.saturating_sub(self.ready.len());
+            ring.reap(want, &mut self.ready)?;
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        let _ = min;
+
+        let done = self.ready.len();
+        out.extend(self.ready.drain(..));
+        Ok(done)
+    }
+
+    // Waits until nothing is left in flight, moving every completion into `out`. A failed wait is tried again
+    // rather than given up on - the kernel may still be moving pages into or out of buffers the caller can only
+    // release once their completions are back. Returns the first failure.
+    pub(crate) fn drain(&mut self, out: &mut Vec<IoCompletion>) -> Result<()> {
+        let mut first = Ok(());
+        while self.in_flight() > 0 {
+            if let Err(err) = self.complete(self.in_flight(), out) {
+                if first.is_ok() {
+                    first = Err(err);
+                }
+                std::thread::yield_now();
+            }
+        }
+        first
+    }
+}
+
+// Stands in for a kernel that will not give us a ring, for tests on the thread that sets it
+#[cfg(test)]
+thread_local! {
+    static REFUSE_RING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring {
+    use super::*;
+    use crate::page::PAGE_SIZE;
+    use crate::pager::file_pager::{AlignedPage, DIRECT_IO_ALIGN, IoMode};
+    use crate::pager::pager::PagerError;
+    use io_uring::{IoUring, opcode, types};
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    struct InFlight {
+        request: IoRequest,
+        // Where the page really moves when the caller's buffer is not aligned for O_DIRECT
+        bounce: Option<Box<AlignedPage>>,
+    }
+
+    pub(super) struct UringQueue {
+        ring: IoUring,
+        file: Arc<FilePager>,
+        // Indexed by the user data of each entry
+        slots: Vec<Option<InFlight>>,
+        free: Vec<usize>,
+    }
+
+    impl UringQueue {
+        pub(super) fn new(file: Arc<FilePager>, depth: u32) -> io::Result<Self> {
+            #[cfg(test)]
+            if REFUSE_RING.get() {
+                return Err(io::ErrorKind::Unsupported.into());
+            }
+            // The submission queue is at least depth deep, so with a slot per entry it can never be full
+            let ring = IoUring::new(depth)?;
+            Ok(Self {
+                ring,
+                file,
+                slots: (0..depth).map(|_| None).collect(),
+                free: (0..depth as usize).rev().collect(),
+            })
+        }
+
+        pub(super) fn in_flight(&self) -> usize {
+            self.slots.len() - self.free.len()
+        }
+
+        // Queues a request, first reaping into `ready` if every slot is taken
+        pub(super) unsafe fn push(
+            &mut self,
+            request: &IoRequest,
+            ready: &mut VecDeque<IoCompletion>,
+        ) -> Result<()> {
+            if self.free.is_empty() {
+                self.reap(1, ready)?;
+            }
+            let slot = self.free.pop().unwrap();
+
+            let aligned = (request.buf.as_ptr() as usize).is_multiple_of(DIRECT_IO_ALIGN);
+            let mut bounce = (self.file.io_mode() == IoMode::Direct && !aligned)
+                .then(|| Box::new(AlignedPage([0u8; PAGE_SIZE])));
+            if let Some(bounce) = &mut bounce
+                && request.op == IoOp::Write
+            {
+                bounce.0.copy_from_slice(unsafe { request.buf.as_ref() });
+            }
+            let buf = match &mut bounce {
+                Some(bounce) => bounce.0.as_mut_ptr(),
+                None => request.buf.as_ptr().cast::<u8>(),
+            };
+
+            let fd = types::Fd(self.file.file().as_raw_fd());
+            let offset = request.page_id.to_offset();
+            let entry = match request.op {
+                IoOp::Read => opcode::Read::new(fd, buf, PAGE_SIZE as u32)
+                    .offset(offset)
+                    .build(),
+                IoOp::Write => opcode::Write::new(fd, buf, PAGE_SIZE as u32)
+                    .offset(offset)
+                    .build(),
+                IoOp::Extend => unreachable!("requests are reads or writes"),
+            }
+            .user_data(slot as u64);
+
+            self.slots[slot] = Some(InFlight {
+                request: *request,
+                bounce,
+            });
+            unsafe { self.ring.submission().push(&entry) }.expect("more entries than slots");
+            Ok(())
+        }
+
+        pub(super) fn submit(&mut self) -> Result<()> {
+            loop {
+                match self.ring.submit() {
+                    Ok(_) => return Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(PagerError::File(err)),
+                }
+            }
+        }
+
+        // Submits anything queued and collects finished entries, waiting for `want` of them
+        pub(super) fn reap(
+            &mut self,
+            want: usize,
+            ready: &mut VecDeque<IoCompletion>,
+        ) -> Result<()> {
+            let want = want.min(self.in_flight());
+            loop {
+                match self.ring.submit_and_wait(want) {
+                    Ok(_) => break,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(PagerError::File(err)),
+                }
+            }
+
+            for cqe in self.ring.completion() {
+                let slot = cqe.user_data() as usize;
+                let InFlight { request, bounce } = self.slots[slot].take().unwrap();
+                self.free.push(slot);
+
+                let page_id = request.page_id;
+                let result = match (cqe.result(), request.op) {
+                    (res, op) if res < 0 => Err(PagerError::Io {
+                        page_id,
+                        op,
+                        source: io::Error::from_raw_os_error(-res),
+                    }),
+                    (res, IoOp::Read) if (res as usize) < PAGE_SIZE => Err(PagerError::ShortRead {
+                        page_id,
+                        read: res as usize,
+                    }),
+                    // A regular file only stops a write short when it runs out of space
+                    (res, op) if (res as usize) < PAGE_SIZE => Err(PagerError::Io {
+                        page_id,
+                        op,
+                        source: io::ErrorKind::WriteZero.into(),
+                    }),
+                    (_, IoOp::Read) => {
+                        if let Some(bounce) = bounce {
+                            unsafe { (*request.buf.as_ptr()).copy_from_slice(&bounce.0) };
+                        }
+                        Ok(())
+                    }
+                    (_, _) => {
+                        self.file.wrote(page_id);
+                        Ok(())
+                    }
+                };
+                ready.push_back(IoCompletion {
+                    tag: request.tag,
+                    page_id,
+                    op: request.op,
+                    result,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for UringQueue {
+        fn drop(&mut self) {
+            // The kernel may still be moving pages into or out of buffers that stop being ours once we return,
+            // so a failed wait is tried again like in drain
+            let mut dropped = VecDeque::new();
+            while self.in_flight() > 0 {
+                if self.reap(1, &mut dropped).is_err() {
+                    std::thread::yield_now();
+                }
+                dropped.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::PAGE_SIZE;
+    use crate::pager::faulty_pager::{Fault, FaultOp, FaultRule, FaultyPager};
+    use crate::pager::file_pager::IoMode;
+    use crate::pager::mem_pager::InMemoryPager;
+    use crate::pager::pager::PagerError;
+    use std::path::PathBuf;
+
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn page_of(byte: u8) -> RawPage {
+        let mut page = [0u8; PAGE_SIZE];
+        page[100] = byte;
+        page[PAGE_SIZE - 1] = byte;
+        page
+    }
+
+    // What for_file should get - a probe of our own, the kernel may refuse rings wherever the tests run
+    fn ring_available() -> bool {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        return io_uring::IoUring::new(4).is_ok();
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        false
+    }
+
+    // Writes pages 0..8 in one batch, reads them back in another and checks every tag comes back once
+    fn round_trip(io: &mut AsyncPager) {
+        let pages: Vec<Box<RawPage>> = (0..8).map(|id| Box::new(page_of(id as u8))).collect();
+        let writes: Vec<_> = pages
+            .iter()
+            .enumerate()
+            .map(|(id, page)| {
+                IoRequest::write(PageID(id as u64), NonNull::from(&**page), id as u64)
+            })
+            .collect();
+        let mut done = Vec::new();
+        unsafe { io.submit(&writes).unwrap() };
+        while done.len() < writes.len() {
+            io.complete(1, &mut done).unwrap();
+        }
+        assert!(done.iter().all(|c| c.result.is_ok() && c.op == IoOp::Write));
+        assert_eq!(io.in_flight(), 0);
+
+        let mut bufs: Vec<Box<RawPage>> = (0..8).map(|_| Box::new([0u8; PAGE_SIZE])).collect();
+        let reads: Vec<_> = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(id, buf)| {
+                IoRequest::read(
+                    PageID(id as u64),
+                    NonNull::from(&mut **buf),
+                    id as u64 + 100,
+                )
+            })
+            .collect();
+        done.clear();
+        unsafe { io.submit(&reads).unwrap() };
+        io.complete(reads.len(), &mut done).unwrap();
+
+        let mut tags: Vec<_> = done.iter().map(|c| c.tag).collect();
+        tags.sort();
+        assert_eq!(tags, (100..108).collect::<Vec<_>>());
+        for (id, buf) in bufs.iter().enumerate() {
+            assert_eq!(**buf, page_of(id as u8));
+        }
+    }
+
+    #[test]
+    fn batches_complete_on_either_backend() {
+        let file =
+            TempFile(std::env::temp_dir().join(format!("inkdb-async-{}.db", std::process::id())));
+        let _ = std::fs::remove_file(&file.0);
+        for mode in [IoMode::Buffered, IoMode::Direct] {
+            let pager = Arc::new(FilePager::open_with(&file.0, mode).unwrap());
+            let mut io = AsyncPager::for_file(pager.clone(), 4);
+            // Containers, seccomp and io_uring_disabled can all keep the ring from us
+            let expected = if ring_available() {
+                AsyncBackend::Uring
+            } else {
+                AsyncBackend::Sync
+            };
+            assert_eq!(io.backend(), expected);
+            round_trip(&mut io);
+            assert_eq!(pager.page_count(), 8);
+
+            // Past the end of the file is a short read, on its own completion
+            let mut buf = Box::new([0u8; PAGE_SIZE]);
+            let mut done = Vec::new();
+            unsafe {
+                io.submit(&[IoRequest::read(PageID(20), NonNull::from(&mut *buf), 7)])
+                    .unwrap()
+            };
+            io.complete(1, &mut done).unwrap();
+            assert!(matches!(
+                done[0].result,
+                Err(PagerError::ShortRead { read: 0, .. })
+            ));
+        }
+
+        assert_eq!(
+            std::fs::metadata(&file.0).unwrap().len(),
+            8 * PAGE_SIZE as u64
+        );
+    }
+
+    #[test]
+    fn refused_rings_fall_back_to_sync() {
+        let file = TempFile(
+            std::env::temp_dir().join(format!("inkdb-async-refused-{}.db", std::process::id())),
+        );
+        let _ = std::fs::remove_file(&file.0);
+        let pager = Arc::new(FilePager::open(&file.0).unwrap());
+
+        REFUSE_RING.set(true);
+        let mut io = AsyncPager::for_file(pager.clone(), 4);
+        REFUSE_RING.set(false);
+        assert_eq!(io.backend(), AsyncBackend::Sync);
+        round_trip(&mut io);
+        assert_eq!(pager.page_count(), 8);
+    }
+
+    #[test]
+    fn sync_fallback_reports_failures_per_request() {
+        let pager = Arc::new(FaultyPager::new(Arc::new(InMemoryPager::new())));
+        let mut io = AsyncPager::new(pager.clone());
+        assert_eq!(io.backend(), AsyncBackend::Sync);
+        round_trip(&mut io);
+
+        pager.inject(FaultRule::new(FaultOp::Read, Fault::Eio).page(PageID(3)));
+        let mut bufs: Vec<Box<RawPage>> = (0..4).map(|_| Box::new([0u8; PAGE_SIZE])).collect();
+        let reads: Vec<_> = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(id, buf)| {
+                IoRequest::read(PageID(id as u64 + 2), NonNull::from(&mut **buf), id as u64)
+            })
+            .collect();
+        let mut done = Vec::new();
+        unsafe { io.submit(&reads).unwrap() };
+        assert_eq!(io.complete(0, &mut done).unwrap(), 4);
+        // Only the failed read carries the error, the rest of the batch went through
+        done.sort_by_key(|c| c.tag);
+        for (tag, completion) in done.iter().enumerate() {
+            assert_eq!(completion.tag, tag as u64);
+            assert_eq!(completion.page_id, PageID(tag as u64 + 2));
+            assert_eq!(completion.op, IoOp::Read);
+            match completion.page_id {
+                PageID(3) => assert!(matches!(
+                    completion.result,
+                    Err(PagerError::Io {
+                        page_id: PageID(3),
+                        op: IoOp::Read,
+                        ..
+                    })
+                )),
+                _ => {
+                    assert!(completion.result.is_ok());
+                    assert_eq!(*bufs[tag], page_of(completion.page_id.0 as u8));
+                }
+            }
+        }
+    }
+}
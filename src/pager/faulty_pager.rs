@@ -0,0 +1,402 @@
+use crate::page::{PAGE_SIZE, PageID, RawPage};
+use crate::pager::file_pager::IoMode;
+use crate::pager::pager::{IoOp, Pager, PagerError, Result};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+//NOTE: Wraps another pager and fails calls on cue, so tests can drive the buffer manager (and later recovery)
+// through the failures real storage has:
+//
+//   Eio        - the call fails with EIO and does nothing
+//   ShortRead  - a read only gets part of the page
+//   TornWrite  - only the first few sectors of a write reach the store, the rest of the page keeps what it
+//                had, and the write reports success - what a power cut part way through a page write leaves
+//   LostSync   - a sync reports success without syncing
+//   Latency    - the call is held up, then goes ahead
+//
+// Faults are scripted as rules - which call, optionally which page, how many times. The first rule matching a
+// call fires and is used up.
+//
+// To make lost syncs visible we remember what every page written since the last real sync looked like before,
+// and crash() puts those back - the worst a crash can do to writes that were never synced.
+
+pub(crate) const SECTOR_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FaultOp {
+    Read,
+    Write,
+    Allocate,
+    Sync,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    Eio,
+    ShortRead { bytes: usize },
+    TornWrite { sectors: usize },
+    LostSync,
+    Latency(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FaultRule {
+    op: FaultOp,
+    page: Option<PageID>,
+    fault: Fault,
+    // None fires forever
+    remaining: Option<usize>,
+}
+
+impl FaultRule {
+    // Fires once, on any page
+    pub(crate) fn new(op: FaultOp, fault: Fault) -> Self {
+        debug_assert!(
+            match fault {
+                Fault::ShortRead { .. } => op == FaultOp::Read,
+                Fault::TornWrite { .. } => op == FaultOp::Write,
+                Fault::LostSync => op == FaultOp::Sync,
+                Fault::Eio | Fault::Latency(_) => true,
+            },
+            "{:?} cannot be injected into {:?}",
+            fault,
+            op
+        );
+        Self {
+            op,
+            page: None,
+            fault,
+            remaining: Some(1),
+        }
+    }
+
+    pub(crate) fn page(mut self, page_id: PageID) -> Self {
+        self.page = Some(page_id);
+        self
+    }
+
+    pub(crate) fn times(mut self, times: usize) -> Self {
+        self.remaining = Some(times);
+        self
+    }
+
+    pub(crate) fn always(mut self) -> Self {
+        self.remaining = None;
+        self
+    }
+
+    fn matches(&self, op: FaultOp, page_id: Option<PageID>) -> bool {
+        self.op == op && self.remaining != Some(0) && (self.page.is_none() || self.page == page_id)
+    }
+}
+
+pub(crate) struct FaultyPager {
+    inner: Arc<dyn Pager>,
+    rules: Mutex<Vec<FaultRule>>,
+    // Before images of pages written since the last real sync
+    unsynced: Mutex<HashMap<PageID, RawPage>>,
+    injected: AtomicU64,
+}
+
+fn eio() -> io::Error {
+    io::Error::from_raw_os_error(libc::EIO)
+}
+
+impl FaultyPager {
+    pub(crate) fn new(inner: Arc<dyn Pager>) -> Self {
+        Self {
+            inner,
+            rules: Mutex::new(Vec::new()),
+            unsynced: Mutex::new(HashMap::new()),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn inject(&self, rule: FaultRule) {
+        self.rules.lock().unwrap().push(rule);
+    }
+
+    // Drops every rule, the pager behaves from here on
+    pub(crate) fn clear(&self) {
+        self.rules.lock().unwrap().clear();
+    }
+
+    // Faults fired so far
+    pub(crate) fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    // Loses every write since the last real sync, as if the machine went down now
+    pub(crate) fn crash(&self) -> Result<()> {
+        let lost: Vec<(PageID, RawPage)> = self.unsynced.lock().unwrap().drain().collect();
+        for (page_id, before) in lost {
+            self.inner.write_page(page_id, &before)?;
+        }
+        Ok(())
+    }
+
+    // Uses up and returns the fault for this call, if any. Latency is served here and the call goes ahead.
+    fn fault(&self, op: FaultOp, page_id: Option<PageID>) -> Option<Fault> {
+        let fault = {
+            let mut rules = self.rules.lock().unwrap();
+            let rule = rules.iter_mut().find(|rule| rule.matches(op, page_id))?;
+            if let Some(remaining) = rule.remaining.as_mut() {
+                *remaining -= 1;
+            }
+            rule.fault
+        };
+        self.injected.fetch_add(1, Ordering::Relaxed);
+
+        match fault {
+            Fault::Latency(delay) => {
+                std::thread::sleep(delay);
+                None
+            }
+            fault => Some(fault),
+        }
+    }
+
+    // Reads the page as the store has it now, keeping that as the before image if this is its first write since
+    // the last sync
+    fn remember(&self, page_id: PageID) -> RawPage {
+        let mut before = [0u8; PAGE_SIZE];
+        // A page the store doesn't have yet comes back as zeroes after a crash
+        if self.inner.read_page(page_id, &mut before).is_err() {
+            before = [0u8; PAGE_SIZE];
+        }
+        self.unsynced
+            .lock()
+            .unwrap()
+            .entry(page_id)
+            .or_insert(before);
+        before
+    }
+}
+
+impl Pager for FaultyPager {
+    fn read_page(&self, page_id: PageID, buf: &mut RawPage) -> Result<()> {
+        match self.fault(FaultOp::Read, Some(page_id)) {
+            Some(Fault::Eio) => Err(PagerError::Io {
+                page_id,
+                op: IoOp::Read,
+                source: eio(),
+            }),
+            Some(Fault::ShortRead { bytes }) => {
+                let mut page = [0u8; PAGE_SIZE];
+                self.inner.read_page(page_id, &mut page)?;
+                let read = bytes.min(page.len());
+                buf[..read].copy_from_slice(&page[..read]);
+                Err(PagerError::ShortRead { page_id, read })
+            }
+            _ => self.inner.read_page(page_id, buf),
+        }
+    }
+
+    fn write_page(&self, page_id: PageID, buf: &RawPage) -> Result<()> {
+        match self.fault(FaultOp::Write, Some(page_id)) {
+            Some(Fault::Eio) => Err(PagerError::Io {
+                page_id,
+                op: IoOp::Write,
+                source: eio(),
+            }),
+            Some(Fault::TornWrite { sectors }) => {
+                let mut torn = self.remember(page_id);
+                let written = (sectors * SECTOR_SIZE).min(torn.len());
+                torn[..written].copy_from_slice(&buf[..written]);
+                self.inner.write_page(page_id, &torn)
+            }
+            _ => {
+                self.remember(page_id);
+                self.inner.write_page(page_id, buf)
+            }
+        }
+    }
+
+    fn allocate_page(&self) -> Result<PageID> {
+        match self.fault(FaultOp::Allocate, None) {
+            Some(Fault::Eio) => Err(PagerError::File(eio())),
+            _ => self.inner.allocate_page(),
+        }
+    }
+
+    fn page_count(&self) -> u64 {
+        self.inner.page_count()
+    }
+
+    fn sync(&self) -> Result<()> {
+        match self.fault(FaultOp::Sync, None) {
+            Some(Fault::Eio) => Err(PagerError::File(eio())),
+            Some(Fault::LostSync) => Ok(()),
+            _ => {
+                self.inner.sync()?;
+                self.unsynced.lock().unwrap().clear();
+                Ok(())
+            }
+        }
+    }
+
+    fn io_mode(&self) -> IoMode {
+        self.inner.io_mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::buffer_manager::{BufferError, BufferManager};
+    use crate::pager::mem_pager::InMemoryPager;
+    use std::time::Instant;
+
+    fn page_of(byte: u8) -> RawPage {
+        // Clear of the header so the page LSN stays 0
+        let mut page = [0u8; PAGE_SIZE];
+        page[100..].fill(byte);
+        page
+    }
+
+    fn setup(pages: u64) -> (Arc<InMemoryPager>, FaultyPager) {
+        let store = Arc::new(InMemoryPager::new());
+        for id in 0..pages {
+            store.write_page(PageID(id), &page_of(id as u8)).unwrap();
+        }
+        let pager = FaultyPager::new(store.clone());
+        (store, pager)
+    }
+
+    fn read(pager: &dyn Pager, page_id: u64) -> RawPage {
+        let mut buf = [0u8; PAGE_SIZE];
+        pager.read_page(PageID(page_id), &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn faults_fire_as_scripted() {
+        let (_store, pager) = setup(4);
+        let mut buf = [0u8; PAGE_SIZE];
+
+        pager.inject(
+            FaultRule::new(FaultOp::Read, Fault::Eio)
+                .page(PageID(2))
+                .times(2),
+        );
+        // Other pages are left alone, page 2 fails twice and then reads fine
+        assert_eq!(read(&pager, 1), page_of(1));
+        for _ in 0..2 {
+            match pager.read_page(PageID(2), &mut buf) {
+                Err(PagerError::Io {
+                    page_id,
+                    op,
+                    source,
+                }) => {
+                    assert_eq!((page_id, op), (PageID(2), IoOp::Read));
+                    assert_eq!(source.raw_os_error(), Some(libc::EIO));
+                }
+                other => panic!("expected EIO, got {:?}", other),
+            }
+        }
+        assert_eq!(read(&pager, 2), page_of(2));
+
+        pager.inject(FaultRule::new(
+            FaultOp::Read,
+            Fault::ShortRead { bytes: 1000 },
+        ));
+        buf.fill(0xFF);
+        assert!(matches!(
+            pager.read_page(PageID(3), &mut buf),
+            Err(PagerError::ShortRead { read: 1000, .. })
+        ));
+        assert_eq!(buf[999], 3);
+        assert_eq!(buf[1000], 0xFF);
+
+        pager.inject(
+            FaultRule::new(FaultOp::Write, Fault::Latency(Duration::from_millis(20))).always(),
+        );
+        let started = Instant::now();
+        pager.write_page(PageID(0), &page_of(9)).unwrap();
+        pager.write_page(PageID(0), &page_of(10)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(read(&pager, 0), page_of(10));
+
+        pager.clear();
+        assert_eq!(pager.injected(), 5);
+    }
+
+    #[test]
+    fn torn_writes_keep_the_old_tail() {
+        let (store, pager) = setup(2);
+
+        pager.inject(FaultRule::new(
+            FaultOp::Write,
+            Fault::TornWrite { sectors: 2 },
+        ));
+        pager.write_page(PageID(1), &page_of(7)).unwrap();
+
+        let on_disk = read(store.as_ref(), 1);
+        assert!(on_disk[100..2 * SECTOR_SIZE].iter().all(|b| *b == 7));
+        assert!(on_disk[2 * SECTOR_SIZE..].iter().all(|b| *b == 1));
+    }
+
+    #[test]
+    fn lost_syncs_lose_writes_on_crash() {
+        let (store, pager) = setup(2);
+
+        pager.write_page(PageID(0), &page_of(1)).unwrap();
+        pager.sync().unwrap();
+
+        // The sync says yes but never happened - both writes after the real sync go
+        pager.write_page(PageID(0), &page_of(2)).unwrap();
+        pager.write_page(PageID(5), &page_of(5)).unwrap();
+        pager.inject(FaultRule::new(FaultOp::Sync, Fault::LostSync));
+        pager.sync().unwrap();
+        pager.crash().unwrap();
+        assert_eq!(read(store.as_ref(), 0), page_of(1));
+        assert_eq!(read(store.as_ref(), 5), [0u8; PAGE_SIZE]);
+
+        // A real sync keeps them
+        pager.write_page(PageID(0), &page_of(3)).unwrap();
+        pager.sync().unwrap();
+        pager.crash().unwrap();
+        assert_eq!(read(store.as_ref(), 0), page_of(3));
+    }
+
+    #[test]
+    fn buffer_manager_surfaces_injected_errors() {
+        let (store, pager) = setup(4);
+        let pager = Arc::new(pager);
+        let bm = BufferManager::new(2, pager.clone());
+
+        // A failed read fails the fetch with the page it was for, and the next fetch tries again
+        pager.inject(FaultRule::new(FaultOp::Read, Fault::Eio).page(PageID(1)));
+        match bm.fetch_page(PageID(1)) {
+            Err(BufferError::Pager(err)) => assert_eq!(err.page_id(), Some(PageID(1))),
+            Err(err) => panic!("expected a pager error, got {:?}", err),
+            Ok(_) => panic!("read of page 1 should have failed"),
+        }
+        let page = bm.fetch_page(PageID(1)).unwrap();
+        page.read(|bytes| assert_eq!(bytes[100], 1));
+        page.write(|bytes| bytes[100] = 0xAA);
+        drop(page);
+
+        // A failed write leaves the page dirty for the next flush, a failed sync is reported like any other error
+        pager.inject(FaultRule::new(FaultOp::Write, Fault::Eio).page(PageID(1)));
+        assert!(matches!(
+            bm.flush_all(),
+            Err(BufferError::Pager(PagerError::Io {
+                op: IoOp::Write,
+                ..
+            }))
+        ));
+        assert!(bm.fetch_page(PageID(1)).unwrap().is_dirty());
+
+        pager.inject(FaultRule::new(FaultOp::Sync, Fault::Eio));
+        assert!(matches!(
+            bm.flush_all(),
+            Err(BufferError::Pager(PagerError::File(_)))
+        ));
+        bm.flush_all().unwrap();
+        assert_eq!(read(store.as_ref(), 1)[100], 0xAA);
+    }
+}
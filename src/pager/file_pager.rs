@@ -0,0 +1,430 @@
+use crate::page::{PAGE_SIZE, PageID, RawPage};
+use crate::pager::pager::{IoOp, Pager, PagerError, Result};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+//NOTE: A single data file holding page N at byte N * PAGE_SIZE (PageID::to_offset).
+//
+// Reads and writes are positional (pread/pwrite through FileExt) so any number of threads can move pages at
+// once without sharing a file cursor. Only growing the file is serialised - allocate_page extends it by a
+// zeroed page under the lock, so two allocations never hand out the same id. A write past the end (a page
+// created in the pool under an id we never allocated) extends the file as a side effect and we follow it.
+//
+// Nothing is durable until sync, which is an fsync of data and metadata - the file length changes as it grows.
+//
+// In IoMode::Direct the file is opened O_DIRECT so pages skip the OS page cache - the buffer pool already caches
+// them and the kernel holding a second copy only costs memory. Direct I/O needs the buffer, offset and length
+// aligned to DIRECT_IO_ALIGN. Offsets and lengths are whole pages, and buffer pool frames come from the arena
+// already page aligned, so only a stray caller's buffer is bounced through an aligned copy. Filesystems that
+// refuse O_DIRECT at open (tmpfs before 6.6, most FUSE mounts) get the file buffered instead - io_mode says
+// which one we ended up with.
+//
+// TODO: A trailing partial page (a crash during an extend) is ignored rather than repaired
+// TODO: A filesystem that takes O_DIRECT at open but fails the I/O with EINVAL is not fallen back from
+
+pub(crate) const DIRECT_IO_ALIGN: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum IoMode {
+    // Through the OS page cache
+    #[default]
+    Buffered,
+    // O_DIRECT where the filesystem allows it
+    Direct,
+}
+
+#[repr(C, align(4096))]
+pub(super) struct AlignedPage(pub(super) RawPage);
+
+pub(crate) struct FilePager {
+    file: File,
+    path: PathBuf,
+    mode: IoMode,
+    // Whole pages in the file
+    pages: Mutex<u64>,
+}
+
+impl FilePager {
+    // Opens the data file, creating it empty if it does not exist
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(path, IoMode::Buffered)
+    }
+
+    pub(crate) fn open_with(path: impl AsRef<Path>, mode: IoMode) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        let (file, mode) = open_data_file(&options, &path, mode).map_err(PagerError::File)?;
+        let len = file.metadata().map_err(PagerError::File)?.len();
+        Ok(Self {
+            file,
+            path,
+            mode,
+            pages: Mutex::new(len / PAGE_SIZE as u64),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // For the async pager, which moves pages itself and tells us about the writes afterwards
+    pub(super) fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub(super) fn wrote(&self, page_id: PageID) {
+        let mut pages = self.pages.lock().unwrap();
+        *pages = (*pages).max(page_id.into() + 1);
+    }
+}
+
+impl Pager for FilePager {
+    fn read_page(&self, page_id: PageID, buf: &mut RawPage) -> Result<()> {
+        read_page_at(&self.file, self.mode, page_id, page_id.to_offset(), buf)
+    }
+
+    fn write_page(&self, page_id: PageID, buf: &RawPage) -> Result<()> {
+        write_page_at(&self.file, self.mode, page_id, page_id.to_offset(), buf)?;
+        self.wrote(page_id);
+        Ok(())
+    }
+
+    fn allocate_page(&self) -> Result<PageID> {
+        let mut pages = self.pages.lock().unwrap();
+        let page_id = PageID(*pages);
+        self.file
+            .set_len((page_id.into() + 1) * PAGE_SIZE as u64)
+            .map_err(|source| PagerError::Io {
+                page_id,
+                op: IoOp::Extend,
+                source,
+            })?;
+        *pages += 1;
+        Ok(page_id)
+    }
+
+    fn page_count(&self) -> u64 {
+        *self.pages.lock().unwrap()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.file.sync_all().map_err(PagerError::File)
+    }
+
+    fn io_mode(&self) -> IoMode {
+        self.mode
+    }
+}
+
+// Opens `path` with O_DIRECT when asked for it, buffered when not or when the filesystem will not have it
+pub(super) fn open_data_file(
+    options: &OpenOptions,
+    path: &Path,
+    mode: IoMode,
+) -> io::Result<(File, IoMode)> {
+    #[cfg(target_os = "linux")]
+    if mode == IoMode::Direct {
+        match open_direct(options, path) {
+            Ok(file) => return Ok((file, IoMode::Direct)),
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = mode;
+    Ok((options.open(path)?, IoMode::Buffered))
+}
+
+#[cfg(target_os = "linux")]
+fn open_direct(options: &OpenOptions, path: &Path) -> io::Result<File> {
+    #[cfg(test)]
+    if REFUSE_DIRECT.get() {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut direct = options.clone();
+    direct.custom_flags(libc::O_DIRECT);
+    direct.open(path)
+}
+
+// Stands in for a filesystem that refuses O_DIRECT, for tests on the thread that sets it
+#[cfg(test)]
+thread_local! {
+    pub(super) static REFUSE_DIRECT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+fn direct_aligned(buf: &RawPage) -> bool {
+    (buf.as_ptr() as usize).is_multiple_of(DIRECT_IO_ALIGN)
+}
+
+// Reads the page at `offset` in `file`, which the segmented pager shares with us
+pub(super) fn read_page_at(
+    file: &File,
+    mode: IoMode,
+    page_id: PageID,
+    offset: u64,
+    buf: &mut RawPage,
+) -> Result<()> {
+    if mode == IoMode::Direct && !direct_aligned(buf) {
+        let mut bounce = Box::new(AlignedPage([0u8; PAGE_SIZE]));
+        read_page_at(file, mode, page_id, offset, &mut bounce.0)?;
+        buf.copy_from_slice(&bounce.0);
+        return Ok(());
+    }
+    let mut read = 0;
+    // read_exact_at would hide how far we got, which is worth knowing for a torn file
+    while read < PAGE_SIZE {
+        match file.read_at(&mut buf[read..], offset + read as u64) {
+            Ok(0) => return Err(PagerError::ShortRead { page_id, read }),
+            // A direct read only stops short at the end of the file, and carrying on from an unaligned
+            // offset would fail anyway
+            Ok(n) if mode == IoMode::Direct && read + n < PAGE_SIZE => {
+                return Err(PagerError::ShortRead {
+                    page_id,
+                    read: read + n,
+                });
+            }
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(source) => {
+                return Err(PagerError::Io {
+                    page_id,
+                    op: IoOp::Read,
+                    source,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn write_page_at(
+    file: &File,
+    mode: IoMode,
+    page_id: PageID,
+    offset: u64,
+    buf: &RawPage,
+) -> Result<()> {
+    if mode == IoMode::Direct && !direct_aligned(buf) {
+        let bounce = Box::new(AlignedPage(*buf));
+        return write_page_at(file, mode, page_id, offset, &bounce.0);
+    }
+    file.write_all_at(buf, offset)
+        .map_err(|source| PagerError::Io {
+            page_id,
+            op: IoOp::Write,
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::buffer_manager::{BufferError, BufferManager};
+    use std::sync::Arc;
+
+    // A fresh file under the temp dir, removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("inkdb-{}-{}.db", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    // Clear of the header so the LSN stays 0 and the buffer manager is free to write the page back
+    fn page_of(byte: u8) -> RawPage {
+        let mut page = [0u8; PAGE_SIZE];
+        page[100] = byte;
+        page[PAGE_SIZE - 1] = byte;
+        page
+    }
+
+    #[test]
+    fn pages_round_trip_through_the_file() {
+        let file = TempFile::new("round-trip");
+        let pager = FilePager::open(&file.0).unwrap();
+        assert_eq!(pager.page_count(), 0);
+
+        assert_eq!(pager.allocate_page().unwrap(), PageID(0));
+        assert_eq!(pager.allocate_page().unwrap(), PageID(1));
+        assert_eq!(
+            std::fs::metadata(&file.0).unwrap().len(),
+            2 * PAGE_SIZE as u64
+        );
+
+        // Allocated pages read back as zeroes until written
+        let mut buf = [1u8; PAGE_SIZE];
+        pager.read_page(PageID(1), &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        pager.write_page(PageID(1), &page_of(7)).unwrap();
+        // Writing past the end grows the file too
+        pager.write_page(PageID(4), &page_of(9)).unwrap();
+        assert_eq!(pager.page_count(), 5);
+        pager.sync().unwrap();
+        drop(pager);
+
+        let pager = FilePager::open(&file.0).unwrap();
+        assert_eq!(pager.page_count(), 5);
+        pager.read_page(PageID(1), &mut buf).unwrap();
+        assert_eq!(buf, page_of(7));
+        pager.read_page(PageID(4), &mut buf).unwrap();
+        assert_eq!(buf, page_of(9));
+        assert_eq!(pager.allocate_page().unwrap(), PageID(5));
+
+        // Past the end of the file is a short read of that page
+        match pager.read_page(PageID(8), &mut buf) {
+            Err(err @ PagerError::ShortRead { read: 0, .. }) => {
+                assert_eq!(err.page_id(), Some(PageID(8)))
+            }
+            other => panic!("expected a short read, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn torn_file_is_a_short_read() {
+        let file = TempFile::new("torn");
+        let pager = FilePager::open(&file.0).unwrap();
+        pager.write_page(PageID(0), &page_of(3)).unwrap();
+        pager.write_page(PageID(1), &page_of(4)).unwrap();
+
+        // Cut the file part way into the second page
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&file.0)
+            .unwrap()
+            .set_len(PAGE_SIZE as u64 + 100)
+            .unwrap();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        pager.read_page(PageID(0), &mut buf).unwrap();
+        match pager.read_page(PageID(1), &mut buf) {
+            Err(PagerError::ShortRead { page_id, read }) => {
+                assert_eq!((page_id, read), (PageID(1), 100))
+            }
+            other => panic!("expected a short read, got {:?}", other),
+        }
+
+        // And a trailing partial page is not counted when the file is opened again
+        assert_eq!(FilePager::open(&file.0).unwrap().page_count(), 1);
+    }
+
+    // Whether the filesystem under the temp dir takes O_DIRECT at all
+    fn supports_direct(path: &Path) -> bool {
+        let direct = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+            .is_ok();
+        let _ = std::fs::remove_file(path);
+        direct
+    }
+
+    #[test]
+    fn direct_io_falls_back_where_the_filesystem_refuses_it() {
+        let file = TempFile::new("direct-refused");
+        REFUSE_DIRECT.set(true);
+        let pager = FilePager::open_with(&file.0, IoMode::Direct);
+        REFUSE_DIRECT.set(false);
+        let pager = pager.unwrap();
+        assert_eq!(pager.io_mode(), IoMode::Buffered);
+
+        // Buffered all the same from here on
+        pager.write_page(PageID(0), &page_of(3)).unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        pager.read_page(PageID(0), &mut buf).unwrap();
+        assert_eq!(buf, page_of(3));
+    }
+
+    #[test]
+    fn direct_io_round_trips_aligned_and_unaligned_buffers() {
+        let file = TempFile::new("direct");
+        let expected = if supports_direct(&file.0) {
+            IoMode::Direct
+        } else {
+            IoMode::Buffered
+        };
+        let pager = FilePager::open_with(&file.0, IoMode::Direct).unwrap();
+        assert_eq!(pager.io_mode(), expected);
+
+        let mut aligned = Box::new(AlignedPage(page_of(5)));
+        pager.write_page(PageID(0), &aligned.0).unwrap();
+        // Off by one byte from a page boundary, which O_DIRECT would refuse without the bounce
+        let mut unaligned = vec![0u8; PAGE_SIZE + 1];
+        let buf: &mut RawPage = (&mut unaligned[1..]).try_into().unwrap();
+        buf.copy_from_slice(&page_of(6));
+        pager.write_page(PageID(1), buf).unwrap();
+        pager.sync().unwrap();
+
+        pager.read_page(PageID(1), &mut aligned.0).unwrap();
+        assert_eq!(aligned.0, page_of(6));
+        pager.read_page(PageID(0), buf).unwrap();
+        assert_eq!(*buf, page_of(5));
+        assert!(matches!(
+            pager.read_page(PageID(2), buf),
+            Err(PagerError::ShortRead { read: 0, .. })
+        ));
+        drop(pager);
+
+        let pager = FilePager::open(&file.0).unwrap();
+        assert_eq!(pager.io_mode(), IoMode::Buffered);
+        assert_eq!(pager.page_count(), 2);
+    }
+
+    #[test]
+    fn buffer_manager_loads_and_writes_back_through_the_file() {
+        let file = TempFile::new("buffer-manager");
+        let pager = Arc::new(FilePager::open(&file.0).unwrap());
+        for _ in 0..8 {
+            let page_id = pager.allocate_page().unwrap();
+            pager
+                .write_page(page_id, &page_of(page_id.into() as u8))
+                .unwrap();
+        }
+
+        {
+            // Fewer frames than pages so the loop below evicts, writing the changes back to the file
+            let bm = BufferManager::new(4, pager.clone());
+            for id in 0..8 {
+                let page = bm.fetch_page(PageID(id)).unwrap();
+                page.read(|bytes| assert_eq!(bytes[100], id as u8));
+                page.write(|bytes| bytes[101] = 0xAA);
+            }
+            bm.flush_all().unwrap();
+        }
+
+        let reopened = FilePager::open(&file.0).unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        for id in 0..8 {
+            reopened.read_page(PageID(id), &mut buf).unwrap();
+            assert_eq!((buf[100], buf[101]), (id as u8, 0xAA));
+        }
+
+        // A page the file does not have surfaces as a pager error naming it
+        let bm = BufferManager::new(4, Arc::new(reopened));
+        match bm.fetch_page(PageID(20)) {
+            Err(BufferError::Pager(err)) => {
+                assert!(matches!(err, PagerError::ShortRead { read: 0, .. }));
+                assert_eq!(err.page_id(), Some(PageID(20)));
+            }
+            Err(err) => panic!("expected a pager error, got {:?}", err),
+            Ok(_) => panic!("page 20 is not in the file"),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use crate::page::{PAGE_SIZE, PageID, RawPage};
+use crate::pager::pager::{Pager, PagerError, Result};
+use std::sync::RwLock;
+
+//NOTE: A pager with nothing behind it but memory, laid out like the data file - pages [0, page_count) exist,
+// allocating adds a zeroed page at the end, a write past the end grows the store and reading past it is a short
+// read. Everything is "durable" as soon as it is written, sync has nothing to do. For tests, and as the store
+// under a FaultyPager when a test wants to look at what actually reached it.
+
+#[derive(Default)]
+pub(crate) struct InMemoryPager {
+    pages: RwLock<Vec<Box<RawPage>>>,
+}
+
+impl InMemoryPager {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Pager for InMemoryPager {
+    fn read_page(&self, page_id: PageID, buf: &mut RawPage) -> Result<()> {
+        let pages = self.pages.read().unwrap();
+        let page = pages
+            .get(page_id.into() as usize)
+            .ok_or(PagerError::ShortRead { page_id, read: 0 })?;
+        buf.copy_from_slice(&page[..]);
+        Ok(())
+    }
+
+    fn write_page(&self, page_id: PageID, buf: &RawPage) -> Result<()> {
+        let mut pages = self.pages.write().unwrap();
+        let idx = page_id.into() as usize;
+        if idx >= pages.len() {
+            pages.resize_with(idx + 1, || Box::new([0u8; PAGE_SIZE]));
+        }
+        pages[idx].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn allocate_page(&self) -> Result<PageID> {
+        let mut pages = self.pages.write().unwrap();
+        pages.push(Box::new([0u8; PAGE_SIZE]));
+        Ok(PageID(pages.len() as u64 - 1))
+    }
+
+    fn page_count(&self) -> u64 {
+        self.pages.read().unwrap().len() as u64
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
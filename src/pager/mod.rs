@@ -1 +1,8 @@
+pub mod allocator;
+pub mod async_pager;
+pub mod faulty_pager;
+pub mod file_pager;
+pub mod mem_pager;
 pub mod pager;
+pub mod segment_pager;
+pub mod store;
@@ -0,0 +1,62 @@
+use crate::page::{PageID, RawPage};
+use crate::pager::file_pager::IoMode;
+use std::io;
+
+//NOTE: The pager is the only layer which talks to storage. It knows nothing about frames, latches or what is
+// inside a page - it moves whole pages between a caller owned buffer and wherever the page lives.
+// The buffer manager is the only caller, it reads on a miss and writes back dirty frames on eviction.
+
+pub(crate) type Result<T> = std::result::Result<T, PagerError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IoOp {
+    Read,
+    Write,
+    Extend,
+}
+
+#[derive(Debug)]
+pub(crate) enum PagerError {
+    ReadFailed(PageID),
+    WriteFailed(PageID),
+    // The file ends part way into (or before) the page - never written, or the file was cut short
+    ShortRead {
+        page_id: PageID,
+        read: usize,
+    },
+    // The OS failed a read, write or extend of the page
+    Io {
+        page_id: PageID,
+        op: IoOp,
+        source: io::Error,
+    },
+    // Failures that are not about one page - opening the file, fsync
+    File(io::Error),
+}
+
+impl PagerError {
+    pub(crate) fn page_id(&self) -> Option<PageID> {
+        match self {
+            PagerError::ReadFailed(page_id)
+            | PagerError::WriteFailed(page_id)
+            | PagerError::ShortRead { page_id, .. }
+            | PagerError::Io { page_id, .. } => Some(*page_id),
+            PagerError::File(_) => None,
+        }
+    }
+}
+
+pub(crate) trait Pager: Send + Sync {
+    fn read_page(&self, page_id: PageID, buf: &mut RawPage) -> Result<()>;
+    fn write_page(&self, page_id: PageID, buf: &RawPage) -> Result<()>;
+    // Grows the store by one zeroed page and returns its id
+    fn allocate_page(&self) -> Result<PageID>;
+    // Whole pages in the store, which is also the id the next allocate_page hands out
+    fn page_count(&self) -> u64;
+    // Everything written before the call is durable once it returns
+    fn sync(&self) -> Result<()>;
+    // How pages actually move - a store asked for direct I/O can still have fallen back to buffered
+    fn io_mode(&self) -> IoMode {
+        IoMode::Buffered
+    }
+}
@@ -0,0 +1,477 @@
+use crate::page::{PAGE_SIZE, PageID, RawPage};
+use crate::pager::file_pager::{IoMode, open_data_file, read_page_at, write_page_at};
+use crate::pager::pager::{IoOp, Pager, PagerError, Result};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+//NOTE: The data file split into fixed size segments - `base.0`, `base.1`, ... - each holding `segment_pages`
+// pages, so page N lives in segment N / segment_pages at (N % segment_pages) * PAGE_SIZE. Segments are created
+// the first time a page in them is allocated or written and a store only ever has trailing segments removed, so
+// backups can copy segments that stopped changing and no single file outgrows the filesystem.
+//
+// Open segments are kept in a small cache of file handles, least recently used closed first once there are
+// `max_open` of them. Handles are shared out as Arcs so a page moves without holding the cache lock, and a
+// handle evicted mid read is closed by whoever finishes with it last.
+//
+// Sync fsyncs every segment written since the last sync (fsync flushes the file, not just the handle, so a
+// segment closed in between is simply opened again) and the directory when segments were created or removed.
+//
+// open_with picks buffered or O_DIRECT I/O as for FilePager. Each segment falls back on its own, and io_mode
+// reports buffered once any of them has.
+//
+// TODO: Like FilePager a trailing partial page in the last segment is ignored rather than repaired
+
+const DEFAULT_SEGMENT_BYTES: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SegmentConfig {
+    pub(crate) segment_pages: u64,
+    // File handles kept open at once
+    pub(crate) max_open: usize,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self {
+            segment_pages: DEFAULT_SEGMENT_BYTES / PAGE_SIZE as u64,
+            max_open: 16,
+        }
+    }
+}
+
+struct FdCache {
+    // With the mode each segment was opened in, in case the filesystem refused O_DIRECT
+    files: HashMap<u64, (Segment, u64)>,
+    // Bumped on every use - the handle with the lowest is closed first
+    tick: u64,
+}
+
+type Segment = (Arc<File>, IoMode);
+
+struct SyncState {
+    segments: HashSet<u64>,
+    directory: bool,
+}
+
+pub(crate) struct SegmentedPager {
+    base: PathBuf,
+    config: SegmentConfig,
+    mode: IoMode,
+    // A segment was opened buffered although we asked for direct I/O
+    fell_back: AtomicBool,
+    fds: Mutex<FdCache>,
+    // Whole pages in the store, across all segments
+    pages: Mutex<u64>,
+    unsynced: Mutex<SyncState>,
+}
+
+impl SegmentedPager {
+    // Opens the segments under `base`, counting pages from the last one. Nothing is created until a page is.
+    pub(crate) fn open(base: impl AsRef<Path>, config: SegmentConfig) -> Result<Self> {
+        Self::open_with(base, config, IoMode::Buffered)
+    }
+
+    pub(crate) fn open_with(
+        base: impl AsRef<Path>,
+        config: SegmentConfig,
+        mode: IoMode,
+    ) -> Result<Self> {
+        assert!(config.segment_pages > 0 && config.max_open > 0);
+        let base = base.as_ref().to_path_buf();
+        let pager = Self {
+            base,
+            config,
+            mode,
+            fell_back: AtomicBool::new(false),
+            fds: Mutex::new(FdCache {
+                files: HashMap::new(),
+                tick: 0,
+            }),
+            pages: Mutex::new(0),
+            unsynced: Mutex::new(SyncState {
+                segments: HashSet::new(),
+                directory: false,
+            }),
+        };
+
+        if let Some(last) = pager.last_segment()? {
+            let len = std::fs::metadata(pager.segment_path(last))
+                .map_err(PagerError::File)?
+                .len();
+            *pager.pages.lock().unwrap() = last * config.segment_pages + len / PAGE_SIZE as u64;
+        }
+        Ok(pager)
+    }
+
+    pub(crate) fn segment_path(&self, segment: u64) -> PathBuf {
+        let mut path = OsString::from(self.base.as_os_str());
+        path.push(format!(".{}", segment));
+        PathBuf::from(path)
+    }
+
+    pub(crate) fn segment_count(&self) -> u64 {
+        self.pages
+            .lock()
+            .unwrap()
+            .div_ceil(self.config.segment_pages)
+    }
+
+    pub(crate) fn open_files(&self) -> usize {
+        self.fds.lock().unwrap().files.len()
+    }
+
+    // Shrinks the store to `pages` pages - segments wholly past the end are removed and the new last one cut
+    // short. Callers make sure nothing still points at the pages being dropped.
+    pub(crate) fn truncate(&self, pages: u64) -> Result<()> {
+        let mut count = self.pages.lock().unwrap();
+        if pages >= *count {
+            return Ok(());
+        }
+        let keep = pages.div_ceil(self.config.segment_pages);
+        let mut unsynced = self.unsynced.lock().unwrap();
+
+        for segment in (keep..count.div_ceil(self.config.segment_pages)).rev() {
+            self.fds.lock().unwrap().files.remove(&segment);
+            unsynced.segments.remove(&segment);
+            match std::fs::remove_file(self.segment_path(segment)) {
+                Ok(()) => unsynced.directory = true,
+                // A gap left by writes that skipped a segment
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(PagerError::File(err)),
+            }
+        }
+
+        let within = pages - keep.saturating_sub(1) * self.config.segment_pages;
+        if keep > 0 && within < self.config.segment_pages {
+            let last = keep - 1;
+            // Not there if writes skipped it, and then there is nothing to cut
+            if let Some((file, _)) = self.try_segment(last, false).map_err(PagerError::File)? {
+                file.set_len(within * PAGE_SIZE as u64)
+                    .map_err(PagerError::File)?;
+                unsynced.segments.insert(last);
+            }
+        }
+        *count = pages;
+        Ok(())
+    }
+
+    fn locate(&self, page_id: PageID) -> (u64, u64) {
+        let id = page_id.into();
+        (
+            id / self.config.segment_pages,
+            (id % self.config.segment_pages) * PAGE_SIZE as u64,
+        )
+    }
+
+    // The handle for a segment, opening it if it is not cached. `None` if it does not exist and we were not
+    // asked to create it.
+    fn try_segment(&self, segment: u64, create: bool) -> io::Result<Option<Segment>> {
+        {
+            let mut fds = self.fds.lock().unwrap();
+            fds.tick += 1;
+            let tick = fds.tick;
+            if let Some((file, used)) = fds.files.get_mut(&segment) {
+                *used = tick;
+                return Ok(Some(file.clone()));
+            }
+        }
+
+        // Opened outside the lock - a racing open of the same segment just loses below
+        let path = self.segment_path(segment);
+        let mut options = OpenOptions::new();
+        options.read(true).write(true);
+        let mode = self.mode;
+        let file = match open_data_file(options.clone().create_new(create), &path, mode) {
+            Ok(file) => {
+                if create {
+                    self.unsynced.lock().unwrap().directory = true;
+                }
+                file
+            }
+            Err(err) if create && err.kind() == io::ErrorKind::AlreadyExists => {
+                open_data_file(&options, &path, mode)?
+            }
+            Err(err) if !create && err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut fds = self.fds.lock().unwrap();
+        fds.tick += 1;
+        let tick = fds.tick;
+        if let Some((file, used)) = fds.files.get_mut(&segment) {
+            *used = tick;
+            return Ok(Some(file.clone()));
+        }
+        if fds.files.len() >= self.config.max_open
+            && let Some(oldest) = fds
+                .files
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(segment, _)| *segment)
+        {
+            fds.files.remove(&oldest);
+        }
+        if file.1 != mode {
+            self.fell_back.store(true, Ordering::Relaxed);
+        }
+        let file = (Arc::new(file.0), file.1);
+        fds.files.insert(segment, (file.clone(), tick));
+        Ok(Some(file))
+    }
+
+    fn segment(&self, segment: u64, page_id: PageID, op: IoOp, create: bool) -> Result<Segment> {
+        match self.try_segment(segment, create) {
+            Ok(Some(file)) => Ok(file),
+            Ok(None) => Err(PagerError::ShortRead { page_id, read: 0 }),
+            Err(source) => Err(PagerError::Io {
+                page_id,
+                op,
+                source,
+            }),
+        }
+    }
+
+    fn last_segment(&self) -> Result<Option<u64>> {
+        let dir = match self.base.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let Some(name) = self.base.file_name() else {
+            return Ok(None);
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(PagerError::File(err)),
+        };
+        let mut last = None;
+        for entry in entries {
+            let entry = entry.map_err(PagerError::File)?;
+            let name = entry.file_name();
+            let Some(segment) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            last = last.max(Some(segment));
+        }
+        Ok(last)
+    }
+
+    fn sync_directory(&self) -> io::Result<()> {
+        match self.base.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+            _ => File::open(".")?.sync_all(),
+        }
+    }
+}
+
+impl Pager for SegmentedPager {
+    fn read_page(&self, page_id: PageID, buf: &mut RawPage) -> Result<()> {
+        let (segment, offset) = self.locate(page_id);
+        let (file, mode) = self.segment(segment, page_id, IoOp::Read, false)?;
+        read_page_at(&file, mode, page_id, offset, buf)
+    }
+
+    fn write_page(&self, page_id: PageID, buf: &RawPage) -> Result<()> {
+        let (segment, offset) = self.locate(page_id);
+        let (file, mode) = self.segment(segment, page_id, IoOp::Write, true)?;
+        write_page_at(&file, mode, page_id, offset, buf)?;
+        self.unsynced.lock().unwrap().segments.insert(segment);
+        let mut pages = self.pages.lock().unwrap();
+        *pages = (*pages).max(page_id.into() + 1);
+        Ok(())
+    }
+
+    fn allocate_page(&self) -> Result<PageID> {
+        let mut pages = self.pages.lock().unwrap();
+        let page_id = PageID(*pages);
+        let (segment, offset) = self.locate(page_id);
+        self.segment(segment, page_id, IoOp::Extend, true)?
+            .0
+            .set_len(offset + PAGE_SIZE as u64)
+            .map_err(|source| PagerError::Io {
+                page_id,
+                op: IoOp::Extend,
+                source,
+            })?;
+        self.unsynced.lock().unwrap().segments.insert(segment);
+        *pages += 1;
+        Ok(page_id)
+    }
+
+    fn page_count(&self) -> u64 {
+        *self.pages.lock().unwrap()
+    }
+
+    fn io_mode(&self) -> IoMode {
+        if self.fell_back.load(Ordering::Relaxed) {
+            IoMode::Buffered
+        } else {
+            self.mode
+        }
+    }
+
+    fn sync(&self) -> Result<()> {
+        let (segments, directory) = {
+            let mut unsynced = self.unsynced.lock().unwrap();
+            let segments: Vec<_> = unsynced.segments.drain().collect();
+            (segments, std::mem::take(&mut unsynced.directory))
+        };
+        let mut result = Ok(());
+        for segment in &segments {
+            let page_id = PageID(segment * self.config.segment_pages);
+            if let Err(err) = self
+                .segment(*segment, page_id, IoOp::Write, false)
+                .and_then(|(file, _)| file.sync_all().map_err(PagerError::File))
+            {
+                result = Err(err);
+                break;
+            }
+        }
+        if result.is_ok() && directory {
+            result = self.sync_directory().map_err(PagerError::File);
+        }
+
+        // Whatever did not make it is still owed to the next sync
+        if result.is_err() {
+            let mut unsynced = self.unsynced.lock().unwrap();
+            unsynced.segments.extend(segments);
+            unsynced.directory |= directory;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pager::allocator::{AllocatorConfig, PageAllocator};
+
+    // A fresh directory under the temp dir, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("inkdb-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn config() -> SegmentConfig {
+        SegmentConfig {
+            segment_pages: 4,
+            max_open: 2,
+        }
+    }
+
+    fn page_of(byte: u8) -> RawPage {
+        let mut page = [0u8; PAGE_SIZE];
+        page[100] = byte;
+        page
+    }
+
+    #[test]
+    fn pages_map_onto_lazily_created_segments() {
+        let dir = TempDir::new("segments");
+        let base = dir.0.join("ink");
+        let pager = SegmentedPager::open(&base, config()).unwrap();
+        assert_eq!(pager.page_count(), 0);
+        assert!(!pager.segment_path(0).exists());
+
+        for id in 0..6 {
+            assert_eq!(pager.allocate_page().unwrap(), PageID(id));
+            pager.write_page(PageID(id), &page_of(id as u8)).unwrap();
+        }
+        assert_eq!(pager.segment_count(), 2);
+        let len = |segment| {
+            std::fs::metadata(pager.segment_path(segment))
+                .unwrap()
+                .len()
+        };
+        assert_eq!(len(0), 4 * PAGE_SIZE as u64);
+        assert_eq!(len(1), 2 * PAGE_SIZE as u64);
+
+        // A write further out creates only the segment it lands in, and the cache never holds more than two
+        pager.write_page(PageID(17), &page_of(17)).unwrap();
+        assert!(!pager.segment_path(2).exists());
+        assert!(pager.segment_path(4).exists());
+        assert!(pager.open_files() <= config().max_open);
+        let mut buf = [0u8; PAGE_SIZE];
+        assert!(matches!(
+            pager.read_page(PageID(9), &mut buf),
+            Err(PagerError::ShortRead { read: 0, .. })
+        ));
+        pager.sync().unwrap();
+        drop(pager);
+
+        let pager = SegmentedPager::open(&base, config()).unwrap();
+        assert_eq!(pager.page_count(), 18);
+        for id in [0, 3, 4, 5, 17] {
+            pager.read_page(PageID(id), &mut buf).unwrap();
+            assert_eq!(buf[100], id as u8);
+        }
+        assert!(pager.open_files() <= config().max_open);
+    }
+
+    #[test]
+    fn truncate_drops_trailing_segments() {
+        let dir = TempDir::new("segments-truncate");
+        let base = dir.0.join("ink");
+        let pager = Arc::new(SegmentedPager::open_with(&base, config(), IoMode::Direct).unwrap());
+
+        // The allocator grows the store an extent at a time across segment boundaries
+        let allocator =
+            PageAllocator::open(pager.clone(), AllocatorConfig { extent_pages: 3 }).unwrap();
+        for _ in 0..10 {
+            allocator.allocate().unwrap();
+        }
+        assert_eq!(pager.page_count(), 14);
+        assert_eq!(pager.segment_count(), 4);
+        drop(allocator);
+
+        pager.truncate(6).unwrap();
+        assert_eq!(pager.page_count(), 6);
+        assert!(!pager.segment_path(2).exists());
+        assert!(!pager.segment_path(3).exists());
+        assert_eq!(
+            std::fs::metadata(pager.segment_path(1)).unwrap().len(),
+            2 * PAGE_SIZE as u64
+        );
+        let mut buf = [0u8; PAGE_SIZE];
+        pager.read_page(PageID(5), &mut buf).unwrap();
+        assert!(pager.read_page(PageID(6), &mut buf).is_err());
+        assert_eq!(pager.allocate_page().unwrap(), PageID(6));
+        pager.sync().unwrap();
+
+        // Cutting on a segment boundary leaves the last kept segment whole
+        pager.truncate(4).unwrap();
+        assert!(!pager.segment_path(1).exists());
+        assert_eq!(
+            std::fs::metadata(pager.segment_path(0)).unwrap().len(),
+            4 * PAGE_SIZE as u64
+        );
+        pager.sync().unwrap();
+        assert_eq!(
+            SegmentedPager::open(&base, config()).unwrap().page_count(),
+            4
+        );
+    }
+}
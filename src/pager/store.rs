@@ -0,0 +1,107 @@
+use crate::pager::file_pager::{FilePager, IoMode};
+use crate::pager::pager::{Pager, Result};
+use crate::pager::segment_pager::{SegmentConfig, SegmentedPager};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+//NOTE: How one database keeps its pages - where, in a single file or in segments, and whether they go through the
+// OS page cache. This is the setting a database is opened with; everything above the pager only ever sees the
+// Arc<dyn Pager> it builds and can ask it (io_mode) whether direct I/O actually took.
+
+#[derive(Debug, Clone)]
+pub(crate) struct StoreConfig {
+    pub(crate) path: PathBuf,
+    // Direct skips the OS page cache, see file_pager.rs
+    pub(crate) io_mode: IoMode,
+    // One file at `path` when None, otherwise segments `path.0`, `path.1`, ...
+    pub(crate) segments: Option<SegmentConfig>,
+}
+
+impl StoreConfig {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            io_mode: IoMode::default(),
+            segments: None,
+        }
+    }
+
+    pub(crate) fn open(&self) -> Result<Arc<dyn Pager>> {
+        Ok(match self.segments {
+            None => Arc::new(FilePager::open_with(&self.path, self.io_mode)?),
+            Some(config) => Arc::new(SegmentedPager::open_with(&self.path, config, self.io_mode)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::buffer_manager::BufferManager;
+    use crate::page::PageID;
+    use crate::pager::file_pager::REFUSE_DIRECT;
+
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn the_io_mode_is_chosen_per_database() {
+        let dir = TempDir(std::env::temp_dir().join(format!("inkdb-store-{}", std::process::id())));
+        let _ = std::fs::remove_dir_all(&dir.0);
+        std::fs::create_dir_all(&dir.0).unwrap();
+
+        let buffered = StoreConfig::new(dir.0.join("buffered.db"));
+        let segmented = StoreConfig {
+            io_mode: IoMode::Direct,
+            segments: Some(SegmentConfig {
+                segment_pages: 4,
+                max_open: 2,
+            }),
+            ..StoreConfig::new(dir.0.join("segmented"))
+        };
+        // Direct I/O asked for on a filesystem that refuses it comes back buffered
+        let refused = StoreConfig {
+            io_mode: IoMode::Direct,
+            ..StoreConfig::new(dir.0.join("refused.db"))
+        };
+
+        for (config, refuse) in [(&buffered, false), (&segmented, false), (&refused, true)] {
+            REFUSE_DIRECT.set(refuse);
+            let pager = config.open().unwrap();
+            {
+                let bm = BufferManager::new(4, pager.clone());
+                for id in 0..6 {
+                    pager.allocate_page().unwrap();
+                    bm.fetch_page(PageID(id))
+                        .unwrap()
+                        .write(|bytes| bytes[100] = id as u8 + 1);
+                }
+                bm.flush_all().unwrap();
+            }
+            REFUSE_DIRECT.set(false);
+            let expected = if refuse {
+                IoMode::Buffered
+            } else {
+                config.io_mode
+            };
+            // The temp dir may not take O_DIRECT either, in which case it falls back too
+            assert!(pager.io_mode() == expected || pager.io_mode() == IoMode::Buffered);
+            drop(pager);
+
+            let pager = config.open().unwrap();
+            assert_eq!(pager.page_count(), 6);
+            let mut buf = [0u8; crate::page::PAGE_SIZE];
+            for id in 0..6 {
+                pager.read_page(PageID(id), &mut buf).unwrap();
+                assert_eq!(buf[100], id as u8 + 1);
+            }
+        }
+        assert!(dir.0.join("segmented.1").exists());
+        assert!(!dir.0.join("segmented").exists());
+    }
+}
@@ -1,3 +1,5 @@
+use crate::buffer::buffer_manager::BufferManager;
+use crate::buffer::pin_tracker::PinScope;
 use std::sync::Arc;
 
 pub(crate) struct TxMemory {
@@ -5,12 +7,24 @@ pub(crate) struct TxMemory {
     // allocator
     pub id: u64,
     // snapshot?
+    // Pins the transaction still holds when it ends are reported as leaks
+    pins: Option<PinScope>,
 }
 
 // NOTE: On page creation we embed the max transaction id into the page
 
 impl TxMemory {
     pub fn new_fake_tx(id: u64, cache: Arc<()>) -> Self {
-        Self { cache, id }
+        Self {
+            cache,
+            id,
+            pins: None,
+        }
+    }
+
+    // Must be called on the thread the transaction runs on, see BufferManager::pin_scope
+    pub(crate) fn track_pins(mut self, bm: &BufferManager) -> Self {
+        self.pins = Some(bm.pin_scope(&format!("transaction {}", self.id)));
+        self
     }
 }